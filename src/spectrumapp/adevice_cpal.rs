@@ -1,8 +1,10 @@
-use crate::spectrumapp::appstate::{set_app_state, AppState};
+use crate::spectrumapp::appstate::{set_app_state, set_app_state_detail, AppState};
 
 use super::MyParams;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
-use std::sync::{Mutex, Weak};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 // extern crate anyhow;
 // extern crate cpal;
@@ -10,88 +12,440 @@ use std::sync::{Mutex, Weak};
 
 use super::appthread::PCMSender;
 use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::Sample;
 use cpal::Stream;
+use cpal::{BufferSize, SampleFormat, SampleRate, StreamConfig};
+use cpal::{Device, Host, SupportedBufferSize, SupportedStreamConfigRange};
+
+/// Sample formats cpal can capture
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum InputFormat {
+    I16,
+    U16,
+    F32,
+}
 
-fn start_cpal_stream(
-    tx: Arc<dyn PCMSender>,
-    audio_device_hint: &Option<String>,
-) -> Result<cpal::Stream, anyhow::Error> {
-    // Use the default host for working with audio devices.
+impl InputFormat {
+    fn sample_format(&self) -> SampleFormat {
+        match self {
+            InputFormat::I16 => SampleFormat::I16,
+            InputFormat::U16 => SampleFormat::U16,
+            InputFormat::F32 => SampleFormat::F32,
+        }
+    }
+}
 
-    println!("start_cpal_stream");
-    let host = cpal::default_host();
-
-    // Setup the default input device and stream with the default input config.
-
-    let mut descbuf: Vec<u8> = Vec::new();
-    use std::io::Write;
-
-    let device = match audio_device_hint {
-        Some(hint) => {
-            let hint = hint.to_lowercase();
-            let devices = host.devices()?;
-            writeln!(&mut descbuf, "Devices: ").unwrap();
-
-            let mut found_device = None;
-            for (device_index, device) in devices.enumerate() {
-                if let Ok(conf) = device.default_input_config() {
-                    match device.name() {
-                        Ok(name) => {
-                            writeln!(&mut descbuf, " {}", name).unwrap();
-                            writeln!(&mut descbuf, "   {:?}", conf).unwrap();
-                            if name.to_lowercase().contains(hint.as_str()) {
-                                found_device = Some(device);
-                                //break;
-                            }
-                        }
-                        Err(err) => {
-                            writeln!(&mut descbuf, " err: {}", err).unwrap();
-                        }
-                    }
-                } else {
-                    writeln!(
-                        &mut descbuf,
-                        "device with device_index:{} name:{} has no .default_input_config()",
-                        device.name().unwrap_or("[noname]".to_string()),
-                        device_index
-                    )
-                    .unwrap();
-                }
-            }
-            found_device
+/// Which input device to open and how, defaults of the host where None
+#[derive(Clone, Debug)]
+pub struct AudioDeviceConfig {
+    /// host (audio API) name, see --list-devices
+    pub host: Option<String>,
+    /// index from --list-devices, exact name or a substring of the name
+    pub device: Option<String>,
+    pub sample_rate: Option<u32>,
+    /// frames per callback
+    pub buffer_size: Option<u32>,
+    pub channels: Option<u16>,
+    pub format: Option<InputFormat>,
+}
+
+impl AudioDeviceConfig {
+    pub fn new() -> Self {
+        Self {
+            host: None,
+            device: None,
+            sample_rate: None,
+            buffer_size: None,
+            channels: None,
+            format: None,
         }
-        None => {
-            let device = host
-                .default_input_device()
-                .expect("Failed to get default input device");
-            println!("Default input device: {}", device.name()?);
-            Some(device)
+    }
+}
+
+fn select_host(name: &Option<String>) -> Result<Host, anyhow::Error> {
+    let Some(name) = name else {
+        return Ok(cpal::default_host());
+    };
+    let available = cpal::available_hosts();
+    let id = available
+        .iter()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+        .ok_or_else(|| {
+            let names: Vec<&str> = available.iter().map(|id| id.name()).collect();
+            anyhow::anyhow!("no audio host '{}', available: {}", name, names.join(", "))
+        })?;
+    Ok(cpal::host_from_id(*id)?)
+}
+
+/// Devices of `host` that can capture, numbered as in --list-devices
+fn input_devices(host: &Host) -> Result<Vec<(String, Device)>, anyhow::Error> {
+    Ok(host
+        .devices()?
+        .filter(|d| d.default_input_config().is_ok())
+        .map(|d| (d.name().unwrap_or_else(|_| "[noname]".to_string()), d))
+        .collect())
+}
+
+fn select_device(host: &Host, hint: &Option<String>) -> Result<Device, anyhow::Error> {
+    let Some(hint) = hint else {
+        let device = host
+            .default_input_device()
+            .ok_or_else(|| anyhow::anyhow!("no default input device"))?;
+        println!("Default input device: {}", device.name()?);
+        return Ok(device);
+    };
+    let devices = input_devices(host)?;
+    if let Ok(index) = hint.parse::<usize>() {
+        return devices
+            .into_iter()
+            .nth(index)
+            .map(|(_, d)| d)
+            .ok_or_else(|| {
+                anyhow::anyhow!("no input device with index {}, see --list-devices", index)
+            });
+    }
+    let lower = hint.to_lowercase();
+    let matching: Vec<usize> = match devices.iter().position(|(name, _)| name == hint) {
+        Some(exact) => vec![exact],
+        None => (0..devices.len())
+            .filter(|i| devices[*i].0.to_lowercase().contains(&lower))
+            .collect(),
+    };
+    if matching.len() > 1 {
+        println!("several devices match '{}', using the first:", hint);
+        for i in &matching {
+            println!("  [{}] {}", i, devices[*i].0);
         }
+    }
+    let first = *matching
+        .first()
+        .ok_or_else(|| anyhow::anyhow!("no input device matches '{}', see --list-devices", hint))?;
+    Ok(devices.into_iter().nth(first).unwrap().1)
+}
+
+fn describe_range(c: &SupportedStreamConfigRange) -> String {
+    let buffer = match c.buffer_size() {
+        SupportedBufferSize::Range { min, max } => format!("buffer {}-{}", min, max),
+        SupportedBufferSize::Unknown => "buffer ?".to_string(),
     };
+    format!(
+        "{} ch, {}-{} Hz, {:?}, {}",
+        c.channels(),
+        c.min_sample_rate().0,
+        c.max_sample_rate().0,
+        c.sample_format(),
+        buffer
+    )
+}
 
-    std::io::stdout().write_all(&descbuf[..])?;
+/// Input devices of the host, in --list-devices order
+pub fn input_device_names(host: &Option<String>) -> Vec<String> {
+    let host = match select_host(host) {
+        Ok(host) => host,
+        Err(_) => return Vec::new(),
+    };
+    match input_devices(&host) {
+        Ok(devices) => devices.into_iter().map(|(name, _)| name).collect(),
+        Err(_) => Vec::new(),
+    }
+}
 
-    let device = device.expect("device by name not found");
+/// Hosts, input devices with their indices and supported configs
+pub fn list_devices() -> String {
+    let mut out = String::new();
+    let default_host = cpal::default_host().id();
+    for id in cpal::available_hosts() {
+        let mark = if id == default_host { " (default)" } else { "" };
+        out += &format!("host {}{}\n", id.name(), mark);
+        let host = match cpal::host_from_id(id) {
+            Ok(host) => host,
+            Err(err) => {
+                out += &format!("  unavailable: {}\n", err);
+                continue;
+            }
+        };
+        let default_name = host.default_input_device().and_then(|d| d.name().ok());
+        let devices = match input_devices(&host) {
+            Ok(devices) => devices,
+            Err(err) => {
+                out += &format!("  {}\n", err);
+                continue;
+            }
+        };
+        for (index, (name, device)) in devices.into_iter().enumerate() {
+            let configs: Vec<SupportedStreamConfigRange> = match device.supported_input_configs() {
+                Ok(configs) => configs.collect(),
+                Err(_) => Vec::new(),
+            };
+            let mark = if Some(&name) == default_name.as_ref() {
+                " (default input)"
+            } else {
+                ""
+            };
+            out += &format!("  [{}] {}{}\n", index, name, mark);
+            if let Ok(def) = device.default_input_config() {
+                out += &format!(
+                    "      default: {} ch, {} Hz, {:?}\n",
+                    def.channels(),
+                    def.sample_rate().0,
+                    def.sample_format()
+                );
+            }
+            for c in &configs {
+                out += &format!("      {}\n", describe_range(c));
+            }
+        }
+    }
+    out
+}
 
-    let def_config = device
-        .default_input_config()
-        .expect("Failed to get default input config");
+/// Rate `audio` opens at, the device default unless one is asked for
+pub fn input_rate(audio: &AudioDeviceConfig) -> Option<u32> {
+    if audio.sample_rate.is_some() {
+        return audio.sample_rate;
+    }
+    let host = select_host(&audio.host).ok()?;
+    let device = select_device(&host, &audio.device).ok()?;
+    Some(device.default_input_config().ok()?.sample_rate().0)
+}
+
+/// The stream config for `audio`, the device default when nothing is asked for
+fn select_config(
+    device: &Device,
+    audio: &AudioDeviceConfig,
+) -> Result<(StreamConfig, SampleFormat), anyhow::Error> {
+    let def_config = device.default_input_config()?;
     println!("Default input config: {:?}", def_config);
-    // let config = cpal::StreamConfig{
-    //     channels: 2,
-    //     sample_rate: cpal::SampleRate(48000),
-    //     buffer_size: cpal::BufferSize::Fixed(256),
-    // };
-    //let config = def_config;
-    let config = def_config;
-    //config.buffer_size = 1024;
+    if audio.sample_rate.is_none() && audio.channels.is_none() && audio.format.is_none() {
+        let mut config: StreamConfig = def_config.config();
+        if let Some(frames) = audio.buffer_size {
+            config.buffer_size = BufferSize::Fixed(frames);
+        }
+        return Ok((config, def_config.sample_format()));
+    }
+
+    let channels = audio.channels.unwrap_or(def_config.channels());
+    let format = audio
+        .format
+        .map(|f| f.sample_format())
+        .unwrap_or(def_config.sample_format());
+    let rate = audio.sample_rate.unwrap_or(def_config.sample_rate().0);
+    let range = device
+        .supported_input_configs()?
+        .find(|c| {
+            c.channels() == channels
+                && c.sample_format() == format
+                && c.min_sample_rate().0 <= rate
+                && rate <= c.max_sample_rate().0
+        })
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "the device can't capture {} ch, {} Hz, {:?}, see --list-devices",
+                channels,
+                rate,
+                format
+            )
+        })?;
+    if let (Some(frames), SupportedBufferSize::Range { min, max }) =
+        (audio.buffer_size, range.buffer_size())
+    {
+        if frames < *min || frames > *max {
+            anyhow::bail!("buffer size {} is outside {}-{}", frames, min, max);
+        }
+    }
+    let mut config: StreamConfig = range.with_sample_rate(SampleRate(rate)).config();
+    if let Some(frames) = audio.buffer_size {
+        config.buffer_size = BufferSize::Fixed(frames);
+    }
+    Ok((config, format))
+}
+
+/// Written by the stream and its callbacks, watched by the supervisor and the UI
+pub struct StreamHealth {
+    /// data callbacks so far
+    pub callbacks: AtomicU64,
+    /// last error reported by the stream
+    pub error: Mutex<Option<String>>,
+    /// name of the device last opened
+    pub device: Mutex<String>,
+    /// rate of the stream last opened, 0 before
+    pub sample_rate: AtomicU32,
+    /// channels of that stream
+    pub channels: AtomicU32,
+}
+
+impl StreamHealth {
+    pub fn new() -> Self {
+        Self {
+            callbacks: AtomicU64::new(0),
+            error: Mutex::new(None),
+            device: Mutex::new(String::new()),
+            sample_rate: AtomicU32::new(0),
+            channels: AtomicU32::new(0),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self.sample_rate.load(Ordering::Relaxed) {
+            0 => "not open".to_string(),
+            rate => format!("{}, {} Hz", self.device.lock().unwrap(), rate),
+        }
+    }
+}
+
+/// seconds without a data callback before the stream counts as stalled
+const STALL_TIMEOUT_S: f64 = 3.0;
+/// reopen backoff, doubled per failure
+const RETRY_MIN_S: f64 = 1.0;
+const RETRY_MAX_S: f64 = 30.0;
+/// failed reopens of a chosen device before the default device is tried in between
+const FALLBACK_AFTER: u32 = 3;
+const SUPERVISE_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, PartialEq)]
+enum SupervisorAction {
+    None,
+    Open { default_device: bool },
+    Close(String),
+}
+
+/// Decides when to drop and reopen the input stream, times in seconds
+struct Supervisor {
+    open: bool,
+    failures: u32,
+    retry_at: f64,
+    last_callbacks: u64,
+    last_progress: f64,
+}
+
+impl Supervisor {
+    fn new() -> Self {
+        Self {
+            open: false,
+            failures: 0,
+            retry_at: 0.0,
+            last_callbacks: 0,
+            last_progress: 0.0,
+        }
+    }
+
+    fn tick(&mut self, now: f64, callbacks: u64, error: Option<String>) -> SupervisorAction {
+        if self.open {
+            if let Some(err) = error {
+                self.open = false;
+                return SupervisorAction::Close(err);
+            }
+            if callbacks != self.last_callbacks {
+                // audio flows again, start the backoff over
+                self.last_callbacks = callbacks;
+                self.last_progress = now;
+                self.failures = 0;
+            } else if now - self.last_progress > STALL_TIMEOUT_S {
+                self.open = false;
+                return SupervisorAction::Close(format!("no audio for {:.0} s", STALL_TIMEOUT_S));
+            }
+            SupervisorAction::None
+        } else if now >= self.retry_at {
+            SupervisorAction::Open {
+                default_device: self.failures >= FALLBACK_AFTER && self.failures % 2 == 1,
+            }
+        } else {
+            SupervisorAction::None
+        }
+    }
+
+    fn opened(&mut self, now: f64, callbacks: u64) {
+        self.open = true;
+        self.last_callbacks = callbacks;
+        self.last_progress = now;
+    }
+
+    /// Schedules the next attempt, returns the delay
+    fn failed(&mut self, now: f64) -> f64 {
+        self.open = false;
+        self.failures += 1;
+        let delay = (RETRY_MIN_S * 2f64.powi(self.failures as i32 - 1)).min(RETRY_MAX_S);
+        self.retry_at = now + delay;
+        delay
+    }
+}
+
+/// Owns the stream (cpal streams can't change threads), reopens it on errors,
+/// stalls and unplugging until `stop` is set
+fn supervise(
+    tx: Arc<dyn PCMSender>,
+    audio: AudioDeviceConfig,
+    health: Arc<StreamHealth>,
+    stop: Arc<AtomicBool>,
+) {
+    let start = Instant::now();
+    let mut supervisor = Supervisor::new();
+    let mut stream: Option<Stream> = None;
+    let retry = |supervisor: &mut Supervisor, now: f64, reason: String| {
+        let delay = supervisor.failed(now);
+        println!("audio input: {}, retrying in {:.0} s", reason, delay);
+        set_app_state(AppState::AudioError);
+        set_app_state_detail(format!("retrying in {:.0} s: {}", delay, reason));
+    };
+    while !stop.load(Ordering::Relaxed) {
+        let now = start.elapsed().as_secs_f64();
+        let error = health.error.lock().unwrap().take();
+        let callbacks = health.callbacks.load(Ordering::Relaxed);
+        match supervisor.tick(now, callbacks, error) {
+            SupervisorAction::None => {}
+            SupervisorAction::Open { default_device } => {
+                let mut audio = audio.clone();
+                if default_device && audio.device.is_some() {
+                    println!("audio input: trying the default device");
+                    audio.device = None;
+                }
+                match start_cpal_stream(tx.clone(), &audio, health.clone()) {
+                    Ok(s) => {
+                        stream = Some(s);
+                        supervisor.opened(now, callbacks);
+                        set_app_state_detail(String::new());
+                    }
+                    Err(err) => retry(&mut supervisor, now, format!("{}", err)),
+                }
+            }
+            SupervisorAction::Close(reason) => {
+                stream = None;
+                retry(&mut supervisor, now, reason);
+            }
+        }
+        std::thread::sleep(SUPERVISE_INTERVAL);
+    }
+    drop(stream);
+}
+
+fn start_cpal_stream(
+    tx: Arc<dyn PCMSender>,
+    audio: &AudioDeviceConfig,
+    health: Arc<StreamHealth>,
+) -> Result<cpal::Stream, anyhow::Error> {
+    println!("start_cpal_stream");
+    let host = select_host(&audio.host)?;
+    let device = select_device(&host, &audio.device)?;
+    println!("Input device: {} ({})", device.name()?, host.id().name());
+
+    let (config, format) = select_config(&device, audio)?;
+    println!("Input config: {:?} {:?}", config, format);
+    if (config.channels as usize) < tx.num_channels() {
+        println!(
+            "warning: device has {} channels, but {} are analyzed",
+            config.channels,
+            tx.num_channels()
+        );
+    }
 
     // A flag to indicate that recording is in progress.
     println!("Begin recording...");
 
     // Run the input stream on a separate thread.
+    let err_health = health.clone();
     let err_fn = move |err| {
         eprintln!("an error occurred on stream: {}", err);
+        *err_health.error.lock().unwrap() = Some(format!("{}", err));
     };
 
     // let mut sine_t: f32 = 0.0;
@@ -109,42 +463,71 @@ fn start_cpal_stream(
     //     sine_t.sin() / 2.0
     // };
 
-    let stream = match config.sample_format() {
-        cpal::SampleFormat::F32 => device.build_input_stream(
-            &config.into(),
-            move |data, _: &_| write_input_data_f32(data, &tx),
+    let cb_health = health.clone();
+    // interleaved as the device delivers them
+    let channels = config.channels as i32;
+    let stream = match format {
+        SampleFormat::F32 => device.build_input_stream(
+            &config,
+            move |data, _: &_| {
+                cb_health.callbacks.fetch_add(1, Ordering::Relaxed);
+                write_input_data_f32(data, channels, &tx)
+            },
             err_fn,
         )?,
-        cpal::SampleFormat::I16 => device.build_input_stream(
-            &config.into(),
-            move |data, _: &_| write_input_data_i16(data, &tx),
-            err_fn,
-        )?,
-        cpal::SampleFormat::U16 => device.build_input_stream(
-            &config.into(),
-            move |data, _: &_| write_input_data_i16(data, &tx),
+        SampleFormat::I16 => device.build_input_stream(
+            &config,
+            move |data, _: &_| {
+                cb_health.callbacks.fetch_add(1, Ordering::Relaxed);
+                write_input_data_i16(data, channels, &tx)
+            },
             err_fn,
         )?,
+        SampleFormat::U16 => {
+            let mut buf = Vec::new();
+            device.build_input_stream(
+                &config,
+                move |data, _: &_| {
+                    cb_health.callbacks.fetch_add(1, Ordering::Relaxed);
+                    write_input_data_u16(data, channels, &mut buf, &tx)
+                },
+                err_fn,
+            )?
+        }
     };
 
+    *health.device.lock().unwrap() = device.name()?;
+    health
+        .sample_rate
+        .store(config.sample_rate.0, Ordering::Relaxed);
+    health
+        .channels
+        .store(config.channels as u32, Ordering::Relaxed);
     set_app_state(AppState::Playing);
     stream.play()?;
 
     Ok(stream)
 }
 
-fn write_input_data_i16(input: &[i16], tx: &Arc<dyn PCMSender>) {
-    let _result = tx.send_pcm16(-1, input);
+fn write_input_data_i16(input: &[i16], channels: i32, tx: &Arc<dyn PCMSender>) {
+    let _result = tx.send_pcm16(channels, input);
+}
+fn write_input_data_u16(input: &[u16], channels: i32, buf: &mut Vec<f32>, tx: &Arc<dyn PCMSender>) {
+    buf.clear();
+    buf.extend(input.iter().map(|s| s.to_f32()));
+    let _result = tx.send_pcm(channels, buf);
 }
-fn write_input_data_f32(input: &[f32], tx: &Arc<dyn PCMSender>) {
-    let _result = tx.send_pcm(-1, input);
+fn write_input_data_f32(input: &[f32], channels: i32, tx: &Arc<dyn PCMSender>) {
+    let _result = tx.send_pcm(channels, input);
 }
 
 pub struct SlidingCpal {
-    me: Weak<Self>,
     sender: Arc<dyn PCMSender>,
-    pub audio_device: Option<String>,
-    pub stream: Mutex<Option<Stream>>,
+    pub audio: AudioDeviceConfig,
+    pub health: Arc<StreamHealth>,
+    /// ends the supervisor thread, which closes the stream
+    stop: Arc<AtomicBool>,
+    thread: Mutex<Option<std::thread::JoinHandle<()>>>,
 }
 
 pub fn resize_vec_len_fast<V>(v: &mut Vec<V>, new_len: usize)
@@ -161,47 +544,47 @@ where
 }
 impl SlidingCpal {
     pub fn new(sender: Arc<dyn PCMSender>, params: &MyParams) -> Arc<Self> {
-        // SAFETY: ? initialized below
-        let sc = Arc::new_cyclic(|me| Self {
-            me: me.clone(),
-            //self_weak: unsafe { mem::transmute(Rc::downgrade(&rc)) },
-            sender,
-            audio_device: params.audio_device.clone(),
-            stream: Mutex::new(None),
-        });
+        Self::with_audio(sender, params.audio.clone())
+    }
 
-        //*rc.borrow_mut() = MaybeUninit::new();
+    fn with_audio(sender: Arc<dyn PCMSender>, audio: AudioDeviceConfig) -> Arc<Self> {
+        Arc::new(Self {
+            sender,
+            audio,
+            health: Arc::new(StreamHealth::new()),
+            stop: Arc::new(AtomicBool::new(false)),
+            thread: Mutex::new(None),
+        })
+    }
 
-        sc
+    /// Closes this input and starts `audio` feeding the same analyzer
+    pub fn switch_to(&self, audio: AudioDeviceConfig) -> Arc<Self> {
+        self.stop.store(true, Ordering::Relaxed);
+        let previous = self.thread.lock().unwrap().take();
+        let input = Self::with_audio(self.sender.clone(), audio);
+        input.spawn(previous);
+        input
     }
 
+    /// Opens the input on a supervisor thread that keeps it open
     pub fn start(&self) {
-        //let (tx, rx) = std::sync::mpsc::sync_channel::<Vec<f32>>(1024);
-        //let mut channels: Option<Box<dyn PCMSender>> = None;
-        //std::mem::swap(&mut channels, &mut self.channels);
-        let audio_device = self.audio_device.clone();
+        self.spawn(None);
+    }
 
+    /// The supervisor waits for `previous` to close its stream first,
+    /// the device may not open twice
+    fn spawn(&self, previous: Option<std::thread::JoinHandle<()>>) {
         let sender = self.sender.clone();
-
-        let me = self.me.upgrade().unwrap();
-        // std::thread::spawn(move || {
-
-        //     //drop(stream);
-        // });
-
-        let stream = start_cpal_stream(sender, &audio_device).unwrap();
-        //Self::receiver_task(rx, channels.unwrap());
-        {
-            let mut guard = me.stream.lock().unwrap();
-            *guard = Some(stream);
-        }
-        // todo
-        // #[cfg(target_arch = "wasm32")]
-        // {
-        //     let stream = start_writer(tx, &audio_device).unwrap();
-        //     Self::receiver_task(rx, channels.unwrap());
-        //     drop(stream);
-        // }
+        let audio = self.audio.clone();
+        let health = self.health.clone();
+        let stop = self.stop.clone();
+        let thread = std::thread::spawn(move || {
+            if let Some(previous) = previous {
+                let _ = previous.join();
+            }
+            supervise(sender, audio, health, stop)
+        });
+        *self.thread.lock().unwrap() = Some(thread);
     }
 
     // pub fn receiver_task(rx: Receiver<Vec<f32>>, out_channels: Box<dyn PCMReceiver>) {
@@ -266,3 +649,63 @@ impl SlidingCpal {
     //     });
     // }
 }
+
+impl Drop for SlidingCpal {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn test_supervisor() {
+    let mut sv = Supervisor::new();
+    assert_eq!(
+        sv.tick(0.0, 0, None),
+        SupervisorAction::Open {
+            default_device: false
+        }
+    );
+    sv.opened(0.0, 0);
+    assert_eq!(sv.tick(1.0, 5, None), SupervisorAction::None);
+    assert_eq!(sv.tick(3.9, 5, None), SupervisorAction::None);
+    // stalled
+    assert!(matches!(sv.tick(4.1, 5, None), SupervisorAction::Close(_)));
+    assert_eq!(sv.failed(4.1), 1.0);
+    assert_eq!(sv.tick(4.5, 5, None), SupervisorAction::None);
+    assert_eq!(
+        sv.tick(5.2, 5, None),
+        SupervisorAction::Open {
+            default_device: false
+        }
+    );
+
+    // failed reopens back off and fall back to the default device in between
+    let mut delays = Vec::new();
+    let mut defaults = Vec::new();
+    let mut now = 5.2;
+    for _ in 0..8 {
+        delays.push(sv.failed(now));
+        now = sv.retry_at;
+        match sv.tick(now, 5, None) {
+            SupervisorAction::Open { default_device } => defaults.push(default_device),
+            action => panic!("{:?}", action),
+        }
+    }
+    assert_eq!(delays, vec![2.0, 4.0, 8.0, 16.0, 30.0, 30.0, 30.0, 30.0]);
+    assert_eq!(
+        defaults,
+        vec![false, true, false, true, false, true, false, true]
+    );
+
+    // an error closes, audio flowing again resets the backoff
+    sv.opened(now, 5);
+    let err = Some("device unplugged".to_string());
+    assert_eq!(
+        sv.tick(now, 5, err),
+        SupervisorAction::Close("device unplugged".to_string())
+    );
+    assert_eq!(sv.failed(now), 30.0);
+    sv.opened(now + 30.0, 5);
+    assert_eq!(sv.tick(now + 30.1, 6, None), SupervisorAction::None);
+    assert_eq!(sv.failed(now + 31.0), 1.0);
+}
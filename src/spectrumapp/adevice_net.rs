@@ -0,0 +1,490 @@
+use std::collections::BTreeMap;
+use std::net::{SocketAddr, TcpListener, UdpSocket};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use super::adevice_pipe::forward_pcm;
+use super::adevice_pipe::PcmSpec;
+use super::appstate::{set_app_state, AppState};
+use super::appthread::PCMSender;
+
+// Audio from another machine, the format comes from the command line:
+//   tcp  raw interleaved PCM, one connection at a time
+//          arecord -f S16_LE -c 2 -r 48000 | nc host 5004
+//   udp  datagrams of whole frames, played in arrival order
+//   rtp  RTP (RFC 3550) with L16 payload (s16be), reordered by timestamp in a
+//        jitter buffer, lost packets are played as silence
+//          ffmpeg -re -i in.wav -ac 2 -ar 48000 -c:a pcm_s16be -f rtp rtp://host:5004
+
+/// a jump further than this restarts the jitter buffer, seconds
+const MAX_GAP_S: f64 = 1.0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum NetProtocol {
+    Tcp,
+    Udp,
+    Rtp,
+}
+
+#[derive(Clone, Debug)]
+pub struct NetConfig {
+    pub protocol: NetProtocol,
+    /// address:port to listen on
+    pub bind: String,
+    pub pcm: PcmSpec,
+    /// RTP audio held back to reorder late packets
+    pub jitter_ms: f32,
+}
+
+impl NetConfig {
+    pub fn describe(&self) -> String {
+        format!("{:?} {} {}", self.protocol, self.bind, self.pcm.describe())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NetStats {
+    pub packets: u64,
+    /// frames played as silence
+    pub lost_frames: u64,
+    /// packets that arrived after their audio was played
+    pub late_packets: u64,
+    pub duplicate_packets: u64,
+    /// source changes and jumps
+    pub resets: u64,
+}
+
+impl NetStats {
+    pub fn describe(&self) -> String {
+        format!(
+            "{} packets, {} frames lost, {} late, {} duplicate, {} resets",
+            self.packets, self.lost_frames, self.late_packets, self.duplicate_packets, self.resets
+        )
+    }
+}
+
+/// Orders packets by frame position and plays them `depth` frames late
+pub struct JitterBuffer {
+    channels: usize,
+    depth: u64,
+    max_gap: u64,
+    /// position of the next frame to play, None until the first packet
+    next: Option<u64>,
+    packets: BTreeMap<u64, Vec<f32>>,
+    /// furthest packet end queued since the reset, packets can overlap
+    max_end: u64,
+    pub stats: NetStats,
+}
+
+impl JitterBuffer {
+    pub fn new(channels: usize, depth: u64, max_gap: u64) -> Self {
+        Self {
+            channels,
+            depth,
+            max_gap,
+            next: None,
+            packets: BTreeMap::new(),
+            max_end: 0,
+            stats: NetStats::default(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.next = None;
+        self.packets.clear();
+        self.max_end = 0;
+    }
+
+    fn frames(&self, samples: &[f32]) -> u64 {
+        (samples.len() / self.channels) as u64
+    }
+
+    /// Queues interleaved `samples` starting at frame `pos`
+    pub fn push(&mut self, pos: u64, samples: Vec<f32>) {
+        self.stats.packets += 1;
+        let next = *self.next.get_or_insert(pos);
+        if pos.abs_diff(next) > self.max_gap {
+            self.stats.resets += 1;
+            self.reset();
+            self.next = Some(pos);
+        } else if pos < next {
+            self.stats.late_packets += 1;
+            return;
+        }
+        self.max_end = self.max_end.max(pos + self.frames(&samples));
+        if self.packets.insert(pos, samples).is_some() {
+            self.stats.duplicate_packets += 1;
+        }
+    }
+
+    fn end(&self) -> u64 {
+        if self.packets.is_empty() {
+            self.next.unwrap_or(0)
+        } else {
+            self.max_end
+        }
+    }
+
+    /// Appends what is due to `out`, everything if `flush`
+    pub fn pop(&mut self, out: &mut Vec<f32>, flush: bool) {
+        while let Some(next) = self.next {
+            if !flush && self.end().saturating_sub(next) <= self.depth {
+                break;
+            }
+            let Some((pos, samples)) = self.packets.pop_first() else {
+                break;
+            };
+            if pos > next {
+                self.stats.lost_frames += pos - next;
+                out.resize(out.len() + (pos - next) as usize * self.channels, 0.0);
+            }
+            // a packet overlapping the played audio plays its new part
+            let skip = (next.saturating_sub(pos) as usize * self.channels).min(samples.len());
+            out.extend_from_slice(&samples[skip..]);
+            self.next = Some(next.max(pos + self.frames(&samples)));
+        }
+    }
+}
+
+pub struct RtpPacket<'a> {
+    pub sequence: u16,
+    pub timestamp: u32,
+    pub ssrc: u32,
+    pub payload_type: u8,
+    pub payload: &'a [u8],
+}
+
+/// The RTP header fields and payload, None if it isn't RTP version 2
+pub fn parse_rtp(packet: &[u8]) -> Option<RtpPacket<'_>> {
+    if packet.len() < 12 || packet[0] >> 6 != 2 {
+        return None;
+    }
+    let word = |at: usize| Some(u32::from_be_bytes(packet.get(at..at + 4)?.try_into().ok()?));
+    let csrc_count = (packet[0] & 0x0f) as usize;
+    let mut start = 12 + 4 * csrc_count;
+    if packet[0] & 0x10 != 0 {
+        let extension_words = word(start)? & 0xffff;
+        start += 4 + 4 * extension_words as usize;
+    }
+    let mut end = packet.len();
+    if packet[0] & 0x20 != 0 {
+        end = end.checked_sub(*packet.last()? as usize)?;
+    }
+    Some(RtpPacket {
+        sequence: u16::from_be_bytes([packet[2], packet[3]]),
+        timestamp: word(4)?,
+        ssrc: word(8)?,
+        payload_type: packet[1] & 0x7f,
+        payload: packet.get(start..end)?,
+    })
+}
+
+fn serve_tcp(listener: TcpListener, pcm: PcmSpec, tx: Arc<dyn PCMSender>) {
+    loop {
+        let (mut stream, peer) = match listener.accept() {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                println!("net: {}", err);
+                continue;
+            }
+        };
+        println!("net: {} connected", peer);
+        match forward_pcm(&mut stream, &pcm, &*tx) {
+            Ok(frames) => println!(
+                "net: {} closed after {:.1} s",
+                peer,
+                frames as f64 / pcm.sample_rate as f64
+            ),
+            Err(err) => println!("net: {}: {}", peer, err),
+        }
+    }
+}
+
+fn serve_udp(socket: UdpSocket, pcm: PcmSpec, tx: Arc<dyn PCMSender>) {
+    let mut buf = vec![0u8; 65536];
+    let mut samples = Vec::new();
+    loop {
+        let len = match socket.recv(&mut buf) {
+            Ok(len) => len,
+            Err(err) => {
+                println!("net: {}", err);
+                continue;
+            }
+        };
+        let whole = len - len % pcm.frame_bytes();
+        samples.clear();
+        pcm.format.decode(&buf[..whole], &mut samples);
+        if !samples.is_empty() {
+            tx.send_pcm(pcm.channels as i32, &samples);
+        }
+    }
+}
+
+fn serve_rtp(
+    socket: UdpSocket,
+    config: NetConfig,
+    tx: Arc<dyn PCMSender>,
+    stats: Arc<Mutex<NetStats>>,
+) {
+    let pcm = &config.pcm;
+    let rate = pcm.sample_rate as f64;
+    let mut jitter = JitterBuffer::new(
+        pcm.channels,
+        (config.jitter_ms as f64 * rate / 1000.0) as u64,
+        (MAX_GAP_S * rate) as u64,
+    );
+    // a quiet source still gets its last packets played
+    let _ = socket.set_read_timeout(Some(Duration::from_secs_f32(
+        (config.jitter_ms / 1000.0).max(0.01),
+    )));
+    let mut buf = vec![0u8; 65536];
+    let mut out = Vec::new();
+    let mut source: Option<(u32, u64)> = None;
+    let losses = |s: &NetStats| (s.lost_frames, s.late_packets, s.resets);
+    let mut reported = losses(&NetStats::default());
+    loop {
+        let flush = match socket.recv(&mut buf) {
+            Ok(len) => {
+                if let Some(rtp) = parse_rtp(&buf[..len]) {
+                    // 64 bit positions from the wrapping 32 bit timestamps
+                    let pos = match source {
+                        Some((ssrc, last)) if ssrc == rtp.ssrc => {
+                            last.wrapping_add(rtp.timestamp.wrapping_sub(last as u32) as i32 as u64)
+                        }
+                        _ => {
+                            println!(
+                                "net: rtp source {:08x}, payload type {}",
+                                rtp.ssrc, rtp.payload_type
+                            );
+                            if source.is_some() {
+                                jitter.stats.resets += 1;
+                            }
+                            jitter.reset();
+                            (1 << 32) + rtp.timestamp as u64
+                        }
+                    };
+                    source = Some((rtp.ssrc, pos));
+                    let whole = rtp.payload.len() - rtp.payload.len() % pcm.frame_bytes();
+                    let mut samples = Vec::with_capacity(whole / pcm.format.bytes_per_sample());
+                    pcm.format.decode(&rtp.payload[..whole], &mut samples);
+                    jitter.push(pos, samples);
+                }
+                false
+            }
+            Err(err)
+                if err.kind() == std::io::ErrorKind::WouldBlock
+                    || err.kind() == std::io::ErrorKind::TimedOut =>
+            {
+                true
+            }
+            Err(err) => {
+                println!("net: {}", err);
+                continue;
+            }
+        };
+        out.clear();
+        jitter.pop(&mut out, flush);
+        if !out.is_empty() {
+            tx.send_pcm(pcm.channels as i32, &out);
+        }
+        // losses are reported when the source pauses
+        if flush && losses(&jitter.stats) != reported {
+            println!("net: {}", jitter.stats.describe());
+            reported = losses(&jitter.stats);
+        }
+        *stats.lock().unwrap() = jitter.stats.clone();
+    }
+}
+
+pub struct SlidingNet {
+    pub config: NetConfig,
+    pub local_addr: SocketAddr,
+    /// RTP only
+    pub stats: Arc<Mutex<NetStats>>,
+}
+
+impl SlidingNet {
+    pub fn start(sender: Arc<dyn PCMSender>, config: NetConfig) -> std::io::Result<Arc<Self>> {
+        if config.pcm.channels < sender.num_channels() {
+            println!(
+                "warning: input has {} channels, but {} are analyzed",
+                config.pcm.channels,
+                sender.num_channels()
+            );
+        }
+        let stats = Arc::new(Mutex::new(NetStats::default()));
+        let local_addr = match config.protocol {
+            NetProtocol::Tcp => {
+                let listener = TcpListener::bind(&config.bind)?;
+                let local_addr = listener.local_addr()?;
+                let pcm = config.pcm.clone();
+                std::thread::spawn(move || serve_tcp(listener, pcm, sender));
+                local_addr
+            }
+            NetProtocol::Udp => {
+                let socket = UdpSocket::bind(&config.bind)?;
+                let local_addr = socket.local_addr()?;
+                let pcm = config.pcm.clone();
+                std::thread::spawn(move || serve_udp(socket, pcm, sender));
+                local_addr
+            }
+            NetProtocol::Rtp => {
+                let socket = UdpSocket::bind(&config.bind)?;
+                let local_addr = socket.local_addr()?;
+                let config = config.clone();
+                let stats = stats.clone();
+                std::thread::spawn(move || serve_rtp(socket, config, sender, stats));
+                local_addr
+            }
+        };
+        set_app_state(AppState::Playing);
+        println!("net: listening {}", config.describe());
+        Ok(Arc::new(Self {
+            config,
+            local_addr,
+            stats,
+        }))
+    }
+}
+
+#[test]
+fn test_net_input() {
+    use std::io::Write;
+
+    // two CSRCs, a one word extension and 2 bytes of padding
+    let mut packet = vec![0xb2, 96, 0x12, 0x34, 0, 0, 1, 0, 0xde, 0xad, 0xbe, 0xef];
+    packet.extend_from_slice(&[0; 8]);
+    packet.extend_from_slice(&[0xbe, 0xde, 0, 1, 9, 9, 9, 9]);
+    packet.extend_from_slice(&[1, 2, 3, 4, 0, 2]);
+    let rtp = parse_rtp(&packet).unwrap();
+    assert_eq!(
+        (rtp.sequence, rtp.timestamp, rtp.ssrc, rtp.payload_type),
+        (0x1234, 256, 0xdeadbeef, 96)
+    );
+    assert_eq!(rtp.payload, &[1, 2, 3, 4]);
+    assert!(parse_rtp(&packet[..11]).is_none());
+
+    // reordered, duplicated, lost and late packets of 4 mono frames
+    let block = |pos: u64| -> Vec<f32> { (pos..pos + 4).map(|i| i as f32).collect() };
+    let mut jitter = JitterBuffer::new(1, 8, 1000);
+    let mut out = Vec::new();
+    for pos in [100, 108, 104, 104, 116] {
+        jitter.push(pos, block(pos));
+        jitter.pop(&mut out, false);
+    }
+    // 112 is lost, 100 is long played when it comes again
+    jitter.push(100, block(100));
+    jitter.pop(&mut out, true);
+    let mut expected: Vec<f32> = (100..112).map(|i| i as f32).collect();
+    expected.extend([0.0; 4]);
+    expected.extend(block(116));
+    assert_eq!(out, expected);
+    assert_eq!(
+        jitter.stats,
+        NetStats {
+            packets: 6,
+            lost_frames: 4,
+            late_packets: 1,
+            duplicate_packets: 1,
+            resets: 0,
+        }
+    );
+
+    // a packet inside an earlier, longer one
+    let mut jitter = JitterBuffer::new(1, 4, 1000);
+    jitter.push(100, (100..108).map(|i| i as f32).collect());
+    jitter.push(104, vec![104.0, 105.0]);
+    let mut out = Vec::new();
+    jitter.pop(&mut out, false);
+    jitter.pop(&mut out, true);
+    assert_eq!(out, (100..108).map(|i| i as f32).collect::<Vec<_>>());
+
+    struct Collect(Mutex<Vec<f32>>);
+    impl PCMSender for Collect {
+        fn num_channels(&self) -> usize {
+            2
+        }
+        fn send_pcm(&self, channels: i32, samples: &[f32]) {
+            assert_eq!(channels, 2);
+            self.0.lock().unwrap().extend_from_slice(samples);
+        }
+        fn send_pcm16(&self, _channels: i32, _samples: &[i16]) {
+            unreachable!();
+        }
+    }
+    let wait_for = |collect: &Collect, len: usize| {
+        for _ in 0..500 {
+            if collect.0.lock().unwrap().len() >= len {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        collect.0.lock().unwrap().clone()
+    };
+    // stereo frames, left counts up and right counts down
+    let frame = |i: u32| [i as i16 * 8, -(i as i16) * 8];
+    let sample = |s: i16| s as f32 / 32768.0;
+
+    // a local RTP sender, packet 3 of 10 is lost and 5 comes before 4
+    let collect = Arc::new(Collect(Mutex::new(Vec::new())));
+    let config = NetConfig {
+        protocol: NetProtocol::Rtp,
+        bind: "127.0.0.1:0".to_string(),
+        pcm: PcmSpec::parse("s16be:2:48000").unwrap(),
+        jitter_ms: 20.0,
+    };
+    let net = SlidingNet::start(collect.clone(), config).unwrap();
+    let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.connect(net.local_addr).unwrap();
+    for seq in [0u32, 1, 2, 5, 4, 6, 7, 8, 9] {
+        // timestamps wrap during the stream
+        let timestamp = (u32::MAX - 1000).wrapping_add(seq * 240);
+        let mut packet = vec![0x80, 96];
+        packet.extend_from_slice(&(seq as u16).to_be_bytes());
+        packet.extend_from_slice(&timestamp.to_be_bytes());
+        packet.extend_from_slice(&0x1234u32.to_be_bytes());
+        for i in seq * 240..(seq + 1) * 240 {
+            packet.extend(frame(i).iter().flat_map(|s| s.to_be_bytes()));
+        }
+        socket.send(&packet).unwrap();
+    }
+    let got = wait_for(&collect, 2 * 2400);
+    assert_eq!(got.len(), 2 * 2400);
+    for i in 0..2400 {
+        let expected = if (720..960).contains(&i) {
+            [0.0, 0.0]
+        } else {
+            frame(i).map(sample)
+        };
+        assert_eq!(
+            got[2 * i as usize..2 * i as usize + 2],
+            expected,
+            "frame {}",
+            i
+        );
+    }
+    assert_eq!(net.stats.lock().unwrap().lost_frames, 240);
+
+    // raw s16le over TCP, written in odd sized pieces
+    let collect = Arc::new(Collect(Mutex::new(Vec::new())));
+    let config = NetConfig {
+        protocol: NetProtocol::Tcp,
+        bind: "127.0.0.1:0".to_string(),
+        pcm: PcmSpec::parse("s16le:2:48000").unwrap(),
+        jitter_ms: 20.0,
+    };
+    let net = SlidingNet::start(collect.clone(), config).unwrap();
+    let bytes: Vec<u8> = (0..1000)
+        .flat_map(frame)
+        .flat_map(|s| s.to_le_bytes())
+        .collect();
+    let mut stream = std::net::TcpStream::connect(net.local_addr).unwrap();
+    for piece in bytes.chunks(777) {
+        stream.write_all(piece).unwrap();
+    }
+    drop(stream);
+    let got = wait_for(&collect, 2 * 1000);
+    let expected: Vec<f32> = (0..1000).flat_map(frame).map(sample).collect();
+    assert_eq!(got, expected);
+}
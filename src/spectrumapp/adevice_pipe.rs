@@ -0,0 +1,304 @@
+use std::io::Read;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use super::appstate::{set_app_state, AppState};
+use super::appthread::PCMSender;
+
+// Interleaved raw PCM from stdin or a named pipe, for example
+//   arecord -f FLOAT_LE -c 2 -r 48000 | sbsdft --stdin f32le:2:48000
+//   ffmpeg -re -i in.mp3 -f s16le -ac 1 -ar 48000 - | sbsdft --stdin s16le:1:48000
+// Samples are forwarded as they arrive, a pipe that is faster than real time
+// (ffmpeg without -re) overruns like a stalled audio thread would.
+
+/// frames per block sent to the processing thread
+const BLOCK_FRAMES: usize = 512;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum PcmFormat {
+    S16le,
+    /// RTP L16
+    S16be,
+    S32le,
+    F32le,
+}
+
+impl PcmFormat {
+    pub fn bytes_per_sample(&self) -> usize {
+        match self {
+            PcmFormat::S16le | PcmFormat::S16be => 2,
+            PcmFormat::S32le | PcmFormat::F32le => 4,
+        }
+    }
+
+    /// Converts whole samples of `bytes`, appending to `out`
+    pub fn decode(&self, bytes: &[u8], out: &mut Vec<f32>) {
+        match self {
+            PcmFormat::S16le => out.extend(
+                bytes
+                    .chunks_exact(2)
+                    .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0),
+            ),
+            PcmFormat::S16be => out.extend(
+                bytes
+                    .chunks_exact(2)
+                    .map(|b| i16::from_be_bytes([b[0], b[1]]) as f32 / 32768.0),
+            ),
+            PcmFormat::S32le => out.extend(
+                bytes
+                    .chunks_exact(4)
+                    .map(|b| i32::from_le_bytes([b[0], b[1], b[2], b[3]]) as f32 / 2147483648.0),
+            ),
+            PcmFormat::F32le => out.extend(
+                bytes
+                    .chunks_exact(4)
+                    .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+            ),
+        }
+    }
+}
+
+/// Layout of interleaved raw PCM
+#[derive(Clone, Debug, PartialEq)]
+pub struct PcmSpec {
+    pub format: PcmFormat,
+    pub channels: usize,
+    pub sample_rate: u32,
+}
+
+impl PcmSpec {
+    /// FORMAT[:CHANNELS[:RATE]], one channel at 48 kHz if missing
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        use clap::ValueEnum;
+        let mut parts = spec.split(':');
+        let format = parts.next().unwrap_or_default();
+        let format = PcmFormat::from_str(format, true).map_err(|_| {
+            format!(
+                "unknown format '{}', expected s16le, s16be, s32le or f32le",
+                format
+            )
+        })?;
+        let channels = match parts.next() {
+            Some(c) => c
+                .parse()
+                .ok()
+                .filter(|c| *c >= 1)
+                .ok_or_else(|| format!("bad channel count '{}'", c))?,
+            None => 1,
+        };
+        let sample_rate = match parts.next() {
+            Some(r) => r
+                .parse()
+                .ok()
+                .filter(|r| *r >= 8000)
+                .ok_or_else(|| format!("bad sample rate '{}', at least 8000", r))?,
+            None => 48000,
+        };
+        if parts.next().is_some() {
+            return Err(format!("expected FORMAT[:CHANNELS[:RATE]], got '{}'", spec));
+        }
+        Ok(Self {
+            format,
+            channels,
+            sample_rate,
+        })
+    }
+
+    pub fn frame_bytes(&self) -> usize {
+        self.format.bytes_per_sample() * self.channels
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "{:?} {} ch {} Hz",
+            self.format, self.channels, self.sample_rate
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PipeConfig {
+    /// None reads stdin
+    pub path: Option<String>,
+    pub pcm: PcmSpec,
+}
+
+impl PipeConfig {
+    pub fn describe(&self) -> String {
+        format!(
+            "{} {}",
+            self.path.as_deref().unwrap_or("stdin"),
+            self.pcm.describe()
+        )
+    }
+}
+
+/// Sends whole blocks of `reader` until its end, returns the frames sent
+pub fn forward_pcm(
+    reader: &mut impl Read,
+    pcm: &PcmSpec,
+    tx: &dyn PCMSender,
+) -> std::io::Result<u64> {
+    let frame_bytes = pcm.frame_bytes();
+    let mut bytes = vec![0u8; BLOCK_FRAMES * frame_bytes];
+    let mut samples = Vec::with_capacity(BLOCK_FRAMES * pcm.channels);
+    let mut filled = 0;
+    let mut frames = 0;
+    loop {
+        let n = match reader.read(&mut bytes[filled..]) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        filled += n;
+        // a pipe hands out what it has, send the whole frames and keep the rest
+        let whole = filled - filled % frame_bytes;
+        if whole == 0 {
+            continue;
+        }
+        samples.clear();
+        pcm.format.decode(&bytes[..whole], &mut samples);
+        tx.send_pcm(pcm.channels as i32, &samples);
+        frames += (whole / frame_bytes) as u64;
+        bytes.copy_within(whole..filled, 0);
+        filled -= whole;
+    }
+    Ok(frames)
+}
+
+pub struct SlidingPipe {
+    sender: Arc<dyn PCMSender>,
+    pub config: PipeConfig,
+    thread: Mutex<Option<std::thread::JoinHandle<()>>>,
+}
+
+impl SlidingPipe {
+    pub fn new(sender: Arc<dyn PCMSender>, config: PipeConfig) -> Arc<Self> {
+        Arc::new(Self {
+            sender,
+            config,
+            thread: Mutex::new(None),
+        })
+    }
+
+    pub fn start(&self) {
+        let sender = self.sender.clone();
+        let config = self.config.clone();
+        if config.pcm.channels < sender.num_channels() {
+            println!(
+                "warning: input has {} channels, but {} are analyzed",
+                config.pcm.channels,
+                sender.num_channels()
+            );
+        }
+        println!("reading {}", config.describe());
+        let thread = std::thread::spawn(move || {
+            set_app_state(AppState::Playing);
+            let pcm = &config.pcm;
+            let seconds = |frames: u64| frames as f64 / pcm.sample_rate as f64;
+            match &config.path {
+                None => {
+                    let result = forward_pcm(&mut std::io::stdin().lock(), pcm, &*sender);
+                    match result {
+                        Ok(frames) => {
+                            println!("stdin: end of input after {:.1} s", seconds(frames))
+                        }
+                        Err(err) => println!("stdin: {}", err),
+                    }
+                }
+                // a FIFO ends when its writer closes it, wait for the next one
+                Some(path) => loop {
+                    let result = std::fs::File::open(path)
+                        .and_then(|mut file| forward_pcm(&mut file, pcm, &*sender));
+                    match result {
+                        Ok(frames) => {
+                            println!("{}: writer closed after {:.1} s", path, seconds(frames))
+                        }
+                        Err(err) => {
+                            println!("{}: {}", path, err);
+                            break;
+                        }
+                    }
+                    // a regular file would end right away again
+                    if !is_fifo(path) {
+                        break;
+                    }
+                },
+            }
+        });
+        *self.thread.lock().unwrap() = Some(thread);
+    }
+}
+
+#[cfg(unix)]
+fn is_fifo(path: &str) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    std::fs::metadata(path)
+        .map(|m| m.file_type().is_fifo())
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_fifo(_path: &str) -> bool {
+    false
+}
+
+#[test]
+fn test_pipe_input() {
+    assert_eq!(
+        PcmSpec::parse("f32le:2:44100"),
+        Ok(PcmSpec {
+            format: PcmFormat::F32le,
+            channels: 2,
+            sample_rate: 44100,
+        })
+    );
+    let pcm = PcmSpec::parse("S16LE").unwrap();
+    assert_eq!((pcm.channels, pcm.sample_rate), (1, 48000));
+    assert!(PcmSpec::parse("u8:1").is_err());
+    assert!(PcmSpec::parse("s16le:0").is_err());
+    assert!(PcmSpec::parse("s16le:1:48000:x").is_err());
+
+    let mut out = Vec::new();
+    PcmFormat::S16le.decode(&[0x00, 0x80, 0x00, 0x40, 0xff], &mut out);
+    PcmFormat::S16be.decode(&[0x40, 0x00], &mut out);
+    PcmFormat::S32le.decode(&0x4000_0000i32.to_le_bytes(), &mut out);
+    PcmFormat::F32le.decode(&0.25f32.to_le_bytes(), &mut out);
+    assert_eq!(out, vec![-1.0, 0.5, 0.5, 0.5, 0.25]);
+
+    struct Collect(Mutex<Vec<(i32, Vec<f32>)>>);
+    impl PCMSender for Collect {
+        fn num_channels(&self) -> usize {
+            2
+        }
+        fn send_pcm(&self, channels: i32, samples: &[f32]) {
+            self.0.lock().unwrap().push((channels, samples.to_vec()));
+        }
+        fn send_pcm16(&self, _channels: i32, _samples: &[i16]) {
+            unreachable!();
+        }
+    }
+
+    // reads that split frames, and a partial frame at the end
+    struct Trickle(Vec<u8>, usize);
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.1.min(buf.len()).min(self.0.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0.drain(..n);
+            Ok(n)
+        }
+    }
+    let pcm = PcmSpec::parse("s16le:2").unwrap();
+    let mut bytes: Vec<u8> = (0..3000i16).flat_map(|i| i.to_le_bytes()).collect();
+    bytes.push(7);
+    let collect = Collect(Mutex::new(Vec::new()));
+    let frames = forward_pcm(&mut Trickle(bytes, 333), &pcm, &collect).unwrap();
+    assert_eq!(frames, 1500);
+    let sent = collect.0.into_inner().unwrap();
+    assert!(sent.iter().all(|(c, s)| *c == 2 && s.len() % 2 == 0));
+    let samples: Vec<f32> = sent.into_iter().flat_map(|(_, s)| s).collect();
+    let expected: Vec<f32> = (0..3000).map(|i| i as f32 / 32768.0).collect();
+    assert_eq!(samples, expected);
+}
@@ -1,3 +1,5 @@
+use std::sync::Mutex;
+
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub enum AppState {
     Uninit,
@@ -15,9 +17,13 @@ pub enum AppState {
 
     GetUserMediaFailed,
     Playing,
+    /// the input device failed or stopped delivering audio, being reopened
+    AudioError,
 }
 
 static mut APP_STATE: AppState = AppState::Uninit;
+/// what went wrong in an error state, shown next to it
+static APP_STATE_DETAIL: Mutex<String> = Mutex::new(String::new());
 
 pub fn get_app_state() -> AppState {
     unsafe { APP_STATE }
@@ -27,3 +33,10 @@ pub fn set_app_state(new_state: AppState) {
         APP_STATE = new_state;
     }
 }
+
+pub fn get_app_state_detail() -> String {
+    APP_STATE_DETAIL.lock().unwrap().clone()
+}
+pub fn set_app_state_detail(detail: String) {
+    *APP_STATE_DETAIL.lock().unwrap() = detail;
+}
@@ -1,6 +1,10 @@
+use std::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex, Weak};
 
-use crossbeam_channel::{bounded, Receiver, Sender};
+#[cfg(not(target_arch = "wasm32"))]
+use cpal::Sample;
+use crossbeam_channel::{bounded, Receiver, Sender, TrySendError};
+use num_enum::{IntoPrimitive, TryFromPrimitive};
 
 use super::sbswdft::SlidingImpl;
 
@@ -14,9 +18,75 @@ pub trait PCMSender: Send + Sync {
     fn send_pcm16(&self, channel: i32, samples: &[i16]);
 }
 
+/// What the audio thread does when the processing thread falls behind
+#[derive(Copy, Clone, Debug, PartialEq, TryFromPrimitive, IntoPrimitive, clap::ValueEnum)]
+#[repr(u8)]
+pub enum OverrunPolicy {
+    /// the new block is discarded
+    DropNewest,
+    /// the oldest queued block makes room for the new one
+    DropOldest,
+    /// the audio thread waits for room
+    Block,
+}
+
+/// Counters of the audio to processing thread queue, read by the UI
+pub struct PipelineStats {
+    policy: AtomicU8,
+    pub dropped_blocks: AtomicU64,
+    pub dropped_samples: AtomicU64,
+    /// sends that found the queue full and had to wait
+    pub blocked_sends: AtomicU64,
+    /// no spare buffer, the audio thread allocated
+    pub reuse_misses: AtomicU64,
+    /// the spare buffer pool was full, a buffer was freed
+    pub reuse_overflows: AtomicU64,
+    pub max_queue_depth: AtomicUsize,
+}
+
+impl PipelineStats {
+    pub fn new() -> Self {
+        Self {
+            policy: AtomicU8::new(OverrunPolicy::DropNewest.into()),
+            dropped_blocks: AtomicU64::new(0),
+            dropped_samples: AtomicU64::new(0),
+            blocked_sends: AtomicU64::new(0),
+            reuse_misses: AtomicU64::new(0),
+            reuse_overflows: AtomicU64::new(0),
+            max_queue_depth: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn policy(&self) -> OverrunPolicy {
+        OverrunPolicy::try_from(self.policy.load(Ordering::Relaxed))
+            .unwrap_or(OverrunPolicy::DropNewest)
+    }
+
+    pub fn set_policy(&self, policy: OverrunPolicy) {
+        self.policy.store(policy.into(), Ordering::Relaxed);
+    }
+
+    pub fn cycle_policy(&self) {
+        let n: u8 = self.policy().into();
+        self.set_policy(match OverrunPolicy::try_from(n + 1) {
+            Ok(x) => x,
+            Err(_) => OverrunPolicy::try_from(0).unwrap(),
+        });
+    }
+
+    fn count_dropped(&self, samples: usize) {
+        self.dropped_blocks.fetch_add(1, Ordering::Relaxed);
+        self.dropped_samples
+            .fetch_add(samples as u64, Ordering::Relaxed);
+    }
+}
+
 #[derive(Clone)]
 pub struct SlidingAppSender {
     main_pcm_tx: Sender<AppMsg>,
+    /// only used to discard the oldest block
+    main_pcm_rx: Receiver<AppMsg>,
+    stats: Arc<PipelineStats>,
 
     /// We don't want to allocate on Audio worklet thread
     //reuse_buffers_tx: Sender<Vec<f32>>,
@@ -24,20 +94,69 @@ pub struct SlidingAppSender {
     channel_num: usize,
 }
 impl SlidingAppSender {
-    pub fn new(tx: Sender<AppMsg>, channel_num: usize, reuse_rx: Receiver<Vec<f32>>) -> Self {
+    pub fn new(
+        tx: Sender<AppMsg>,
+        rx: Receiver<AppMsg>,
+        stats: Arc<PipelineStats>,
+        channel_num: usize,
+        reuse_rx: Receiver<Vec<f32>>,
+    ) -> Self {
         Self {
             channel_num,
             main_pcm_tx: tx,
+            main_pcm_rx: rx,
+            stats,
             reuse_buffers_rx: reuse_rx,
             //weak_sliding_channels: channels,
         }
     }
 }
 impl SlidingAppSender {
-    fn send_vec(&self, num_channels: i32, buf: Vec<f32>) {
-        let _ = self
-            .main_pcm_tx
-            .try_send(AppMsg::PcmAudio(num_channels, buf));
+    fn send_vec(&self, num_channels: i32, buf: Vec<f32>, capture_ms: f64) {
+        let stats = &self.stats;
+        let msg = AppMsg::PcmAudio(num_channels, buf, capture_ms);
+        let msg = match self.main_pcm_tx.try_send(msg) {
+            Err(TrySendError::Full(msg)) => msg,
+            _ => {
+                stats
+                    .max_queue_depth
+                    .fetch_max(self.main_pcm_tx.len(), Ordering::Relaxed);
+                return;
+            }
+        };
+
+        let samples = |msg: &AppMsg| match msg {
+            AppMsg::PcmAudio(_, buf, _) => buf.len(),
+            _ => 0,
+        };
+        match stats.policy() {
+            OverrunPolicy::DropNewest => {
+                stats.count_dropped(samples(&msg));
+            }
+            OverrunPolicy::DropOldest => {
+                if let Ok(old) = self.main_pcm_rx.try_recv() {
+                    stats.count_dropped(samples(&old));
+                }
+                if let Err(err) = self.main_pcm_tx.try_send(msg) {
+                    stats.count_dropped(samples(&err.into_inner()));
+                }
+            }
+            OverrunPolicy::Block => {
+                stats.blocked_sends.fetch_add(1, Ordering::Relaxed);
+                let _ = self.main_pcm_tx.send(msg);
+            }
+        }
+        stats
+            .max_queue_depth
+            .fetch_max(self.main_pcm_tx.len(), Ordering::Relaxed);
+    }
+
+    fn reuse_buffer(&self) -> Option<Vec<f32>> {
+        let buf = self.reuse_buffers_rx.try_recv().ok();
+        if buf.is_none() {
+            self.stats.reuse_misses.fetch_add(1, Ordering::Relaxed);
+        }
+        buf
     }
 }
 
@@ -53,7 +172,8 @@ impl PCMSender for SlidingAppSender {
     }
 
     fn send_pcm(&self, num_channels: i32, samples: &[f32]) {
-        let optbuf = self.reuse_buffers_rx.try_recv().ok();
+        let capture_ms = super::kwasm::now_ms();
+        let optbuf = self.reuse_buffer();
         let buf: Vec<f32>;
 
         if let Some(mut sbuf) = optbuf {
@@ -65,12 +185,13 @@ impl PCMSender for SlidingAppSender {
             buf = samples.to_vec();
         }
 
-        self.send_vec(num_channels, buf);
+        self.send_vec(num_channels, buf, capture_ms);
     }
 
     #[cfg(not(target_arch = "wasm32"))]
     fn send_pcm16(&self, num_channels: i32, samples: &[i16]) {
-        let optbuf = self.reuse_buffers_rx.try_recv().ok();
+        let capture_ms = super::kwasm::now_ms();
+        let optbuf = self.reuse_buffer();
         let buf: Vec<f32>;
 
         if let Some(mut sbuf) = optbuf {
@@ -84,7 +205,7 @@ impl PCMSender for SlidingAppSender {
             buf = sbuf;
         }
 
-        self.send_vec(num_channels, buf);
+        self.send_vec(num_channels, buf, capture_ms);
     }
 
     // fn on_receive(&self, channels_samples: &[Vec<f32>]) {
@@ -104,8 +225,8 @@ pub type AppFunc = dyn FnOnce(&ProcessingApp) + Send;
 
 pub enum AppMsg {
     RunFunc(Box<AppFunc>),
-    /// num_channels
-    PcmAudio(i32, Vec<f32>),
+    /// num_channels, interleaved samples, wall-clock time of the audio callback in ms
+    PcmAudio(i32, Vec<f32>, f64),
 }
 
 pub struct ProcessingApp {
@@ -114,7 +235,9 @@ pub struct ProcessingApp {
     pub sliding_channels: Vec<Arc<Mutex<SlidingImpl>>>,
     bufs: Mutex<Vec<Vec<f32>>>,
     pub main_pcm_tx: Sender<AppMsg>,
+    main_pcm_rx: Receiver<AppMsg>,
     pub main_priority_tx: Sender<AppMsg>,
+    pub stats: Arc<PipelineStats>,
 
     /// We don't want to allocate on Audio worklet thread
     //pub reuse_buffers_tx: Sender<Vec<f32>>,
@@ -140,7 +263,9 @@ impl ProcessingApp {
             sliding_channels: sliding_channels,
             bufs: Mutex::new(bufs),
             main_pcm_tx: tx,
+            main_pcm_rx: rx.clone(),
             main_priority_tx: priority_tx,
+            stats: Arc::new(PipelineStats::new()),
             reuse_buffers_rx: reuse_rx,
             //reuse_buffers_tx: reuse_tx,
         });
@@ -156,6 +281,8 @@ impl ProcessingApp {
     pub fn new_sender(&self) -> SlidingAppSender {
         SlidingAppSender::new(
             self.main_pcm_tx.clone(),
+            self.main_pcm_rx.clone(),
+            self.stats.clone(),
             self.sliding_channels.len(),
             self.reuse_buffers_rx.clone(),
         )
@@ -189,25 +316,34 @@ impl ProcessingApp {
             Some(AppMsg::RunFunc(appfn)) => {
                 appfn(self);
             }
-            Some(AppMsg::PcmAudio(ch_num, samples)) => {
+            Some(AppMsg::PcmAudio(ch_num, samples, capture_ms)) => {
                 let mut bufs = self.bufs.lock().unwrap();
                 let in_channels = if ch_num < 0 {
                     self.sliding_channels.len()
                 } else {
                     ch_num as usize
                 };
-                self.on_receive(&mut bufs, in_channels, &samples);
+                self.on_receive(&mut bufs, in_channels, &samples, capture_ms);
 
                 // Reusing Vec<f32>
-                let _ = reuse_tx.try_send(samples);
+                if reuse_tx.try_send(samples).is_err() {
+                    self.stats.reuse_overflows.fetch_add(1, Ordering::Relaxed);
+                }
             }
         }
 
         true
     }
 
-    pub fn on_receive(&self, bufs: &mut Vec<Vec<f32>>, in_channels: usize, samples: &[f32]) {
-        for c in 0..in_channels {
+    pub fn on_receive(
+        &self,
+        bufs: &mut Vec<Vec<f32>>,
+        in_channels: usize,
+        samples: &[f32],
+        capture_ms: f64,
+    ) {
+        // extra input channels are not analyzed
+        for c in 0..in_channels.min(bufs.len()) {
             let mut buf = &mut bufs[c];
             buf.resize(samples.len() / in_channels, 0.0);
             let buf = &mut buf;
@@ -223,16 +359,47 @@ impl ProcessingApp {
         }
 
         // TODO
-        self.on_receive_sliding(&bufs);
+        self.on_receive_sliding(&bufs, capture_ms);
     }
 
-    fn on_receive_sliding(&self, channels_samples: &[Vec<f32>]) {
+    fn on_receive_sliding(&self, channels_samples: &[Vec<f32>], capture_ms: f64) {
         for (out_channel, samples) in self.sliding_channels.iter().zip(channels_samples) {
             match &mut *out_channel.lock().unwrap() {
-                SlidingImpl::DFT(dft) => dft.on_input(&samples),
+                SlidingImpl::DFT(dft) => dft.on_input(&samples, capture_ms),
                 //SlidingImpl::Correlator(corr) => corr.on_input(&buf),
                 //_ => {}
             };
         }
     }
 }
+
+#[test]
+fn test_overrun_policies() {
+    let (tx, rx) = bounded(2);
+    let (_reuse_tx, reuse_rx) = bounded(2);
+    let stats = Arc::new(PipelineStats::new());
+    let sender = SlidingAppSender::new(tx, rx.clone(), stats.clone(), 1, reuse_rx);
+
+    for i in 0..4 {
+        sender.send_pcm(1, &[i as f32; 8]);
+    }
+    assert_eq!(stats.dropped_blocks.load(Ordering::Relaxed), 2);
+    assert_eq!(stats.dropped_samples.load(Ordering::Relaxed), 16);
+    assert_eq!(stats.max_queue_depth.load(Ordering::Relaxed), 2);
+    assert_eq!(stats.reuse_misses.load(Ordering::Relaxed), 4);
+
+    let first = |rx: &Receiver<AppMsg>| match rx.try_recv() {
+        Ok(AppMsg::PcmAudio(_, buf, _)) => buf[0],
+        _ => -1.0,
+    };
+    // drop newest kept the first blocks
+    sender.send_pcm(1, &[9.0; 8]);
+    assert_eq!(first(&rx), 0.0);
+
+    stats.set_policy(OverrunPolicy::DropOldest);
+    sender.send_pcm(1, &[10.0; 8]);
+    sender.send_pcm(1, &[11.0; 8]);
+    assert_eq!(first(&rx), 10.0);
+    assert_eq!(first(&rx), 11.0);
+    assert_eq!(stats.dropped_blocks.load(Ordering::Relaxed), 4);
+}
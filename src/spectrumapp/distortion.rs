@@ -0,0 +1,338 @@
+use std::collections::VecDeque;
+
+use super::sbswdft::fixed_point_to_phase_shift_per_sample64;
+use super::sbswdft::phase_shift_per_sample_to_fixed_point64;
+use super::sbswdft::ChannelSWDFT;
+use super::sbswdft::RawSSample;
+use super::sbswdft::RegularBin;
+use super::sbswdft::QUANTIZER_LEVELS_INV_F64;
+
+// Distortion analyzer, works on the raw (rectangular window) DFT sums.
+// Like subtract_peak, it takes the strongest bin as the fundamental and removes
+// it from the spectrum with subtract_sine, at a frequency interpolated between
+// the bins. Then the same for each harmonic, in the strongest bin next to its
+// frequency. What is left is noise.
+
+pub struct DistortionConfig {
+    /// highest harmonic measured, 2 is the first one
+    pub max_order: usize,
+    /// fundamental's share of the total power needed to call it a test tone
+    pub min_dominance: f64,
+}
+
+impl DistortionConfig {
+    pub fn new() -> Self {
+        Self {
+            max_order: 9,
+            min_dominance: 0.5,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Tone {
+    /// 1 for the fundamental
+    pub order: usize,
+    pub frequency_hz: f64,
+    /// peak amplitude, 1.0 is full scale
+    pub amplitude: f64,
+}
+
+impl Tone {
+    pub fn dbfs(&self) -> f64 {
+        20.0 * self.amplitude.max(1e-12).log10()
+    }
+
+    /// level relative to `fundamental`, dBc
+    pub fn dbc(&self, fundamental: &Tone) -> f64 {
+        20.0 * (self.amplitude / fundamental.amplitude).max(1e-12).log10()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DistortionReport {
+    pub fundamental: Tone,
+    /// harmonics inside the analyzed band, from the 2nd up
+    pub harmonics: Vec<Tone>,
+    /// ratios, not percent
+    pub thd: f64,
+    pub thd_n: f64,
+    pub sinad_db: f64,
+    pub enob: f64,
+    /// frequency range the noise was integrated over
+    pub band_hz: (f64, f64),
+}
+
+impl DistortionReport {
+    pub fn thd_db(&self) -> f64 {
+        20.0 * self.thd.max(1e-12).log10()
+    }
+
+    pub fn thd_n_db(&self) -> f64 {
+        20.0 * self.thd_n.max(1e-12).log10()
+    }
+}
+
+/// Pulsation in radians per sample and length of a bin
+fn bin_shape(bin: &RegularBin) -> (f64, f64) {
+    let omega = std::f64::consts::TAU * fixed_point_to_phase_shift_per_sample64(bin.bin.pulsation);
+    // sum_ranged_all leaves out the oldest partial sum
+    (omega, (bin.bin.current_length.max(2) - 1) as f64)
+}
+
+/// The analyzer's input is the average of sample pairs, a gentle low-pass
+fn decimation_gain(omega: f64) -> f64 {
+    (0.25 * omega).cos().abs().max(1e-3)
+}
+
+/// Peak amplitude of a tone centered on bin `i`, 1.0 is full scale
+fn amplitude(spectrum: &[RawSSample], bins: &VecDeque<RegularBin>, i: usize) -> f64 {
+    // raw sums are sample * sine, to_f64 takes off one of the quantizations
+    let (omega, length) = bin_shape(&bins[i]);
+    let magnitude =
+        spectrum[i].complex.to_f64().magnitude_squared().sqrt() * QUANTIZER_LEVELS_INV_F64;
+    2.0 * magnitude / length / decimation_gain(omega)
+}
+
+/// Parabola through the magnitudes of bins `i - 1`, `i` and `i + 1`, returns
+/// the pulsation at its top
+fn parabola_top(bins: &VecDeque<RegularBin>, i: usize, magnitudes: [f64; 3]) -> f64 {
+    let [l, c, r] = magnitudes;
+    let (omega, _) = bin_shape(&bins[i]);
+    let den = l - 2.0 * c + r;
+    if den.abs() < 1e-30 {
+        return omega;
+    }
+    let delta = (0.5 * (l - r) / den).clamp(-0.5, 0.5);
+    let (left, _) = bin_shape(&bins[i - 1]);
+    let (right, _) = bin_shape(&bins[i + 1]);
+    if delta < 0.0 {
+        omega + delta * (omega - left)
+    } else {
+        omega + delta * (right - omega)
+    }
+}
+
+/// Pulsation of a tone peaking at bin `i`, interpolated between its neighbours.
+/// The bins differ in length, so the parabola is biased; the bias is taken
+/// from the parabola through subtract_sine's model of a tone at the estimate
+fn interpolated_omega(spectrum: &[RawSSample], bins: &VecDeque<RegularBin>, i: usize) -> f64 {
+    const INTERPOLATION_STEPS: usize = 3;
+    if i == 0 || i + 1 >= spectrum.len() {
+        return bin_shape(&bins[i]).0;
+    }
+    let measured = parabola_top(
+        bins,
+        i,
+        [i - 1, i, i + 1].map(|j| amplitude(spectrum, bins, j)),
+    );
+    let mut omega = measured;
+    for _ in 0..INTERPOLATION_STEPS {
+        let f = omega / std::f64::consts::TAU;
+        let Some((_, phase)) = fit_sine(spectrum, bins, i, f) else {
+            break;
+        };
+        let model = [i - 1, i, i + 1].map(|j| {
+            let response = ChannelSWDFT::sine_response(&bins[j], f, phase);
+            response.magnitude_squared().sqrt() / bin_shape(&bins[j]).1
+        });
+        omega += measured - parabola_top(bins, i, model);
+    }
+    omega
+}
+
+/// Magnitude and phase, as subtract_sine takes them, of a sine of `f`
+/// cycles per sample that explains the sum of bin `i`
+fn fit_sine(
+    spectrum: &[RawSSample],
+    bins: &VecDeque<RegularBin>,
+    i: usize,
+    f: f64,
+) -> Option<(f64, f64)> {
+    // the response is linear in the cosine and sine of the phase
+    let c = ChannelSWDFT::sine_response(&bins[i], f, 0.0);
+    let s = ChannelSWDFT::sine_response(&bins[i], f, 0.25);
+    let y = spectrum[i].complex.to_f64();
+    let det = (c.re * s.im - s.re * c.im) * bins[i].bin.inv_lengthf;
+    if det.abs() < 1e-30 {
+        return None;
+    }
+    let u = (y.re * s.im - s.re * y.im) / det;
+    let v = (c.re * y.im - y.re * c.im) / det;
+    Some((u.hypot(v), v.atan2(u) / std::f64::consts::TAU))
+}
+
+/// Removes a sine of pulsation `omega` fitted to bin `i` from the spectrum,
+/// returns its amplitude
+fn subtract_tone(
+    spectrum: &mut Vec<RawSSample>,
+    bins: &VecDeque<RegularBin>,
+    i: usize,
+    omega: f64,
+) -> f64 {
+    let pulse = phase_shift_per_sample_to_fixed_point64(omega / std::f64::consts::TAU);
+    let f = fixed_point_to_phase_shift_per_sample64(pulse);
+    let Some((mag, phase)) = fit_sine(spectrum, bins, i, f) else {
+        return 0.0;
+    };
+    ChannelSWDFT::subtract_sine(
+        i as i32,
+        pulse,
+        mag,
+        phase,
+        spectrum,
+        bins,
+        spectrum.len() as i32,
+    );
+    // the sine sums to mag / inv_lengthf * amplitude / 2
+    2.0 * mag * QUANTIZER_LEVELS_INV_F64 * bins[i].bin.inv_lengthf / decimation_gain(omega)
+}
+
+/// Strongest of the bins around the one nearest to `omega`
+fn harmonic_bin(spectrum: &[RawSSample], bins: &VecDeque<RegularBin>, omega: f64) -> usize {
+    let mut nearest = 0;
+    let mut nearest_distance = f64::MAX;
+    for (i, bin) in bins.iter().enumerate().take(spectrum.len()) {
+        let distance = (bin_shape(bin).0 - omega).abs();
+        if distance < nearest_distance {
+            nearest = i;
+            nearest_distance = distance;
+        }
+    }
+    let range = nearest.saturating_sub(1)..(nearest + 2).min(spectrum.len());
+    range
+        .max_by(|&a, &b| amplitude(spectrum, bins, a).total_cmp(&amplitude(spectrum, bins, b)))
+        .unwrap_or(nearest)
+}
+
+/// Measures the dominant tone and its harmonics in `spectrum`, which should
+/// be the raw sums of `bins`. None if there's no dominant tone.
+pub fn analyze(
+    config: &DistortionConfig,
+    bins: &VecDeque<RegularBin>,
+    mut spectrum: Vec<RawSSample>,
+    sample_rate: u32,
+) -> Option<DistortionReport> {
+    const TAU: f64 = std::f64::consts::TAU;
+    let len = spectrum.len().min(bins.len());
+    if len < 3 {
+        return None;
+    }
+    spectrum.truncate(len);
+    let to_hz = |omega: f64| omega / TAU * sample_rate as f64;
+
+    let max_index = ChannelSWDFT::find_max_ssample(&spectrum);
+    let omega0 = interpolated_omega(&spectrum, bins, max_index);
+    let fundamental = Tone {
+        order: 1,
+        frequency_hz: to_hz(omega0),
+        amplitude: subtract_tone(&mut spectrum, bins, max_index, omega0),
+    };
+    if fundamental.amplitude < 1e-6 {
+        return None;
+    }
+
+    let max_omega = bin_shape(&bins[len - 1]).0;
+    let mut harmonics = Vec::new();
+    for order in 2..=config.max_order {
+        let omega = omega0 * order as f64;
+        if omega > max_omega || omega >= std::f64::consts::PI {
+            break;
+        }
+        let i = harmonic_bin(&spectrum, bins, omega);
+        harmonics.push(Tone {
+            order,
+            frequency_hz: to_hz(omega),
+            amplitude: subtract_tone(&mut spectrum, bins, i, omega),
+        });
+    }
+
+    // integrate what's left, each bin covers the space halfway to its neighbours;
+    // mean power of a bin of white noise with variance s^2 is length * s^2
+    let mut noise_power = 0.0;
+    for i in 0..len {
+        let (_, length) = bin_shape(&bins[i]);
+        let (left, _) = bin_shape(&bins[i.saturating_sub(1)]);
+        let (right, _) = bin_shape(&bins[(i + 1).min(len - 1)]);
+        let width = 0.5 * (right - left).abs() / std::f64::consts::PI;
+        let a = amplitude(&spectrum, bins, i);
+        noise_power += 0.5 * a * a * length * width;
+    }
+
+    let fundamental_power = 0.5 * fundamental.amplitude * fundamental.amplitude;
+    let harmonics_power: f64 = harmonics
+        .iter()
+        .map(|h| 0.5 * h.amplitude * h.amplitude)
+        .sum();
+    let distortion_power = harmonics_power + noise_power;
+    if fundamental_power / (fundamental_power + distortion_power) < config.min_dominance {
+        return None;
+    }
+
+    let thd = (harmonics_power / fundamental_power).sqrt();
+    let thd_n = (distortion_power / fundamental_power).sqrt();
+    let sinad_db =
+        10.0 * ((fundamental_power + distortion_power) / distortion_power.max(1e-30)).log10();
+    let enob = (sinad_db - 1.76) / 6.02;
+
+    Some(DistortionReport {
+        fundamental,
+        harmonics,
+        thd,
+        thd_n,
+        sinad_db,
+        enob,
+        band_hz: (to_hz(bin_shape(&bins[0]).0), to_hz(max_omega)),
+    })
+}
+
+#[test]
+fn test_synthetic_distortion() {
+    use super::sbswdft::SpectrumConfig;
+
+    let config = SpectrumConfig::new();
+    let mut swdft = ChannelSWDFT::new(&config);
+    swdft.spectrum_bins = ChannelSWDFT::make_spectrum_bins(0, &config);
+    swdft.distortion = Some(DistortionConfig::new());
+    swdft.should_colorize = false;
+
+    // 1% second and 0.5% third harmonic, THD = sqrt(1^2 + 0.5^2) % = 1.118 %
+    let f0 = 997.0;
+    let input_rate = 48000.0;
+    let amplitude = 0.5;
+    let signal: Vec<f32> = (0..input_rate as usize / 2)
+        .map(|n| {
+            let t = n as f64 / input_rate;
+            let w = std::f64::consts::TAU * f0 * t;
+            (amplitude * (w.sin() + 0.01 * (2.0 * w).sin() + 0.005 * (3.0 * w + 0.3).sin())) as f32
+        })
+        .collect();
+    for chunk in signal.chunks(512) {
+        swdft.process_input(chunk);
+    }
+    let report = swdft.collect_spectrum().distortion.unwrap();
+    let ratio = |h: usize| report.harmonics[h - 2].amplitude / report.fundamental.amplitude;
+
+    assert!((report.fundamental.frequency_hz - f0).abs() < 0.1);
+    assert!((report.fundamental.amplitude - amplitude).abs() < 0.001);
+    assert!((ratio(2) - 0.01).abs() < 0.0002);
+    assert!((ratio(3) - 0.005).abs() < 0.0002);
+    assert!(ratio(4) < 0.0002);
+    assert!((report.thd - 0.01118).abs() < 0.0002);
+    assert!(report.thd_n >= report.thd && report.thd_n < 0.0115);
+    assert!((report.sinad_db + 20.0 * report.thd_n.log10()).abs() < 0.01);
+    assert!((report.enob - (report.sinad_db - 1.76) / 6.02).abs() < 1e-9);
+
+    // no dominant tone in noise
+    let mut x: u32 = 1;
+    let noise: Vec<f32> = (0..input_rate as usize / 2)
+        .map(|_| {
+            x = x.wrapping_mul(1664525).wrapping_add(1013904223);
+            (x >> 8) as f32 / (1 << 24) as f32 - 0.5
+        })
+        .collect();
+    for chunk in noise.chunks(512) {
+        swdft.process_input(chunk);
+    }
+    assert!(swdft.collect_spectrum().distortion.is_none());
+}
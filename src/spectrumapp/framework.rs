@@ -76,6 +76,12 @@ pub trait Example<P>: 'static + Sized {
         queue: &wgpu::Queue,
         spawner: &Spawner,
     );
+    /// Called instead of `init` when there is no usable adapter
+    fn run_without_gpu(_params: P) {
+        panic!("No suitable GPU adapters found on the system!");
+    }
+    /// Called once when the event loop ends
+    fn on_exit(&mut self) {}
 }
 
 struct Setup {
@@ -109,7 +115,7 @@ pub fn parse_url_query_string<'a>(query: &'a str, search_key: &str) -> Option<&'
 }
 
 // new one
-async fn setup<P, E: Example<P>>(title: &str) -> Setup {
+async fn setup<P, E: Example<P>>(title: &str) -> Option<Setup> {
     #[cfg(not(target_arch = "wasm32"))]
     {
         env_logger::init();
@@ -159,8 +165,7 @@ async fn setup<P, E: Example<P>>(title: &str) -> Setup {
     };
     let adapter =
         wgpu::util::initialize_adapter_from_env_or_default(&instance, backend, Some(&surface))
-            .await
-            .expect("No suitable GPU adapters found on the system!");
+            .await?;
 
     #[cfg(not(target_arch = "wasm32"))]
     {
@@ -222,7 +227,7 @@ async fn setup<P, E: Example<P>>(title: &str) -> Setup {
         })
         .expect("Unable to find a suitable GPU adapter!");
 
-    Setup {
+    Some(Setup {
         window,
         event_loop,
         instance,
@@ -231,7 +236,7 @@ async fn setup<P, E: Example<P>>(title: &str) -> Setup {
         adapter,
         device,
         queue,
-    }
+    })
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -372,6 +377,9 @@ fn start<P, E: Example<P>>(
             event::Event::UserEvent(MyEvent::CanvasResize(dx, dy)) => {
                 window.set_inner_size(Size::new(PhysicalSize::new(dx as u32, dy as u32)));
             }
+            event::Event::LoopDestroyed => {
+                example.on_exit();
+            }
             event::Event::RedrawEventsCleared => {
                 #[cfg(not(target_arch = "wasm32"))]
                 {
@@ -520,8 +528,10 @@ impl Spawner {
 
 #[cfg(not(target_arch = "wasm32"))]
 pub fn run<P, E: Example<P>>(title: &str, params: P) {
-    let setup = pollster::block_on(setup::<P, E>(title));
-    start::<P, E>(setup, params);
+    match pollster::block_on(setup::<P, E>(title)) {
+        Some(setup) => start::<P, E>(setup, params),
+        None => E::run_without_gpu(params),
+    }
 }
 
 // #[cfg(target_arch = "wasm32")]
@@ -539,7 +549,10 @@ pub fn run<P: 'static, E: Example<P>>(title: &str, params: P) {
 
     let title = title.to_owned();
     wasm_bindgen_futures::spawn_local(async move {
-        let setup = setup::<P, E>(&title).await;
+        let setup = match setup::<P, E>(&title).await {
+            Some(setup) => setup,
+            None => return E::run_without_gpu(params),
+        };
         let start_closure = Closure::once_into_js(move || start::<P, E>(setup, params));
 
         // make sure to handle JS exceptions thrown inside start.
@@ -0,0 +1,384 @@
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use num_enum::IntoPrimitive;
+use num_enum::TryFromPrimitive;
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand::SeedableRng;
+
+// Test signal generator, plays through a cpal output device
+// or renders to a WAV file when there is no output device.
+
+#[derive(Copy, Clone, Debug, PartialEq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+pub enum SignalKind {
+    Sine,
+    Multitone,
+    WhiteNoise,
+    PinkNoise,
+    LinearSweep,
+    LogSweep,
+    ImpulseTrain,
+}
+
+#[derive(Clone)]
+pub struct GeneratorParams {
+    pub kind: SignalKind,
+    /// sine frequency, or impulse rate of the impulse train
+    pub frequency_hz: f32,
+    /// peak level, dB relative to full scale
+    pub level_db: f32,
+    pub sweep_start_hz: f32,
+    pub sweep_end_hz: f32,
+    pub sweep_s: f32,
+}
+
+impl GeneratorParams {
+    pub fn new() -> Self {
+        Self {
+            kind: SignalKind::Sine,
+            frequency_hz: 1000.0,
+            level_db: -12.0,
+            sweep_start_hz: 20.0,
+            sweep_end_hz: 20000.0,
+            sweep_s: 5.0,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self.kind {
+            SignalKind::Sine => format!("sine {:.1} Hz", self.frequency_hz),
+            SignalKind::ImpulseTrain => format!("impulse train {:.2} Hz", self.frequency_hz),
+            SignalKind::LinearSweep | SignalKind::LogSweep => format!(
+                "{:?} {:.0}..{:.0} Hz in {:.1} s",
+                self.kind, self.sweep_start_hz, self.sweep_end_hz, self.sweep_s
+            ),
+            _ => format!("{:?}", self.kind),
+        }
+    }
+}
+
+const MULTITONE_TONES: usize = 10;
+const MULTITONE_MIN_HZ: f64 = 50.0;
+const MULTITONE_MAX_HZ: f64 = 10000.0;
+
+pub struct SignalGenerator {
+    pub params: GeneratorParams,
+    sample_rate: f64,
+
+    phase: f64,
+    multitone_phases: Vec<f64>,
+    sweep_t: f64,
+    impulse_countdown: f64,
+    rng: StdRng,
+    pink: [f64; 7],
+}
+
+impl SignalGenerator {
+    pub fn new(params: GeneratorParams, sample_rate: u32) -> Self {
+        // Schroeder phases keep the crest factor of the multitone low
+        let n = MULTITONE_TONES as f64;
+        let multitone_phases = (0..MULTITONE_TONES)
+            .map(|k| -std::f64::consts::PI * (k * k) as f64 / n)
+            .collect();
+        Self {
+            params,
+            sample_rate: sample_rate as f64,
+            phase: 0.0,
+            multitone_phases,
+            sweep_t: 0.0,
+            impulse_countdown: 0.0,
+            rng: StdRng::seed_from_u64(0x5b5df7),
+            pink: [0.0; 7],
+        }
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate as f64;
+    }
+
+    pub fn amplitude(&self) -> f64 {
+        10f64.powf(self.params.level_db as f64 / 20.0)
+    }
+
+    /// Fills interleaved `out`, the same signal on every channel
+    pub fn fill(&mut self, out: &mut [f32], channels: usize) {
+        let channels = channels.max(1);
+        for frame in out.chunks_mut(channels) {
+            let v = self.next_sample();
+            for s in frame.iter_mut() {
+                *s = v;
+            }
+        }
+    }
+
+    pub fn next_sample(&mut self) -> f32 {
+        const TAU: f64 = std::f64::consts::TAU;
+        let amplitude = self.amplitude();
+        let sr = self.sample_rate;
+        let p = &self.params;
+
+        let v = match p.kind {
+            SignalKind::Sine => {
+                self.phase = (self.phase + TAU * p.frequency_hz as f64 / sr) % TAU;
+                self.phase.sin()
+            }
+            SignalKind::Multitone => {
+                let ratio = MULTITONE_MAX_HZ / MULTITONE_MIN_HZ;
+                let last = (MULTITONE_TONES - 1) as f64;
+                let mut sum = 0.0;
+                for (k, phase) in self.multitone_phases.iter_mut().enumerate() {
+                    let f = MULTITONE_MIN_HZ * ratio.powf(k as f64 / last);
+                    *phase = (*phase + TAU * f / sr) % TAU;
+                    sum += phase.sin();
+                }
+                sum / MULTITONE_TONES as f64
+            }
+            SignalKind::WhiteNoise => self.rng.gen_range(-1.0..1.0),
+            SignalKind::PinkNoise => {
+                // Paul Kellet's refined pink noise filter
+                let white: f64 = self.rng.gen_range(-1.0..1.0);
+                let b = &mut self.pink;
+                b[0] = 0.99886 * b[0] + white * 0.0555179;
+                b[1] = 0.99332 * b[1] + white * 0.0750759;
+                b[2] = 0.96900 * b[2] + white * 0.1538520;
+                b[3] = 0.86650 * b[3] + white * 0.3104856;
+                b[4] = 0.55000 * b[4] + white * 0.5329522;
+                b[5] = -0.7616 * b[5] - white * 0.0168980;
+                let pink = b[0] + b[1] + b[2] + b[3] + b[4] + b[5] + b[6] + white * 0.5362;
+                b[6] = white * 0.115926;
+                (pink * 0.11).clamp(-1.0, 1.0)
+            }
+            SignalKind::LinearSweep | SignalKind::LogSweep => {
+                let duration = (p.sweep_s as f64).max(0.01);
+                let x = self.sweep_t / duration;
+                let f0 = p.sweep_start_hz as f64;
+                let f1 = p.sweep_end_hz as f64;
+                let f = if p.kind == SignalKind::LinearSweep {
+                    f0 + (f1 - f0) * x
+                } else {
+                    f0 * (f1 / f0).powf(x)
+                };
+                self.sweep_t += 1.0 / sr;
+                if self.sweep_t >= duration {
+                    self.sweep_t = 0.0;
+                }
+                self.phase = (self.phase + TAU * f.min(sr / 2.0) / sr) % TAU;
+                self.phase.sin()
+            }
+            SignalKind::ImpulseTrain => {
+                self.impulse_countdown -= 1.0;
+                if self.impulse_countdown <= 0.0 {
+                    self.impulse_countdown += sr / (p.frequency_hz as f64).max(0.1);
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+        };
+        (v * amplitude) as f32
+    }
+}
+
+pub type SharedGenerator = Arc<Mutex<SignalGenerator>>;
+
+/// Renders `seconds` of the generator to a 16 bit WAV file
+pub fn write_wav(
+    generator: &mut SignalGenerator,
+    path: &std::path::Path,
+    sample_rate: u32,
+    seconds: f32,
+) -> Result<(), anyhow::Error> {
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate,
+        bits_per_sample: 16,
+        sample_format: hound::SampleFormat::Int,
+    };
+    generator.set_sample_rate(sample_rate);
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    let n = (seconds * sample_rate as f32) as usize;
+    for _ in 0..n {
+        let v = generator.next_sample().clamp(-1.0, 1.0);
+        writer.write_sample((v * i16::MAX as f32) as i16)?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use output::GeneratorOutput;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod output {
+    use super::SharedGenerator;
+    use super::SignalGenerator;
+    use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+    pub enum GeneratorOutput {
+        Device(cpal::Stream),
+        /// no output device, the signal went to a file
+        Wav(std::path::PathBuf),
+    }
+
+    impl GeneratorOutput {
+        /// Plays on the default output device, or falls back to a WAV file
+        pub fn start(generator: SharedGenerator, wav_path: &Option<String>) -> Option<Self> {
+            if wav_path.is_none() {
+                match start_output_stream(generator.clone()) {
+                    Ok(stream) => return Some(GeneratorOutput::Device(stream)),
+                    Err(err) => {
+                        println!("generator: no output device ({}), writing WAV", err);
+                    }
+                }
+            }
+            let path = std::path::PathBuf::from(
+                wav_path
+                    .clone()
+                    .unwrap_or("sbsdft_generator.wav".to_string()),
+            );
+            let output = GeneratorOutput::Wav(path);
+            match output.refresh(&mut generator.lock().unwrap()) {
+                true => Some(output),
+                false => None,
+            }
+        }
+
+        /// Rewrites the WAV file after the params changed, a device
+        /// picks up the new params by itself
+        pub fn refresh(&self, generator: &mut SignalGenerator) -> bool {
+            let path = match self {
+                GeneratorOutput::Device(_) => return true,
+                GeneratorOutput::Wav(path) => path,
+            };
+            match super::write_wav(generator, path, 48000, 10.0) {
+                Ok(()) => {
+                    println!("generator: wrote {}", path.display());
+                    true
+                }
+                Err(err) => {
+                    println!("generator: can't write {}: {}", path.display(), err);
+                    false
+                }
+            }
+        }
+    }
+
+    fn start_output_stream(generator: SharedGenerator) -> Result<cpal::Stream, anyhow::Error> {
+        let host = cpal::default_host();
+        let device = host
+            .default_output_device()
+            .ok_or(anyhow::anyhow!("no default output device"))?;
+        let config = device.default_output_config()?;
+        println!(
+            "generator output: {} {:?}",
+            device.name().unwrap_or_default(),
+            config
+        );
+        let channels = config.channels() as usize;
+        // the callback runs its own generator and only polls the shared
+        // params, so the audio thread never waits on the UI thread
+        let mut local = {
+            let mut shared = generator.lock().unwrap();
+            shared.set_sample_rate(config.sample_rate().0);
+            super::SignalGenerator::new(shared.params.clone(), config.sample_rate().0)
+        };
+        let mut fill = move |out: &mut [f32]| {
+            if let Ok(shared) = generator.try_lock() {
+                local.params.clone_from(&shared.params);
+            }
+            local.fill(out, channels);
+        };
+
+        let err_fn = move |err| {
+            eprintln!("an error occurred on generator stream: {}", err);
+        };
+
+        let mut scratch: Vec<f32> = Vec::new();
+        let stream = match config.sample_format() {
+            cpal::SampleFormat::F32 => device.build_output_stream(
+                &config.into(),
+                move |data: &mut [f32], _: &_| {
+                    fill(data);
+                },
+                err_fn,
+            )?,
+            cpal::SampleFormat::I16 => device.build_output_stream(
+                &config.into(),
+                move |data: &mut [i16], _: &_| {
+                    scratch.resize(data.len(), 0.0);
+                    fill(&mut scratch);
+                    for (d, s) in data.iter_mut().zip(scratch.iter()) {
+                        *d = (s.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+                    }
+                },
+                err_fn,
+            )?,
+            cpal::SampleFormat::U16 => device.build_output_stream(
+                &config.into(),
+                move |data: &mut [u16], _: &_| {
+                    scratch.resize(data.len(), 0.0);
+                    fill(&mut scratch);
+                    for (d, s) in data.iter_mut().zip(scratch.iter()) {
+                        *d = ((s.clamp(-1.0, 1.0) + 1.0) * 32767.5) as u16;
+                    }
+                },
+                err_fn,
+            )?,
+        };
+        stream.play()?;
+        Ok(stream)
+    }
+}
+
+#[cfg(test)]
+fn render(params: GeneratorParams, sample_rate: u32, seconds: f64) -> Vec<f32> {
+    let mut generator = SignalGenerator::new(params, sample_rate);
+    let mut out = vec![0.0; (seconds * sample_rate as f64) as usize];
+    generator.fill(&mut out, 1);
+    out
+}
+
+/// Frequency from the upward zero crossings of `samples`
+#[cfg(test)]
+fn crossing_rate(samples: &[f32], sample_rate: u32) -> f64 {
+    let crossings: Vec<usize> = (1..samples.len())
+        .filter(|&i| samples[i - 1] < 0.0 && samples[i] >= 0.0)
+        .collect();
+    let (first, last) = (crossings[0], crossings[crossings.len() - 1]);
+    (crossings.len() - 1) as f64 * sample_rate as f64 / (last - first) as f64
+}
+
+#[test]
+fn test_sine() {
+    let mut params = GeneratorParams::new();
+    params.frequency_hz = 440.0;
+    params.level_db = -6.0;
+    let out = render(params, 48000, 1.0);
+
+    let f = crossing_rate(&out, 48000);
+    assert!((f - 440.0).abs() < 0.5, "{}", f);
+    let peak = out.iter().fold(0f32, |m, v| m.max(v.abs())) as f64;
+    let expected = 10f64.powf(-6.0 / 20.0);
+    assert!((peak - expected).abs() < 1e-3, "{} {}", peak, expected);
+}
+
+#[test]
+fn test_sweeps() {
+    for kind in [SignalKind::LinearSweep, SignalKind::LogSweep] {
+        let mut params = GeneratorParams::new();
+        params.kind = kind;
+        params.sweep_start_hz = 200.0;
+        params.sweep_end_hz = 2000.0;
+        params.sweep_s = 10.0;
+        let out = render(params, 48000, 10.0);
+
+        // 50 ms at either end, the frequency barely moves in that time
+        let n = 2400;
+        let start = crossing_rate(&out[..n], 48000);
+        let end = crossing_rate(&out[out.len() - n..], 48000);
+        assert!((start - 200.0).abs() < 200.0 * 0.03, "{:?} {}", kind, start);
+        assert!((end - 2000.0).abs() < 2000.0 * 0.03, "{:?} {}", kind, end);
+    }
+}
@@ -0,0 +1,458 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::IntoDeserializer;
+use serde::Deserialize;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+use super::kikod::Kikod;
+
+// Keys, the mouse wheel and drags mapped to named actions. The F1 help
+// and the key hints in the overlay are made from the same table, and the
+// settings file can rebind or unbind any of it:
+//   "key_bindings": [{"input": "Ctrl+S", "action": "save_settings"},
+//                    {"input": "Shift+Wheel", "action": "resolution_up"},
+//                    {"input": "F2", "action": null}]
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    ToggleHelp,
+    ZoomIn,
+    ZoomOut,
+    /// in for a positive amount
+    Zoom,
+    ResolutionUp,
+    ResolutionDown,
+    CycleResolutionPolicy,
+    PanLeft,
+    PanRight,
+    /// to lower frequencies for a positive amount
+    Pan,
+    Pause,
+    CycleGraph,
+    ToggleLogScale,
+    ToggleSubtractionPeaks,
+    ToggleColorize,
+    CycleMethod,
+    CycleWindow,
+    FewerSubdivisions,
+    MoreSubdivisions,
+    CollectFrequencyDown,
+    CollectFrequencyUp,
+    TogglePeakHold,
+    ToggleMaxHold,
+    ToggleMinHold,
+    CycleAverage,
+    ToggleNoiseFloor,
+    ToggleSubtractNoiseFloor,
+    ToggleTransfer,
+    SwapTransferChannels,
+    ToggleDistortion,
+    ToggleGenerator,
+    CycleGeneratorSignal,
+    GeneratorFrequencyDown,
+    GeneratorFrequencyUp,
+    GeneratorLevelDown,
+    GeneratorLevelUp,
+    CycleOverrunPolicy,
+    ToggleRecording,
+    ReplayPause,
+    ReplayRestart,
+    ReplayBack,
+    ReplayForward,
+    ReplayFaster,
+    ReplaySlower,
+    CycleInputDevice,
+    Preset(u8),
+    SaveSettings,
+    Snapshot,
+}
+
+impl Action {
+    pub fn describe(&self) -> String {
+        match self {
+            Action::ToggleHelp => "show or hide this help".to_string(),
+            Action::ZoomIn => "zoom in".to_string(),
+            Action::ZoomOut => "zoom out".to_string(),
+            Action::Zoom => "zoom in or out".to_string(),
+            Action::ResolutionUp => "longer windows, finer resolution".to_string(),
+            Action::ResolutionDown => "shorter windows, faster response".to_string(),
+            Action::CycleResolutionPolicy => "next resolution policy".to_string(),
+            Action::PanLeft => "move to lower frequencies".to_string(),
+            Action::PanRight => "move to higher frequencies".to_string(),
+            Action::Pan => "move along the frequencies".to_string(),
+            Action::Pause => "pause the analyzer".to_string(),
+            Action::CycleGraph => "next graph type".to_string(),
+            Action::ToggleLogScale => "logarithmic y scale".to_string(),
+            Action::ToggleSubtractionPeaks => "subtraction peaks".to_string(),
+            Action::ToggleColorize => "colorize".to_string(),
+            Action::CycleMethod => "next spectrum method".to_string(),
+            Action::CycleWindow => "next window type".to_string(),
+            Action::FewerSubdivisions => "fewer window subdivisions".to_string(),
+            Action::MoreSubdivisions => "more window subdivisions".to_string(),
+            Action::CollectFrequencyDown => "less motion blur".to_string(),
+            Action::CollectFrequencyUp => "more motion blur".to_string(),
+            Action::TogglePeakHold => "peak-hold trace".to_string(),
+            Action::ToggleMaxHold => "max-hold trace".to_string(),
+            Action::ToggleMinHold => "min-hold trace".to_string(),
+            Action::CycleAverage => "next average trace".to_string(),
+            Action::ToggleNoiseFloor => "show the noise floor".to_string(),
+            Action::ToggleSubtractNoiseFloor => "subtract the noise floor".to_string(),
+            Action::ToggleTransfer => "transfer function".to_string(),
+            Action::SwapTransferChannels => "swap transfer function channels".to_string(),
+            Action::ToggleDistortion => "distortion analysis".to_string(),
+            Action::ToggleGenerator => "test signal generator".to_string(),
+            Action::CycleGeneratorSignal => "next generator signal".to_string(),
+            Action::GeneratorFrequencyDown => "generator a third octave down".to_string(),
+            Action::GeneratorFrequencyUp => "generator a third octave up".to_string(),
+            Action::GeneratorLevelDown => "generator 3 dB quieter".to_string(),
+            Action::GeneratorLevelUp => "generator 3 dB louder".to_string(),
+            Action::CycleOverrunPolicy => "next overrun policy".to_string(),
+            Action::ToggleRecording => "record spectra".to_string(),
+            Action::ReplayPause => "pause the replay".to_string(),
+            Action::ReplayRestart => "replay from the start".to_string(),
+            Action::ReplayBack => "replay 5 s back".to_string(),
+            Action::ReplayForward => "replay 5 s forward".to_string(),
+            Action::ReplayFaster => "replay faster".to_string(),
+            Action::ReplaySlower => "replay slower".to_string(),
+            Action::CycleInputDevice => "next input device".to_string(),
+            Action::Preset(n) => format!("preset {}", n),
+            Action::SaveSettings => "save the settings".to_string(),
+            Action::Snapshot => "save a PNG snapshot".to_string(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub alt: bool,
+    pub shift: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Input {
+    Key(Kikod),
+    /// both directions, the action gets the signed amount
+    Wheel,
+    /// dragging with the left or right button held
+    Drag {
+        left: bool,
+    },
+}
+
+/// An input with the modifiers held, "Ctrl+Shift+S", "Wheel", "LeftDrag"
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Chord {
+    pub modifiers: Modifiers,
+    pub input: Input,
+}
+
+impl Chord {
+    pub fn new(modifiers: Modifiers, input: Input) -> Self {
+        Self { modifiers, input }
+    }
+
+    pub fn key(key: Kikod) -> Self {
+        Self::new(Modifiers::default(), Input::Key(key))
+    }
+}
+
+impl fmt::Display for Chord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let m = &self.modifiers;
+        for (held, name) in [(m.ctrl, "Ctrl+"), (m.alt, "Alt+"), (m.shift, "Shift+")] {
+            if held {
+                write!(f, "{}", name)?;
+            }
+        }
+        match self.input {
+            Input::Key(key) => {
+                let name = format!("{:?}", key);
+                // Key1 is just 1
+                match name.strip_prefix("Key") {
+                    Some(digit) if digit.len() == 1 => write!(f, "{}", digit),
+                    _ => write!(f, "{}", name),
+                }
+            }
+            Input::Wheel => write!(f, "Wheel"),
+            Input::Drag { left: true } => write!(f, "LeftDrag"),
+            Input::Drag { left: false } => write!(f, "RightDrag"),
+        }
+    }
+}
+
+impl FromStr for Chord {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        let mut modifiers = Modifiers::default();
+        let mut parts: Vec<&str> = text.split('+').map(|p| p.trim()).collect();
+        let last = parts.pop().unwrap_or("");
+        for part in parts {
+            match part.to_lowercase().as_str() {
+                "ctrl" | "control" => modifiers.ctrl = true,
+                "alt" => modifiers.alt = true,
+                "shift" => modifiers.shift = true,
+                _ => return Err(format!("unknown modifier '{}' in '{}'", part, text)),
+            }
+        }
+        let input = match last {
+            "Wheel" => Input::Wheel,
+            "LeftDrag" => Input::Drag { left: true },
+            "RightDrag" => Input::Drag { left: false },
+            _ => {
+                // 1 for Key1, a for A
+                let name = if last.len() == 1 {
+                    let c = last.chars().next().unwrap();
+                    if c.is_ascii_digit() {
+                        format!("Key{}", c)
+                    } else {
+                        last.to_uppercase()
+                    }
+                } else {
+                    last.to_string()
+                };
+                let de = name.as_str().into_deserializer();
+                let key = Kikod::deserialize(de)
+                    .map_err(|_: serde::de::value::Error| format!("unknown key '{}'", last))?;
+                Input::Key(key)
+            }
+        };
+        Ok(Self::new(modifiers, input))
+    }
+}
+
+impl TryFrom<String> for Chord {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, String> {
+        text.parse()
+    }
+}
+
+impl From<Chord> for String {
+    fn from(chord: Chord) -> Self {
+        chord.to_string()
+    }
+}
+
+/// A user override, no action unbinds the input
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Binding {
+    pub input: Chord,
+    pub action: Option<Action>,
+}
+
+pub struct Keymap {
+    /// in the order the help lists them
+    bindings: Vec<(Chord, Action)>,
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        use Kikod::*;
+        let keys = [
+            (F1, Action::ToggleHelp),
+            (Plus, Action::ZoomIn),
+            (Equals, Action::ZoomIn),
+            (NumpadAdd, Action::ZoomIn),
+            (Minus, Action::ZoomOut),
+            (NumpadSubtract, Action::ZoomOut),
+            (Left, Action::PanLeft),
+            (Right, Action::PanRight),
+            (Up, Action::ResolutionUp),
+            (Down, Action::ResolutionDown),
+            (Q, Action::CycleResolutionPolicy),
+            (Space, Action::Pause),
+            (P, Action::CycleGraph),
+            (L, Action::ToggleLogScale),
+            (S, Action::ToggleSubtractionPeaks),
+            (C, Action::ToggleColorize),
+            (M, Action::CycleMethod),
+            (O, Action::CycleWindow),
+            (B, Action::FewerSubdivisions),
+            (N, Action::MoreSubdivisions),
+            (Z, Action::CollectFrequencyDown),
+            (X, Action::CollectFrequencyUp),
+            (H, Action::TogglePeakHold),
+            (K, Action::ToggleMaxHold),
+            (J, Action::ToggleMinHold),
+            (A, Action::CycleAverage),
+            (F, Action::ToggleNoiseFloor),
+            (D, Action::ToggleSubtractNoiseFloor),
+            (T, Action::ToggleTransfer),
+            (Y, Action::SwapTransferChannels),
+            (E, Action::ToggleDistortion),
+            (G, Action::ToggleGenerator),
+            (V, Action::CycleGeneratorSignal),
+            (Comma, Action::GeneratorFrequencyDown),
+            (Period, Action::GeneratorFrequencyUp),
+            (LBracket, Action::GeneratorLevelDown),
+            (RBracket, Action::GeneratorLevelUp),
+            (U, Action::CycleOverrunPolicy),
+            (W, Action::ToggleRecording),
+            (End, Action::ReplayPause),
+            (Home, Action::ReplayRestart),
+            (PageUp, Action::ReplayBack),
+            (PageDown, Action::ReplayForward),
+            (Insert, Action::ReplayFaster),
+            (Delete, Action::ReplaySlower),
+            (I, Action::CycleInputDevice),
+            (Key1, Action::Preset(1)),
+            (Key2, Action::Preset(2)),
+            (Key3, Action::Preset(3)),
+            (Key4, Action::Preset(4)),
+            (Key5, Action::Preset(5)),
+            (Key6, Action::Preset(6)),
+            (Key7, Action::Preset(7)),
+            (Key8, Action::Preset(8)),
+            (Key9, Action::Preset(9)),
+            (F2, Action::SaveSettings),
+            (F12, Action::Snapshot),
+        ];
+        let mut bindings: Vec<(Chord, Action)> = keys
+            .iter()
+            .map(|&(key, action)| (Chord::key(key), action))
+            .collect();
+        let none = Modifiers::default();
+        bindings.push((Chord::new(none, Input::Wheel), Action::Zoom));
+        bindings.push((Chord::new(none, Input::Drag { left: true }), Action::Pan));
+        Self { bindings }
+    }
+
+    /// The defaults with `overrides` applied in order
+    pub fn with_overrides(overrides: &[Binding]) -> Self {
+        let mut keymap = Self::new();
+        for binding in overrides {
+            let found = keymap
+                .bindings
+                .iter()
+                .position(|(c, _)| *c == binding.input);
+            match (found, binding.action) {
+                (Some(i), Some(action)) => keymap.bindings[i].1 = action,
+                (Some(i), None) => {
+                    keymap.bindings.remove(i);
+                }
+                (None, Some(action)) => keymap.bindings.push((binding.input, action)),
+                (None, None) => {}
+            }
+        }
+        keymap
+    }
+
+    /// Without a binding for Shift, the input without it: Shift+Equals types a +
+    pub fn lookup(&self, chord: &Chord) -> Option<Action> {
+        let find = |chord: &Chord| {
+            self.bindings
+                .iter()
+                .find(|(c, _)| c == chord)
+                .map(|(_, action)| *action)
+        };
+        find(chord).or_else(|| {
+            let mut unshifted = *chord;
+            unshifted.modifiers.shift = false;
+            (unshifted != *chord).then(|| find(&unshifted)).flatten()
+        })
+    }
+
+    /// "Q/Up/Down" for the inputs bound to `actions`, for the overlay
+    pub fn keys(&self, actions: &[Action]) -> String {
+        let mut names: Vec<String> = Vec::new();
+        for action in actions {
+            for (chord, _) in self.bindings.iter().filter(|(_, a)| a == action) {
+                names.push(chord.to_string());
+            }
+        }
+        names.join("/")
+    }
+
+    /// One line per action, with every input bound to it
+    pub fn help_lines(&self) -> Vec<String> {
+        let mut actions: Vec<Action> = Vec::new();
+        for (_, action) in &self.bindings {
+            if !actions.contains(action) {
+                actions.push(*action);
+            }
+        }
+        actions
+            .iter()
+            .map(|action| {
+                let keys = self.keys(&[*action]).replace('/', ", ");
+                format!("{:>14}  {}", keys, action.describe())
+            })
+            .collect()
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_keymap() {
+    let keymap = Keymap::new();
+    assert_eq!(
+        keymap.lookup(&Chord::key(Kikod::M)),
+        Some(Action::CycleMethod)
+    );
+    assert_eq!(
+        keymap.lookup(&"3".parse().unwrap()),
+        Some(Action::Preset(3))
+    );
+    assert_eq!(keymap.lookup(&"Ctrl+M".parse().unwrap()), None);
+    assert_eq!(
+        keymap.lookup(&"Shift+Equals".parse().unwrap()),
+        Some(Action::ZoomIn)
+    );
+    assert_eq!(keymap.keys(&[Action::ZoomIn]), "Plus/Equals/NumpadAdd");
+    assert_eq!(keymap.keys(&[Action::Zoom, Action::Pan]), "Wheel/LeftDrag");
+    assert!(keymap
+        .help_lines()
+        .iter()
+        .any(|line| line.ends_with("Wheel  zoom in or out")));
+
+    let chord: Chord = "ctrl+shift+s".parse().unwrap();
+    assert_eq!(chord.to_string(), "Ctrl+Shift+S");
+    assert!("Hyper+S".parse::<Chord>().is_err());
+    assert!("Shift+Foo".parse::<Chord>().is_err());
+
+    let text = r#"[{"input": "Ctrl+S", "action": "save_settings"},
+        {"input": "Shift+Wheel", "action": "resolution_up"},
+        {"input": "M", "action": {"preset": 2}},
+        {"input": "F2", "action": null}]"#;
+    let overrides: Vec<Binding> = serde_json::from_str(text).unwrap();
+    let keymap = Keymap::with_overrides(&overrides);
+    let shift = Modifiers {
+        shift: true,
+        ..Modifiers::default()
+    };
+    assert_eq!(
+        keymap.lookup(&Chord::new(shift, Input::Wheel)),
+        Some(Action::ResolutionUp)
+    );
+    assert_eq!(
+        keymap.lookup(&Chord::key(Kikod::M)),
+        Some(Action::Preset(2))
+    );
+    assert_eq!(keymap.lookup(&Chord::key(Kikod::F2)), None);
+    assert_eq!(keymap.keys(&[Action::SaveSettings]), "Ctrl+S");
+    // the method has no key left, so its help line is gone too
+    let help = keymap.help_lines();
+    assert!(help
+        .iter()
+        .all(|line| !line.ends_with("next spectrum method")));
+    assert!(help
+        .iter()
+        .any(|line| line.ends_with("Ctrl+S  save the settings")));
+    assert_eq!(
+        serde_json::to_string(&overrides[0]).unwrap(),
+        r#"{"input":"Ctrl+S","action":"save_settings"}"#
+    );
+}
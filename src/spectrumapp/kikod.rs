@@ -1,3 +1,6 @@
+use serde_derive::Deserialize;
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
 pub enum Kikod {
     /// The '1' key over the letters.
     Key1,
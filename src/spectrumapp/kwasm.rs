@@ -15,6 +15,20 @@ pub fn fix_webgl_color(wgsl_shader: &str) -> String {
     wgsl_shader.into()
 }
 
+/// Wall-clock time, milliseconds since the Unix epoch
+#[cfg(not(target_arch = "wasm32"))]
+pub fn now_ms() -> f64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs_f64() * 1000.0)
+        .unwrap_or(0.0)
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn now_ms() -> f64 {
+    js_sys::Date::now()
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 pub fn spawn_once<F, T>(_tname: &'static str, f: F) -> ()
 where
@@ -0,0 +1,658 @@
+use std::net::UdpSocket;
+use std::net::{SocketAddr, ToSocketAddrs};
+
+use super::recorder::BinFrequencies;
+use super::sbswdft::Collected;
+use super::sbswdft::SpectrumConfig;
+
+// Drives addressable LED strips, colorchord style, from the first channel.
+// Each LED gets the colors of the bins (already colorized) or peaks that fall
+// on it, weighted by power in gamma space like colorize_spectrum, and the
+// brightness of the loudest of them. Brightness is relative to the rolling
+// gain, `LED_FULL_SCALE` is about the top of the spectrum view.
+
+/// relative value at full brightness
+const LED_FULL_SCALE: f64 = 100.0;
+/// RGB channels in a DMX universe, 170 LEDs
+const DMX_CHANNELS: usize = 510;
+/// LEDs in a WLED DNRGB packet
+const WLED_MAX_LEDS: usize = 489;
+/// WLED returns to its own effects this many seconds after the last frame
+const WLED_TIMEOUT_S: u8 = 2;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum LedProtocol {
+    /// E1.31 / sACN, UDP port 5568
+    E131,
+    /// Art-Net ArtDmx, UDP port 6454
+    Artnet,
+    /// WLED UDP realtime (DNRGB), UDP port 21324
+    Wled,
+}
+
+impl LedProtocol {
+    pub fn default_port(&self) -> u16 {
+        match self {
+            LedProtocol::E131 => 5568,
+            LedProtocol::Artnet => 6454,
+            LedProtocol::Wled => 21324,
+        }
+    }
+
+    /// sACN universes count from 1, Art-Net from 0
+    pub fn default_universe(&self) -> u16 {
+        match self {
+            LedProtocol::E131 => 1,
+            LedProtocol::Artnet | LedProtocol::Wled => 0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum LedLayout {
+    /// low to high frequencies along the strip
+    Linear,
+    /// one octave around the strip, all octaves of a note on the same LED
+    Folded,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum LedSource {
+    /// the colorized spectrum bins
+    Spectrum,
+    /// the peaks, spread over `spread` LEDs each
+    Peaks,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum LedCurve {
+    Linear,
+    /// level ^ gamma
+    Gamma,
+    /// `db_range` dB below full scale to full scale
+    Db,
+}
+
+#[derive(Clone)]
+pub struct LedConfig {
+    /// host or host:port, the protocol's port if missing
+    pub target: String,
+    pub protocol: LedProtocol,
+    pub num_leds: usize,
+    pub layout: LedLayout,
+    pub source: LedSource,
+    pub curve: LedCurve,
+    pub gamma: f32,
+    pub db_range: f32,
+    /// 0..1, scales every LED
+    pub brightness: f32,
+    /// first universe, the protocol's default if None
+    pub universe: Option<u16>,
+    /// peak width in LEDs
+    pub spread: f32,
+    pub rate_hz: f32,
+}
+
+impl LedConfig {
+    pub fn new() -> Self {
+        Self {
+            target: "127.0.0.1".to_string(),
+            protocol: LedProtocol::E131,
+            num_leds: 60,
+            layout: LedLayout::Linear,
+            source: LedSource::Spectrum,
+            curve: LedCurve::Gamma,
+            gamma: 2.2,
+            db_range: 30.0,
+            brightness: 1.0,
+            universe: None,
+            spread: 1.5,
+            rate_hz: 40.0,
+        }
+    }
+
+    pub fn first_universe(&self) -> u16 {
+        self.universe
+            .unwrap_or_else(|| self.protocol.default_universe())
+    }
+
+    /// 0..1 brightness of a relative value
+    pub fn level(&self, relative: f64) -> f32 {
+        let level = (relative / LED_FULL_SCALE) as f32;
+        let level = match self.curve {
+            LedCurve::Linear => level,
+            LedCurve::Gamma => level.max(0.0).powf(self.gamma),
+            LedCurve::Db => 1.0 + 20.0 * (level + 1e-12).log10() / self.db_range,
+        };
+        level.clamp(0.0, 1.0) * self.brightness.clamp(0.0, 1.0)
+    }
+}
+
+/// One LED's accumulated colors, in gamma space like colorize_spectrum
+#[derive(Clone, Copy)]
+struct LedSum {
+    rgb: [f64; 3],
+    weight: f64,
+    value: f64,
+}
+
+impl LedSum {
+    const ZERO: LedSum = LedSum {
+        rgb: [0.0; 3],
+        weight: 0.0,
+        value: 0.0,
+    };
+
+    fn add(&mut self, rgba: u32, value: f64) {
+        let weight = value * value;
+        for (c, sum) in self.rgb.iter_mut().enumerate() {
+            let channel = ((rgba >> (8 * c)) & 0xff) as f64;
+            *sum += weight * channel * channel;
+        }
+        self.weight += weight;
+        self.value = self.value.max(value);
+    }
+
+    fn rgb(&self, level: f32) -> [u8; 3] {
+        if self.weight <= 0.0 {
+            return [0; 3];
+        }
+        self.rgb.map(|sum| {
+            ((sum / self.weight).sqrt() * level as f64)
+                .round()
+                .min(255.0) as u8
+        })
+    }
+}
+
+/// Strip position of a frequency, in LEDs
+fn led_position(layout: LedLayout, num_leds: usize, fraction: f32, freq_hz: f32) -> f32 {
+    match layout {
+        LedLayout::Linear => fraction * num_leds as f32,
+        // the octave starts at A like hz2color
+        LedLayout::Folded => (freq_hz / 440.0).log2().rem_euclid(1.0) * num_leds as f32,
+    }
+}
+
+/// The LED colors of a collected frame
+pub fn map_leds(
+    config: &LedConfig,
+    frequencies: &[f32],
+    collected: &Collected,
+    gain: f64,
+) -> Vec<[u8; 3]> {
+    let num_leds = config.num_leds;
+    let n = frequencies.len().min(collected.spectrum.len());
+    let mut sums = vec![LedSum::ZERO; num_leds];
+    if num_leds == 0 || n == 0 {
+        return Vec::new();
+    }
+
+    match config.source {
+        LedSource::Spectrum => {
+            let mut hit = vec![false; num_leds];
+            for (i, s) in collected.spectrum[..n].iter().enumerate() {
+                let fraction = (i as f32 + 0.5) / n as f32;
+                let pos = led_position(config.layout, num_leds, fraction, frequencies[i]);
+                let led = (pos as usize).min(num_leds - 1);
+                sums[led].add(s.color.rgba, s.value * gain);
+                hit[led] = true;
+            }
+            // more LEDs than bins, fill the gaps from the bin below
+            for led in 1..num_leds {
+                if !hit[led] {
+                    sums[led] = sums[led - 1];
+                    hit[led] = hit[led - 1];
+                }
+            }
+        }
+        LedSource::Peaks => {
+            let spread = config.spread.max(0.01);
+            for peak in collected.peaks.iter().flatten() {
+                let Some(i) = usize::try_from(peak.probe_index).ok().filter(|i| *i < n) else {
+                    continue;
+                };
+                let fraction = (i as f32 + 0.5) / n as f32;
+                let pos = led_position(config.layout, num_leds, fraction, frequencies[i]);
+                let reach = spread.ceil() as i64;
+                for offset in -reach..=reach {
+                    let led = pos.floor() as i64 + offset;
+                    let led = match config.layout {
+                        LedLayout::Linear if led < 0 || led >= num_leds as i64 => continue,
+                        LedLayout::Linear => led as usize,
+                        LedLayout::Folded => led.rem_euclid(num_leds as i64) as usize,
+                    };
+                    // same falloff as weighted_color
+                    let distance = (led as f32 + 0.5 - pos).abs();
+                    let distance = match config.layout {
+                        LedLayout::Folded => distance.min(num_leds as f32 - distance),
+                        LedLayout::Linear => distance,
+                    };
+                    if distance < spread {
+                        let weight = (1.0 - distance / spread).powi(4) as f64;
+                        sums[led].add(peak.color.rgba, weight * peak.value * gain);
+                    }
+                }
+            }
+        }
+    }
+
+    sums.iter().map(|s| s.rgb(config.level(s.value))).collect()
+}
+
+fn push_u16(out: &mut Vec<u8>, v: u16) {
+    out.extend_from_slice(&v.to_be_bytes());
+}
+
+/// An E1.31 data packet for one universe, `data` up to 512 channels
+pub fn encode_e131(cid: &[u8; 16], universe: u16, sequence: u8, data: &[u8]) -> Vec<u8> {
+    let count = data.len() + 1;
+    let mut out = Vec::with_capacity(126 + data.len());
+    // root layer
+    push_u16(&mut out, 0x0010);
+    push_u16(&mut out, 0x0000);
+    out.extend_from_slice(b"ASC-E1.17\0\0\0");
+    push_u16(&mut out, 0x7000 | (109 + count) as u16);
+    out.extend_from_slice(&4u32.to_be_bytes());
+    out.extend_from_slice(cid);
+    // framing layer
+    push_u16(&mut out, 0x7000 | (87 + count) as u16);
+    out.extend_from_slice(&2u32.to_be_bytes());
+    let mut name = [0u8; 64];
+    name[..6].copy_from_slice(b"sbsdft");
+    out.extend_from_slice(&name);
+    out.push(100); // priority
+    push_u16(&mut out, 0); // no synchronization
+    out.push(sequence);
+    out.push(0); // options
+    push_u16(&mut out, universe);
+    // DMP layer
+    push_u16(&mut out, 0x7000 | (10 + count) as u16);
+    out.push(0x02);
+    out.push(0xa1);
+    push_u16(&mut out, 0x0000);
+    push_u16(&mut out, 0x0001);
+    push_u16(&mut out, count as u16);
+    out.push(0); // DMX start code
+    out.extend_from_slice(data);
+    out
+}
+
+/// An ArtDmx packet for one universe, `data` up to 512 channels
+pub fn encode_artnet(universe: u16, sequence: u8, data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(18 + data.len() + 1);
+    out.extend_from_slice(b"Art-Net\0");
+    out.extend_from_slice(&0x5000u16.to_le_bytes());
+    push_u16(&mut out, 14); // protocol version
+    out.push(sequence);
+    out.push(0); // physical port
+    out.extend_from_slice(&universe.to_le_bytes());
+    // the length must be even
+    let len = data.len() + data.len() % 2;
+    push_u16(&mut out, len as u16);
+    out.extend_from_slice(data);
+    out.resize(18 + len, 0);
+    out
+}
+
+/// A WLED DNRGB packet, LEDs from `start`
+pub fn encode_wled(start: u16, leds: &[[u8; 3]]) -> Vec<u8> {
+    let mut out = vec![4, WLED_TIMEOUT_S];
+    push_u16(&mut out, start);
+    out.extend(leds.iter().flatten());
+    out
+}
+
+/// The packets of one frame of LEDs
+pub fn encode_frame(
+    protocol: LedProtocol,
+    cid: &[u8; 16],
+    first_universe: u16,
+    sequence: u8,
+    leds: &[[u8; 3]],
+) -> Vec<Vec<u8>> {
+    match protocol {
+        LedProtocol::Wled => leds
+            .chunks(WLED_MAX_LEDS)
+            .enumerate()
+            .map(|(i, chunk)| encode_wled((i * WLED_MAX_LEDS) as u16, chunk))
+            .collect(),
+        LedProtocol::E131 | LedProtocol::Artnet => {
+            let data: Vec<u8> = leds.iter().flatten().copied().collect();
+            data.chunks(DMX_CHANNELS)
+                .enumerate()
+                .map(|(i, chunk)| {
+                    let universe = first_universe.wrapping_add(i as u16);
+                    match protocol {
+                        LedProtocol::E131 => encode_e131(cid, universe, sequence, chunk),
+                        _ => encode_artnet(universe, sequence, chunk),
+                    }
+                })
+                .collect()
+        }
+    }
+}
+
+/// A received LED packet, see `LedReceiver`
+#[derive(Debug, PartialEq)]
+pub struct LedPacket {
+    pub protocol: LedProtocol,
+    /// the universe, or the first LED for WLED
+    pub address: u16,
+    pub leds: Vec<[u8; 3]>,
+}
+
+fn rgb_triplets(data: &[u8]) -> Vec<[u8; 3]> {
+    data.chunks_exact(3).map(|c| [c[0], c[1], c[2]]).collect()
+}
+
+/// Decodes the packets `encode_frame` makes, None for anything else
+pub fn decode_packet(packet: &[u8]) -> Option<LedPacket> {
+    let u16_at = |at: usize| Some(u16::from_be_bytes(packet.get(at..at + 2)?.try_into().ok()?));
+    if packet.get(4..16) == Some(b"ASC-E1.17\0\0\0") {
+        let count = u16_at(123)? as usize;
+        let data = packet.get(126..125 + count)?;
+        return Some(LedPacket {
+            protocol: LedProtocol::E131,
+            address: u16_at(113)?,
+            leds: rgb_triplets(data),
+        });
+    }
+    if packet.starts_with(b"Art-Net\0") && packet.get(8..10) == Some(&[0x00, 0x50]) {
+        let len = u16_at(16)? as usize;
+        return Some(LedPacket {
+            protocol: LedProtocol::Artnet,
+            address: u16::from_le_bytes(packet.get(14..16)?.try_into().ok()?),
+            leds: rgb_triplets(packet.get(18..18 + len)?),
+        });
+    }
+    match packet.first()? {
+        // DRGB
+        2 => Some(LedPacket {
+            protocol: LedProtocol::Wled,
+            address: 0,
+            leds: rgb_triplets(packet.get(2..)?),
+        }),
+        // DNRGB
+        4 => Some(LedPacket {
+            protocol: LedProtocol::Wled,
+            address: u16_at(2)?,
+            leds: rgb_triplets(packet.get(4..)?),
+        }),
+        _ => None,
+    }
+}
+
+/// Stand-in for a controller, for tests and --led-listen
+pub struct LedReceiver {
+    pub socket: UdpSocket,
+}
+
+impl LedReceiver {
+    pub fn bind(addr: &str) -> std::io::Result<Self> {
+        Ok(Self {
+            socket: UdpSocket::bind(addr)?,
+        })
+    }
+
+    /// The next packet that decodes
+    pub fn recv(&self) -> std::io::Result<LedPacket> {
+        let mut buf = [0u8; 2048];
+        loop {
+            let len = self.socket.recv(&mut buf)?;
+            if let Some(packet) = decode_packet(&buf[..len]) {
+                return Ok(packet);
+            }
+        }
+    }
+}
+
+/// Prints what arrives as a row of colored blocks per packet, until killed
+pub fn run_listen(addr: &str) -> std::io::Result<()> {
+    let receiver = LedReceiver::bind(addr)?;
+    println!("led: listening on {}", receiver.socket.local_addr()?);
+    loop {
+        let packet = receiver.recv()?;
+        let blocks: String = packet
+            .leds
+            .iter()
+            .map(|[r, g, b]| format!("\x1b[38;2;{};{};{}m\u{2588}", r, g, b))
+            .collect();
+        println!(
+            "{:?} {:5} {}\x1b[0m",
+            packet.protocol, packet.address, blocks
+        );
+    }
+}
+
+pub struct LedSender {
+    pub config: LedConfig,
+    socket: UdpSocket,
+    /// sACN source id
+    cid: [u8; 16],
+    bins: BinFrequencies,
+    sequence: u8,
+    last_send_s: f64,
+    pub leds: Vec<[u8; 3]>,
+    pub frames: usize,
+}
+
+impl LedSender {
+    pub fn new(config: LedConfig) -> std::io::Result<Self> {
+        let target: SocketAddr = match config.target.to_socket_addrs() {
+            Ok(mut addrs) => addrs.next(),
+            // no port given
+            Err(_) => (config.target.as_str(), config.protocol.default_port())
+                .to_socket_addrs()?
+                .next(),
+        }
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no address"))?;
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        // controllers are often reached by broadcast
+        socket.set_broadcast(true)?;
+        socket.connect(target)?;
+
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or(0);
+        let cid = (nanos ^ ((std::process::id() as u128) << 64)).to_be_bytes();
+        Ok(Self {
+            config,
+            socket,
+            cid,
+            bins: BinFrequencies::new(),
+            sequence: 0,
+            last_send_s: f64::NEG_INFINITY,
+            leds: Vec::new(),
+            frames: 0,
+        })
+    }
+
+    pub fn send_frame(&mut self, collected: &Collected, spectrum: &SpectrumConfig) {
+        let now_s = spectrum.input_seconds(collected.sample_index);
+        if now_s - self.last_send_s < 1.0 / self.config.rate_hz as f64 {
+            return;
+        }
+        self.last_send_s = now_s;
+
+        self.bins.update(spectrum);
+        let gain = 1.0 / collected.cur_rolling_gain.max(1e-12);
+        self.leds = map_leds(&self.config, &self.bins.frequencies, collected, gain);
+
+        // Art-Net skips 0, it means no sequencing
+        self.sequence = self.sequence.wrapping_add(1).max(1);
+        for packet in encode_frame(
+            self.config.protocol,
+            &self.cid,
+            self.config.first_universe(),
+            self.sequence,
+            &self.leds,
+        ) {
+            // a missing controller shows up as refused sends, keep going
+            let _ = self.socket.send(&packet);
+        }
+        self.frames += 1;
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "{:?} {} x{} {:?} {:?}, {} frames",
+            self.config.protocol,
+            self.config.target,
+            self.config.num_leds,
+            self.config.layout,
+            self.config.source,
+            self.frames
+        )
+    }
+}
+
+#[test]
+fn test_led_output() {
+    use super::sbswdft::hz2color;
+    use super::sbswdft::BinMeta;
+    use super::sbswdft::SPeak;
+    use super::sbswdft::SSample;
+    use super::sbswdft::SpectrumBinsState;
+    use super::sbswdft::WindowType;
+    use super::spectrumui::StateSnapshot;
+
+    // packets decode back to the LEDs, across universes
+    let leds: Vec<[u8; 3]> = (0..200).map(|i| [i as u8, 1, 255 - i as u8]).collect();
+    let cid = [7; 16];
+    for protocol in [LedProtocol::E131, LedProtocol::Artnet, LedProtocol::Wled] {
+        let packets = encode_frame(protocol, &cid, 3, 9, &leds);
+        let decoded: Vec<LedPacket> = packets.iter().map(|p| decode_packet(p).unwrap()).collect();
+        let joined: Vec<[u8; 3]> = decoded.iter().flat_map(|p| p.leds.clone()).collect();
+        assert_eq!(joined, leds);
+        let addresses: Vec<u16> = decoded.iter().map(|p| p.address).collect();
+        match protocol {
+            LedProtocol::Wled => assert_eq!(addresses, vec![0]),
+            _ => assert_eq!(addresses, vec![3, 4]),
+        }
+        // truncated packets don't panic
+        for packet in &packets {
+            for len in 0..packet.len() {
+                decode_packet(&packet[..len]);
+            }
+        }
+    }
+    assert!(decode_packet(&[2]).is_none());
+    assert!(decode_packet(&[4, 1, 0]).is_none());
+    let e131 = encode_e131(&cid, 1, 0, &[0; 512]);
+    assert_eq!(e131.len(), 638);
+    assert_eq!(&e131[16..18], &[0x72, 0x6e]);
+    assert_eq!(encode_artnet(0, 1, &[1, 2, 3]).len(), 18 + 4);
+
+    // 200 bins from 40 Hz, a loud 440 Hz bin and its octave
+    let spectrum = SpectrumConfig {
+        num_bins: 200,
+        collect_frequency: 60,
+        ..SpectrumConfig::new()
+    };
+    let frequencies = super::recorder::bin_frequencies(&spectrum);
+    let near = |hz: f32| {
+        (0..frequencies.len())
+            .min_by(|a, b| {
+                (frequencies[*a] - hz)
+                    .abs()
+                    .partial_cmp(&(frequencies[*b] - hz).abs())
+                    .unwrap()
+            })
+            .unwrap()
+    };
+    let (a4, a5) = (near(440.0), near(880.0));
+    let spectrum_samples = frequencies
+        .iter()
+        .enumerate()
+        .map(|(i, f)| {
+            let mut meta = BinMeta::new();
+            meta.reinit(*f as f64, 24000);
+            let value = if i == a4 || i == a5 { 100.0 } else { 0.0 };
+            SSample::new(value, &meta)
+        })
+        .collect();
+    let mut collected = Collected {
+        cur_rolling_gain: 1.0,
+        spectrum: spectrum_samples,
+        peaks: None,
+        rendered: None,
+        snapshot: StateSnapshot {
+            current_algo: SpectrumBinsState::NC,
+            window_type: WindowType::BlackmanNutall,
+            collect_every: 1,
+            collect_frequency: 60,
+            window_kernel_len: 1,
+        },
+        noise_floor: None,
+        complex: None,
+        frame_index: 0,
+        distortion: None,
+        sample_index: 0,
+        capture_ms: 0.0,
+    };
+    let color = hz2color(frequencies[a4]);
+    let rgb = [color as u8, (color >> 8) as u8, (color >> 16) as u8];
+
+    let mut config = LedConfig::new();
+    config.num_leds = 20;
+    config.curve = LedCurve::Linear;
+    let linear = map_leds(&config, &frequencies, &collected, 1.0);
+    let lit: Vec<usize> = (0..20).filter(|i| linear[*i] != [0; 3]).collect();
+    assert_eq!(lit, vec![a4 * 20 / 200, a5 * 20 / 200]);
+    assert_eq!(linear[lit[0]], rgb);
+
+    config.layout = LedLayout::Folded;
+    config.num_leds = 12;
+    let folded = map_leds(&config, &frequencies, &collected, 1.0);
+    // A4 and A5 both land next to where the octave starts, LED 0 or 11
+    let lit: Vec<usize> = (0..12).filter(|i| folded[*i] != [0; 3]).collect();
+    assert!(
+        !lit.is_empty() && lit.iter().all(|i| *i == 0 || *i == 11),
+        "{:?}",
+        lit
+    );
+
+    // peaks spread around their LED and wrap around the octave
+    config.source = LedSource::Peaks;
+    config.spread = 2.0;
+    collected.peaks = Some(vec![SPeak {
+        probe_index: a4 as i32,
+        value: 100.0,
+        color: collected.spectrum[a4].color.clone(),
+        octave: 0.0,
+        alpha: 1.0,
+    }]);
+    let peaks = map_leds(&config, &frequencies, &collected, 1.0);
+    let lit = peaks.iter().filter(|c| **c != [0; 3]).count();
+    assert!(lit >= 2 && lit <= 4, "{:?}", peaks);
+    assert!(peaks[0] != [0; 3] || peaks[11] != [0; 3]);
+
+    // quieter is dimmer on every curve, and nothing is past full scale
+    for curve in [LedCurve::Linear, LedCurve::Gamma, LedCurve::Db] {
+        config.curve = curve;
+        assert!(config.level(10.0) < config.level(50.0));
+        assert_eq!(config.level(1000.0), 1.0);
+        assert_eq!(config.level(0.0), 0.0);
+    }
+
+    // the sender's packets arrive at a receiver
+    let receiver = LedReceiver::bind("127.0.0.1:0").unwrap();
+    receiver
+        .socket
+        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .unwrap();
+    config.target = receiver.socket.local_addr().unwrap().to_string();
+    config.protocol = LedProtocol::E131;
+    config.curve = LedCurve::Linear;
+    let mut sender = LedSender::new(config).unwrap();
+    sender.send_frame(&collected, &spectrum);
+    collected.sample_index = 10;
+    // too soon for the next frame
+    sender.send_frame(&collected, &spectrum);
+    assert_eq!(sender.frames, 1);
+    let packet = receiver.recv().unwrap();
+    assert_eq!(packet.address, 1);
+    assert_eq!(packet.leds, sender.leds);
+}
@@ -2,6 +2,10 @@ mod framework;
 
 #[cfg(not(target_arch = "wasm32"))]
 pub mod adevice_cpal;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod adevice_net;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod adevice_pipe;
 // #[cfg(not(target_arch = "wasm32"))]
 // use device_cpal::SlidingCpal;
 // #[cfg(not(target_arch = "wasm32"))]
@@ -11,6 +15,9 @@ pub mod adevice_cpal;
 pub mod adevice_web;
 
 use crate::spectrumapp::spectrumui::SlidingChannel;
+use keymap::Action;
+use keymap::Chord;
+use keymap::Input;
 use kikod::Kikod;
 use std::collections::VecDeque;
 use std::sync::Mutex;
@@ -20,12 +27,30 @@ use spectrumui::SpectrumUI;
 
 pub mod appstate;
 pub mod displayparams;
+pub mod distortion;
 pub mod fontrenderer;
+pub mod generator;
+pub mod keymap;
 pub mod kikod;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod led;
 pub mod myvertex;
+pub mod noisefloor;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod osc;
+pub mod recorder;
+pub mod replay;
+pub mod resolution;
 pub mod sbswdft;
+pub mod settings;
+pub mod softraster;
+pub mod spectrogram;
 pub mod spectrumui;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod stream;
 pub mod texture;
+pub mod traces;
+pub mod transfer;
 
 #[cfg(target_arch = "wasm32")]
 pub mod pool;
@@ -50,7 +75,6 @@ use fontrenderer::FontRenderer;
 use myvertex::*;
 use sbswdft::ChannelSWDFT;
 use sbswdft::SlidingImpl;
-use sbswdft::SpectrumConfig;
 use texture::KRGBAImage;
 use texture::KRect;
 
@@ -64,6 +88,8 @@ use wgpu::util::DeviceExt;
 
 use num_enum::IntoPrimitive;
 use num_enum::TryFromPrimitive;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
@@ -305,8 +331,14 @@ struct Example {
     uniform_buf: wgpu::Buffer,
     #[allow(unused)]
     impl_params: MyParams,
+    /// save the next frame as PNG
+    snapshot_requested: bool,
+    modifiers: keymap::Modifiers,
 }
 
+/// bins one pan step moves, a key press or 30 bins of dragging
+const PAN_STEP_BINS: i32 = 30;
+
 fn create_lines_render_pipeline(
     device: &wgpu::Device,
     sconfig: &wgpu::SurfaceConfiguration,
@@ -765,6 +797,169 @@ fn create_ui_render_pipeline(
 // }
 
 impl Example {
+    /// Runs what the keymap binds `input` to
+    fn on_input(&mut self, input: Input, amount: f32) {
+        let chord = Chord::new(self.modifiers, input);
+        let action = self
+            .sliding_renderer
+            .spectrum_ui
+            .as_ref()
+            .and_then(|v| v.keymap.lookup(&chord));
+        if let Some(action) = action {
+            self.perform(action, amount);
+        }
+    }
+
+    /// `amount` is 1 for a key, the lines scrolled for the wheel and
+    /// pan steps for a drag; the toggles ignore it
+    fn perform(&mut self, action: Action, amount: f32) {
+        let renderer = &mut self.sliding_renderer;
+        let ui = renderer.spectrum_ui.as_mut();
+        match action {
+            Action::ToggleHelp => {
+                ui.map(|v| v.toggle_help());
+            }
+            Action::ZoomIn | Action::Zoom => {
+                ui.map(|v| v.on_mouse_wheel(amount));
+            }
+            Action::ZoomOut => {
+                ui.map(|v| v.on_mouse_wheel(-amount));
+            }
+            Action::ResolutionUp => renderer.on_resolution_scale(amount),
+            Action::ResolutionDown => renderer.on_resolution_scale(-amount),
+            Action::CycleResolutionPolicy => {
+                ui.map(|v| v.cycle_resolution_policy());
+            }
+            Action::PanLeft | Action::PanRight | Action::Pan => {
+                let mut bins = (amount * PAN_STEP_BINS as f32).round() as i32;
+                if action == Action::PanRight {
+                    bins = -bins;
+                }
+                if bins != 0 {
+                    ui.map(|v| v.move_spectrum(bins > 0, bins.abs()));
+                }
+            }
+            Action::Pause => {
+                ui.map(|v| v.toggle_pause());
+            }
+            Action::CycleGraph => {
+                ui.map(|v| v.toggle_peaks());
+            }
+            Action::ToggleLogScale => {
+                ui.map(|v| v.toggle_logarithmic());
+            }
+            Action::ToggleSubtractionPeaks => {
+                ui.map(|v| v.toggle_subtraction_peaks());
+            }
+            Action::ToggleColorize => {
+                ui.map(|v| v.toggle_colorize());
+            }
+            Action::CycleMethod => {
+                ui.map(|v| v.cycle_method());
+            }
+            Action::CycleWindow => {
+                ui.map(|v| v.cycle_window_type());
+            }
+            Action::FewerSubdivisions => {
+                ui.map(|v| v.change_window_subdivisions(false));
+            }
+            Action::MoreSubdivisions => {
+                ui.map(|v| v.change_window_subdivisions(true));
+            }
+            Action::CollectFrequencyDown => {
+                ui.map(|v| v.change_collect_freq(false));
+            }
+            Action::CollectFrequencyUp => {
+                ui.map(|v| v.change_collect_freq(true));
+            }
+            Action::TogglePeakHold => {
+                ui.map(|v| v.toggle_trace(traces::TraceKind::PeakHold));
+            }
+            Action::ToggleMaxHold => {
+                ui.map(|v| v.toggle_trace(traces::TraceKind::MaxHold));
+            }
+            Action::ToggleMinHold => {
+                ui.map(|v| v.toggle_trace(traces::TraceKind::MinHold));
+            }
+            Action::CycleAverage => {
+                ui.map(|v| v.toggle_trace(traces::TraceKind::Average));
+            }
+            Action::ToggleNoiseFloor => {
+                ui.map(|v| v.toggle_noise_floor());
+            }
+            Action::ToggleSubtractNoiseFloor => {
+                ui.map(|v| v.toggle_subtract_noise_floor());
+            }
+            Action::ToggleTransfer => {
+                ui.map(|v| v.toggle_transfer());
+            }
+            Action::SwapTransferChannels => {
+                ui.map(|v| v.swap_transfer_channels());
+            }
+            Action::ToggleDistortion => {
+                ui.map(|v| v.toggle_distortion());
+            }
+            Action::ToggleGenerator => renderer.toggle_generator(),
+            Action::CycleGeneratorSignal => {
+                renderer.change_generator(|p| {
+                    let n: u8 = p.kind.into();
+                    p.kind = generator::SignalKind::try_from(n + 1)
+                        .unwrap_or(generator::SignalKind::Sine);
+                });
+            }
+            Action::GeneratorFrequencyDown | Action::GeneratorFrequencyUp => {
+                // third-octave steps
+                let step = if action == Action::GeneratorFrequencyUp {
+                    amount
+                } else {
+                    -amount
+                };
+                renderer.change_generator(|p| {
+                    p.frequency_hz =
+                        (p.frequency_hz * (2.0 as f32).powf(step / 3.0)).clamp(0.1, 24000.0);
+                });
+            }
+            Action::GeneratorLevelDown | Action::GeneratorLevelUp => {
+                let step = if action == Action::GeneratorLevelUp {
+                    3.0 * amount
+                } else {
+                    -3.0 * amount
+                };
+                renderer.change_generator(|p| {
+                    p.level_db = (p.level_db + step).clamp(-90.0, 0.0);
+                });
+            }
+            Action::CycleOverrunPolicy => {
+                ui.map(|v| v.cycle_overrun_policy());
+            }
+            Action::ToggleRecording => {
+                ui.map(|v| v.toggle_recording());
+            }
+            Action::ReplayPause => {
+                ui.map(|v| v.change_replay(|r| r.toggle_pause()));
+            }
+            Action::ReplayRestart => {
+                ui.map(|v| v.change_replay(|r| r.seek_to(0.0)));
+            }
+            Action::ReplayBack | Action::ReplayForward => {
+                let delta = if action == Action::ReplayBack {
+                    -5000.0 * amount as f64
+                } else {
+                    5000.0 * amount as f64
+                };
+                ui.map(|v| v.change_replay(|r| r.seek(delta)));
+            }
+            Action::ReplayFaster | Action::ReplaySlower => {
+                let faster = action == Action::ReplayFaster;
+                ui.map(|v| v.change_replay(|r| r.change_speed(faster)));
+            }
+            Action::CycleInputDevice => renderer.cycle_input_device(),
+            Action::Preset(number) => renderer.select_preset(number as usize),
+            Action::SaveSettings => renderer.save_settings(),
+            Action::Snapshot => self.snapshot_requested = true,
+        }
+    }
+
     fn generate_matrix(dx: f32, dy: f32) -> cgmath::Matrix4<f32> {
         println!("generate_matrix: dx{} dy{}", dx, dy);
         let mx_projection = cgmath::ortho(0.0, dx, dy, 0.0, -1.0, 1.0);
@@ -776,9 +971,70 @@ impl Example {
 #[derive(Clone)]
 pub struct MyParams {
     #[cfg(not(target_arch = "wasm32"))]
-    audio_device: Option<String>,
+    audio: adevice_cpal::AudioDeviceConfig,
+    /// raw PCM from stdin or a FIFO instead of the audio device
+    #[cfg(not(target_arch = "wasm32"))]
+    pipe: Option<adevice_pipe::PipeConfig>,
+    /// audio from the network instead of the audio device
+    #[cfg(not(target_arch = "wasm32"))]
+    net: Option<adevice_net::NetConfig>,
+    /// number of analyzed input channels, 2 enables transfer function measurement
+    input_channels: usize,
+    #[cfg(not(target_arch = "wasm32"))]
+    generator_wav: Option<String>,
+    /// analysis and view to start with: defaults, the settings file, then the command line
+    settings: settings::Settings,
+    /// set when the input's rate holds the range of `settings` below the one asked for
+    rate_limit: Option<settings::RateLimit>,
+    overrun_policy: appthread::OverrunPolicy,
+    /// drop peaks under the noise floor
+    noise_gate: bool,
+    /// record collected spectra from the start
+    record: Option<recorder::RecorderConfig>,
+    /// play this recording instead of the audio input
+    replay: Option<Arc<replay::Recording>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    stream: Option<stream::StreamConfig>,
+    #[cfg(not(target_arch = "wasm32"))]
+    osc: Option<osc::OscConfig>,
+    #[cfg(not(target_arch = "wasm32"))]
+    led: Option<led::LedConfig>,
+    /// settings file loaded at start and saved on exit, None to keep nothing
+    #[cfg(not(target_arch = "wasm32"))]
+    settings_path: Option<std::path::PathBuf>,
+    /// not after a replay or a run configured on the command line
+    #[cfg(not(target_arch = "wasm32"))]
+    save_settings_on_exit: bool,
+}
+
+impl MyParams {
+    /// sample rate of the input when it isn't the default 48 kHz device rate
+    #[cfg(not(target_arch = "wasm32"))]
+    fn input_sample_rate(&self) -> Option<u32> {
+        let net = self.net.as_ref().map(|n| n.pcm.sample_rate);
+        let pipe = self.pipe.as_ref().map(|p| p.pcm.sample_rate);
+        pipe.or(net).or(self.audio.sample_rate)
+    }
+}
+
+/// The font atlas, the same texels the GPU samples
+fn font_image() -> KRGBAImage {
+    let size = 256;
+    KRGBAImage {
+        dx: size,
+        dy: size,
+        pixels: include_bytes!("ascii.raw").to_vec(),
+    }
+}
+
+/// Glyph metrics of the font atlas, for FontRenderer
+fn font_atlas() -> FontAtlas {
+    let img = font_image();
+    let rect = KRect::at_origin(img.dx, img.dy, 0);
+    let mut font_atlas = FontAtlas::new();
+    font_atlas.resize_texture(&rect, img.dx, &img);
 
-    is_mobile: bool,
+    font_atlas
 }
 
 fn create_indices() -> Vec<u16> {
@@ -831,6 +1087,29 @@ impl framework::Example<MyParams> for Example {
         }
     }
 
+    #[cfg(not(target_arch = "wasm32"))]
+    fn run_without_gpu(params: MyParams) {
+        let png = HeadlessPng {
+            path: std::path::PathBuf::from("sbsdft_view.png"),
+            dx: 1280,
+            dy: 720,
+            after_s: 1.0,
+            every_s: Some(1.0),
+        };
+        println!(
+            "no GPU adapter, rendering to {} every second instead",
+            png.path.display()
+        );
+        run_headless(params, png);
+    }
+
+    fn on_exit(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.impl_params.save_settings_on_exit {
+            self.sliding_renderer.save_settings();
+        }
+    }
+
     /// constructs initial instance of Example struct
     fn init(
         sconfig: &wgpu::SurfaceConfiguration,
@@ -1099,6 +1378,8 @@ impl framework::Example<MyParams> for Example {
             index_buf,
             uniform_buf,
             impl_params,
+            snapshot_requested: false,
+            modifiers: keymap::Modifiers::default(),
         }
     }
 
@@ -1108,134 +1389,17 @@ impl framework::Example<MyParams> for Example {
             winit::event::WindowEvent::Focused(false) => {}
             winit::event::WindowEvent::Focused(true) => {}
             winit::event::WindowEvent::CloseRequested => {}
+            winit::event::WindowEvent::ModifiersChanged(state) => {
+                self.modifiers = keymap::Modifiers {
+                    ctrl: state.ctrl(),
+                    alt: state.alt(),
+                    shift: state.shift(),
+                };
+            }
             winit::event::WindowEvent::KeyboardInput { input, .. } => {
                 if input.state == winit::event::ElementState::Pressed {
                     match input.virtual_keycode {
-                        Some(key) => match key {
-                            VirtualKeyCode::NumpadAdd
-                            | VirtualKeyCode::Equals
-                            | VirtualKeyCode::Plus => {
-                                self.sliding_renderer
-                                    .spectrum_ui
-                                    .as_mut()
-                                    .map(|v| v.on_mouse_wheel(1.0));
-                            }
-                            VirtualKeyCode::NumpadSubtract | VirtualKeyCode::Minus => {
-                                self.sliding_renderer
-                                    .spectrum_ui
-                                    .as_mut()
-                                    .map(|v| v.on_mouse_wheel(-1.0));
-                            }
-                            VirtualKeyCode::Up => {
-                                self.sliding_renderer.on_resolution_scale(1.0);
-                            }
-                            VirtualKeyCode::Down => {
-                                self.sliding_renderer.on_resolution_scale(-1.0);
-                            }
-                            VirtualKeyCode::Left => {
-                                self.sliding_renderer
-                                    .spectrum_ui
-                                    .as_mut()
-                                    .map(|v| v.move_spectrum(true, 30));
-                            }
-                            VirtualKeyCode::Right => {
-                                self.sliding_renderer
-                                    .spectrum_ui
-                                    .as_mut()
-                                    .map(|v| v.move_spectrum(false, 30));
-                            }
-
-                            VirtualKeyCode::Space => {
-                                self.sliding_renderer
-                                    .spectrum_ui
-                                    .as_mut()
-                                    .map(|v| v.toggle_pause());
-                            }
-
-                            VirtualKeyCode::P => {
-                                self.sliding_renderer
-                                    .spectrum_ui
-                                    .as_mut()
-                                    .map(|v| v.toggle_peaks());
-                            }
-                            VirtualKeyCode::L => {
-                                self.sliding_renderer
-                                    .spectrum_ui
-                                    .as_mut()
-                                    .map(|v| v.toggle_logarithmic());
-                            }
-                            // VirtualKeyCode::R => {
-                            //     self.sliding_renderer
-                            //         .spectrum_ui
-                            //         .as_mut()
-                            //         .map(|v| v.measurement_reset());
-                            //}
-                            VirtualKeyCode::S => {
-                                self.sliding_renderer
-                                    .spectrum_ui
-                                    .as_mut()
-                                    .map(|v| v.toggle_subtraction_peaks());
-                            }
-                            VirtualKeyCode::C => {
-                                self.sliding_renderer
-                                    .spectrum_ui
-                                    .as_mut()
-                                    .map(|v| v.toggle_colorize());
-                            }
-
-                            VirtualKeyCode::O => {
-                                self.sliding_renderer
-                                    .spectrum_ui
-                                    .as_mut()
-                                    .map(|v| v.cycle_window_type());
-                            }
-
-                            VirtualKeyCode::B => {
-                                self.sliding_renderer
-                                    .spectrum_ui
-                                    .as_mut()
-                                    .map(|v| v.change_window_subdivisions(false));
-                            }
-                            VirtualKeyCode::N => {
-                                self.sliding_renderer
-                                    .spectrum_ui
-                                    .as_mut()
-                                    .map(|v| v.change_window_subdivisions(true));
-                            }
-                            VirtualKeyCode::M => {
-                                self.sliding_renderer
-                                    .spectrum_ui
-                                    .as_mut()
-                                    .map(|v| v.cycle_method());
-                            }
-
-                            VirtualKeyCode::Z => {
-                                self.sliding_renderer
-                                    .spectrum_ui
-                                    .as_mut()
-                                    .map(|v| v.change_collect_freq(false));
-                            }
-                            VirtualKeyCode::X => {
-                                self.sliding_renderer
-                                    .spectrum_ui
-                                    .as_mut()
-                                    .map(|v| v.change_collect_freq(true));
-                            }
-
-                            // VirtualKeyCode::Period => {
-                            //     self.sliding_renderer
-                            //         .spectrum_ui
-                            //         .as_mut()
-                            //         .map(|v| v.measurement_change_freq(false));
-                            // }
-                            // VirtualKeyCode::Comma => {
-                            //     self.sliding_renderer
-                            //         .spectrum_ui
-                            //         .as_mut()
-                            //         .map(|v| v.measurement_change_freq(true));
-                            //}
-                            _ => {}
-                        },
+                        Some(key) => self.on_input(Input::Key(keycode2kikod(key)), 1.0),
                         None => {}
                     };
                 }
@@ -1243,25 +1407,23 @@ impl framework::Example<MyParams> for Example {
             winit::event::WindowEvent::MouseWheel { delta, .. } => match delta {
                 winit::event::MouseScrollDelta::LineDelta(_dx, dy) => {
                     klog!("scroll  LineDelta {}", dy);
-                    self.sliding_renderer
-                        .spectrum_ui
-                        .as_mut()
-                        .map(|v| v.on_mouse_wheel(dy));
+                    self.on_input(Input::Wheel, dy);
                 }
                 winit::event::MouseScrollDelta::PixelDelta(pos) => {
                     let dy = pos.y as f32 / 100.0;
                     klog!("scroll PixelDelta {:?}", dy);
-                    self.sliding_renderer
-                        .spectrum_ui
-                        .as_mut()
-                        .map(|v| v.on_mouse_wheel(dy));
+                    self.on_input(Input::Wheel, dy);
                 }
             },
             winit::event::WindowEvent::CursorMoved { position, .. } => {
-                self.sliding_renderer
+                let dragged = self
+                    .sliding_renderer
                     .spectrum_ui
                     .as_mut()
-                    .map(|v| v.on_cursor_move(position.into()));
+                    .and_then(|v| v.on_cursor_move(position.into()));
+                if let Some((left, bins)) = dragged {
+                    self.on_input(Input::Drag { left }, bins as f32 / PAN_STEP_BINS as f32);
+                }
             }
             winit::event::WindowEvent::MouseInput { state, button, .. } => {
                 let pressed = state == winit::event::ElementState::Pressed;
@@ -1353,6 +1515,17 @@ impl framework::Example<MyParams> for Example {
 
                 //let (pos_col_verts, pos_col_tex_verts) = (Vec::new(), Vec::<PosColTexVertex>::new());
                 let (pos_col_verts, pos_col_tex_verts) = spectrum_ui.render();
+                if self.snapshot_requested {
+                    self.snapshot_requested = false;
+                    let path = format!("sbsdft_{}.png", kwasm::now_ms() as u64);
+                    save_png(
+                        std::path::Path::new(&path),
+                        self.sliding_renderer.last_screenx,
+                        self.sliding_renderer.last_screeny,
+                        &pos_col_verts,
+                        &pos_col_tex_verts,
+                    );
+                }
                 // if pos_col_verts.len() == 0 {
                 //     println!("pos_col_verts.len() == 0");
                 // }
@@ -1462,6 +1635,107 @@ fn draw_chunked<'a>(
     }
 }
 
+/// Rasterizes a frame on the CPU and writes it as PNG
+fn save_png(
+    path: &std::path::Path,
+    dx: u32,
+    dy: u32,
+    pc: &[PosColVertex],
+    pct: &[PosColTexVertex],
+) {
+    let mut raster = softraster::SoftRaster::new(dx, dy, font_image());
+    raster.render(pc, pct);
+    match raster.save_png(path) {
+        Ok(()) => println!("saved {}", path.display()),
+        Err(err) => println!("can't save {}: {}", path.display(), err),
+    }
+}
+
+/// Snapshots of a run without a window or GPU
+#[cfg(not(target_arch = "wasm32"))]
+struct HeadlessPng {
+    path: std::path::PathBuf,
+    dx: u32,
+    dy: u32,
+    /// seconds of analysis before the first snapshot
+    after_s: f32,
+    /// keep rewriting the snapshot this often, else exit after the first
+    every_s: Option<f32>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn run_headless(params: MyParams, png: HeadlessPng) {
+    let mut renderer = SlidingRenderer::new(&params);
+    renderer.init();
+    renderer.on_resize(png.dx, png.dy);
+    let mut raster = softraster::SoftRaster::new(png.dx, png.dy, font_image());
+
+    let start = std::time::Instant::now();
+    let mut next_s = png.after_s;
+    loop {
+        renderer.notify_render_tick();
+        if let Some(ui) = renderer.spectrum_ui.as_mut() {
+            // drains the spectrum queues like a frame on screen
+            let (pc, pct) = ui.render();
+            if start.elapsed().as_secs_f32() >= next_s {
+                raster.render(&pc, &pct);
+                if let Err(err) = raster.save_png(&png.path) {
+                    println!("can't save {}: {}", png.path.display(), err);
+                    return;
+                }
+                match png.every_s {
+                    Some(every_s) => next_s += every_s,
+                    None => {
+                        println!("saved {}", png.path.display());
+                        return;
+                    }
+                }
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_millis(16));
+    }
+}
+
+/// Batch mode of --spectrogram, exits with 1 if any file failed
+#[cfg(not(target_arch = "wasm32"))]
+fn run_spectrograms(inputs: &[String], dir: Option<String>, sc: &spectrogram::SpectrogramConfig) {
+    if let Some(dir) = &dir {
+        if let Err(err) = std::fs::create_dir_all(dir) {
+            eprintln!("{}: {}", dir, err);
+            std::process::exit(1);
+        }
+    }
+    let mut failed = 0;
+    for input in inputs {
+        let input = std::path::Path::new(input);
+        let name = format!(
+            "{}.png",
+            input.file_stem().unwrap_or_default().to_string_lossy()
+        );
+        let output = match &dir {
+            Some(dir) => std::path::Path::new(dir).join(name),
+            None => input.with_file_name(name),
+        };
+        let start = std::time::Instant::now();
+        match spectrogram::render_file(input, &output, sc) {
+            Ok(()) => println!(
+                "{} -> {} in {:.1} s",
+                input.display(),
+                output.display(),
+                start.elapsed().as_secs_f32()
+            ),
+            Err(err) => {
+                eprintln!("{}: {:#}", input.display(), err);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        eprintln!("{} of {} files failed", failed, inputs.len());
+        std::process::exit(1);
+    }
+}
+
 use clap::Parser;
 
 use self::appthread::ProcessingApp;
@@ -1470,9 +1744,395 @@ use self::appthread::ProcessingApp;
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Substring of audio input device to use
+    /// Audio input device, its index from --list-devices, exact name or a substring of the name
     #[arg(short, long)]
     input_audio_device: Option<String>,
+
+    /// List audio hosts, input devices and their supported configs, then exit
+    #[arg(long)]
+    list_devices: bool,
+
+    /// Audio host (API) to open the input device with, e.g. ALSA or JACK
+    #[arg(long)]
+    audio_host: Option<String>,
+
+    /// Sample rate of the input device, also the rate the analysis assumes
+    #[arg(long, value_parser = clap::value_parser!(u32).range(8000..))]
+    input_sample_rate: Option<u32>,
+
+    /// Frames per audio callback
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    input_buffer_size: Option<u32>,
+
+    /// Channels to open the input device with
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+    input_channels: Option<u16>,
+
+    /// Sample format to open the input device with
+    #[arg(long, value_enum)]
+    input_format: Option<adevice_cpal::InputFormat>,
+
+    /// Read interleaved raw PCM from stdin instead of an audio device, FORMAT[:CHANNELS[:RATE]]
+    /// with FORMAT s16le, s16be, s32le or f32le, e.g. f32le:2:48000
+    #[arg(long, conflicts_with = "fifo")]
+    stdin: Option<String>,
+
+    /// Read raw PCM from this named pipe, reopened whenever its writer closes it
+    #[arg(long)]
+    fifo: Option<String>,
+
+    /// FORMAT[:CHANNELS[:RATE]] of --fifo
+    #[arg(long, default_value = "s16le:1:48000")]
+    fifo_format: String,
+
+    /// Receive audio over the network instead of an audio device
+    #[arg(long, value_enum, conflicts_with_all = ["stdin", "fifo"])]
+    net: Option<adevice_net::NetProtocol>,
+
+    /// Address and port --net listens on
+    #[arg(long, default_value = "0.0.0.0:5004")]
+    net_bind: String,
+
+    /// FORMAT[:CHANNELS[:RATE]] of --net, s16be:2:48000 (L16) for rtp and s16le:2:48000 otherwise
+    #[arg(long)]
+    net_format: Option<String>,
+
+    /// Audio held back to reorder RTP packets, ms
+    #[arg(long, default_value_t = 40.0)]
+    net_jitter_ms: f32,
+
+    /// Analyze the first two input channels separately (needed for transfer function measurement)
+    #[arg(long)]
+    dual_channel: bool,
+
+    /// Write the test signal generator to this WAV file instead of an output device
+    #[arg(long)]
+    generator_wav: Option<String>,
+
+    /// Report only peaks that clear the noise floor
+    #[arg(long)]
+    noise_gate: bool,
+
+    /// What to do with audio when processing falls behind
+    #[arg(long, value_enum, default_value = "drop-newest")]
+    overrun_policy: appthread::OverrunPolicy,
+
+    /// Record collected spectra to this file (toggle with W)
+    #[arg(long)]
+    record: Option<String>,
+
+    /// Recording format, guessed from the --record extension (.npy, .bin, else csv)
+    #[arg(long, value_enum)]
+    record_format: Option<recorder::RecordFormat>,
+
+    /// Record only every n-th collected frame
+    #[arg(long, default_value_t = 1)]
+    record_every: usize,
+
+    /// Record per-bin complex values too
+    #[arg(long)]
+    record_complex: bool,
+
+    /// Play a recording (.csv, .npy or .bin) instead of the audio input
+    #[arg(long)]
+    replay: Option<String>,
+
+    /// Render without a window or GPU and save the view to this PNG
+    #[arg(long)]
+    png: Option<String>,
+
+    /// Size of the --png image, WIDTHxHEIGHT
+    #[arg(long, default_value = "1280x720")]
+    png_size: String,
+
+    /// Seconds of analysis before the --png snapshot
+    #[arg(long, default_value_t = 2.0)]
+    png_after: f32,
+
+    /// Keep rewriting the --png snapshot this often instead of exiting
+    #[arg(long)]
+    png_every: Option<f32>,
+
+    /// Render a spectrogram PNG of each of these files and exit, WAV only
+    #[arg(long, num_args = 1..)]
+    spectrogram: Vec<String>,
+
+    /// Directory for the spectrograms, next to each input file if not given
+    #[arg(long)]
+    spectrogram_dir: Option<String>,
+
+    /// Analysis method of the spectrograms
+    #[arg(long, value_enum, default_value = "nc")]
+    spectrogram_method: spectrogram::Method,
+
+    /// Frequency axis of the spectrograms
+    #[arg(long, value_enum, default_value = "log")]
+    spectrogram_scale: spectrogram::FreqScale,
+
+    #[arg(long, value_enum, default_value = "inferno")]
+    spectrogram_colormap: spectrogram::Colormap,
+
+    /// Level at the bottom of the colormap, dB relative to full scale
+    #[arg(long, default_value_t = -100.0, allow_hyphen_values = true)]
+    spectrogram_db_min: f32,
+
+    /// Level at the top of the colormap, dB relative to full scale
+    #[arg(long, default_value_t = 0.0, allow_hyphen_values = true)]
+    spectrogram_db_max: f32,
+
+    /// Spectrogram columns per second of audio
+    #[arg(long, default_value_t = 50.0)]
+    spectrogram_pps: f32,
+
+    /// Spectrogram height in pixels, also the number of analyzed bins
+    #[arg(long, default_value_t = 600)]
+    spectrogram_height: u32,
+
+    #[arg(long, default_value_t = 40.0)]
+    spectrogram_min_f: f32,
+
+    #[arg(long, default_value_t = 12000.0)]
+    spectrogram_max_f: f32,
+
+    /// Publish collected spectra as newline-delimited JSON on this TCP port
+    #[arg(long)]
+    stream_tcp: Option<u16>,
+
+    /// Publish collected spectra to WebSocket clients on this port
+    #[arg(long)]
+    stream_ws: Option<u16>,
+
+    /// Address the --stream-tcp and --stream-ws servers listen on
+    #[arg(long, default_value = "127.0.0.1")]
+    stream_bind: String,
+
+    /// Send peaks, levels, bands and onsets as OSC to this HOST:PORT over UDP
+    #[arg(long)]
+    osc: Option<String>,
+
+    /// Address prefix of the OSC messages
+    #[arg(long, default_value = "/sbsdft")]
+    osc_prefix: String,
+
+    /// OSC messages per second, onsets are sent as they happen
+    #[arg(long, default_value_t = 60.0)]
+    osc_rate: f32,
+
+    /// Drive an LED strip at this HOST[:PORT], the protocol's port if missing
+    #[arg(long)]
+    led: Option<String>,
+
+    #[arg(long, value_enum, default_value = "e131")]
+    led_protocol: led::LedProtocol,
+
+    /// Number of LEDs on the strip
+    #[arg(long, default_value_t = 60)]
+    led_count: usize,
+
+    #[arg(long, value_enum, default_value = "linear")]
+    led_layout: led::LedLayout,
+
+    #[arg(long, value_enum, default_value = "spectrum")]
+    led_source: led::LedSource,
+
+    #[arg(long, value_enum, default_value = "gamma")]
+    led_curve: led::LedCurve,
+
+    /// Exponent of the gamma brightness curve
+    #[arg(long, default_value_t = 2.2)]
+    led_gamma: f32,
+
+    /// dB below full scale that the db brightness curve starts at
+    #[arg(long, default_value_t = 30.0)]
+    led_db_range: f32,
+
+    /// Overall brightness, 0..1
+    #[arg(long, default_value_t = 1.0)]
+    led_brightness: f32,
+
+    /// First DMX universe, 1 for E1.31 and 0 for Art-Net by default
+    #[arg(long)]
+    led_universe: Option<u16>,
+
+    /// Width of a peak in LEDs, for --led-source peaks
+    #[arg(long, default_value_t = 1.5)]
+    led_spread: f32,
+
+    /// LED frames per second
+    #[arg(long, default_value_t = 40.0)]
+    led_rate: f32,
+
+    /// Print LED packets arriving at this HOST:PORT instead of running the app
+    #[arg(long)]
+    led_listen: Option<String>,
+
+    /// Settings file, loaded at start and saved on exit and with F2 [default: ~/.config/sbsdft/settings.json]
+    #[arg(long)]
+    config: Option<String>,
+
+    #[command(flatten)]
+    analysis: AnalysisArgs,
+}
+
+/// Analysis and view, over the settings file
+#[derive(clap::Args, Debug)]
+struct AnalysisArgs {
+    /// Number of frequency bins
+    #[arg(long, value_parser = clap::value_parser!(u32).range(2..=settings::MAX_BINS as i64))]
+    bins: Option<u32>,
+
+    /// Lowest frequency shown, Hz
+    #[arg(long)]
+    min_f: Option<f32>,
+
+    /// Highest frequency shown, Hz, at most a quarter of the input sample rate
+    #[arg(long)]
+    max_f: Option<f32>,
+
+    /// Window length in wavelengths of a bin's frequency (r)
+    #[arg(long)]
+    cycles: Option<f32>,
+
+    /// Below this frequency the windows of the shelf policy grow slower, Hz
+    #[arg(long)]
+    shelf_hz: Option<f32>,
+
+    /// Spectra collected per second (motion blur)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    collect_frequency: Option<u32>,
+
+    #[arg(long, value_enum)]
+    method: Option<settings::SpectrumMethod>,
+
+    #[arg(long, value_enum)]
+    window: Option<sbswdft::WindowType>,
+
+    /// Subdivisions of the smoothing window
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..=64))]
+    subdivisions: Option<i32>,
+
+    #[arg(long, value_enum)]
+    graph: Option<GraphType>,
+
+    /// How fast the peak-hold trace falls back, dB per second
+    #[arg(long)]
+    peak_decay: Option<f64>,
+
+    /// Spectra in the average trace (N), the exponential one weighs by 2/(N+1)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..=10000))]
+    average_frames: Option<u32>,
+
+    /// Averaging time of the transfer function, seconds
+    #[arg(long)]
+    transfer_averaging: Option<f64>,
+
+    /// Window length table "hz:cycles,hz:cycles,...", enables the table resolution policy
+    #[arg(long)]
+    resolution_table: Option<String>,
+}
+
+impl AnalysisArgs {
+    fn any(&self) -> bool {
+        self.bins.is_some()
+            || self.min_f.is_some()
+            || self.max_f.is_some()
+            || self.cycles.is_some()
+            || self.shelf_hz.is_some()
+            || self.collect_frequency.is_some()
+            || self.method.is_some()
+            || self.window.is_some()
+            || self.subdivisions.is_some()
+            || self.graph.is_some()
+            || self.resolution_table.is_some()
+            || self.peak_decay.is_some()
+            || self.average_frames.is_some()
+            || self.transfer_averaging.is_some()
+    }
+
+    /// Puts the given flags over `settings`, already at `input_rate`
+    fn apply(&self, settings: &mut settings::Settings, input_rate: u32) -> Result<(), String> {
+        let c = &mut settings.spectrum;
+        let nyquist = c.sample_rate as f32 / 2.0;
+        if let Some(max_f) = self.max_f {
+            if !(max_f > 0.0 && max_f <= nyquist) {
+                return Err(format!(
+                    "--max-f {} Hz is outside 0-{} Hz, the analysis runs at half of the {} Hz input",
+                    max_f, nyquist, input_rate
+                ));
+            }
+            c.max_f = max_f;
+        }
+        if let Some(min_f) = self.min_f {
+            if !(min_f > 0.0) {
+                return Err(format!("--min-f must be positive, got {}", min_f));
+            }
+            c.min_f = min_f;
+        }
+        if !(c.min_f < c.max_f) {
+            return Err(format!(
+                "--min-f {} Hz must be below --max-f {} Hz",
+                c.min_f, c.max_f
+            ));
+        }
+        if let Some(cycles) = self.cycles {
+            if !(cycles > 0.0) {
+                return Err(format!("--cycles must be positive, got {}", cycles));
+            }
+            c.wave_cycles_resolution = cycles;
+        }
+        if let Some(shelf_hz) = self.shelf_hz {
+            if !(shelf_hz >= 0.0) {
+                return Err(format!("--shelf-hz must not be negative, got {}", shelf_hz));
+            }
+            c.resolution_low_f_shelf_hz = shelf_hz;
+        }
+        if let Some(bins) = self.bins {
+            c.num_bins = bins;
+        }
+        if let Some(frequency) = self.collect_frequency {
+            if frequency > c.sample_rate / 2 {
+                return Err(format!(
+                    "--collect-frequency {} is above {}, a spectrum every other sample at {} Hz",
+                    frequency,
+                    c.sample_rate / 2,
+                    c.sample_rate
+                ));
+            }
+            c.collect_frequency = frequency as usize;
+        }
+        settings::Settings::check_spectrum(c)?;
+        if let Some(method) = self.method {
+            settings.method = method;
+        }
+        if let Some(window) = self.window {
+            settings.window_type = window;
+        }
+        if let Some(subdivisions) = self.subdivisions {
+            settings.subdivisions = subdivisions;
+        }
+        if let Some(graph) = self.graph {
+            settings.graph_type = graph;
+        }
+        if let Some(decay) = self.peak_decay {
+            if !(decay > 0.0 && decay.is_finite()) {
+                return Err(format!("--peak-decay must be positive, got {}", decay));
+            }
+            settings.peak_decay_db_per_s = decay;
+        }
+        if let Some(frames) = self.average_frames {
+            settings.average_frames = frames as usize;
+        }
+        if let Some(seconds) = self.transfer_averaging {
+            if !(seconds > 0.0 && seconds.is_finite()) {
+                return Err(format!(
+                    "--transfer-averaging must be positive, got {}",
+                    seconds
+                ));
+            }
+            settings.transfer_averaging_s = seconds;
+        }
+        Ok(())
+    }
 }
 
 pub fn main(is_mobile: bool) {
@@ -1483,16 +2143,297 @@ pub fn main(is_mobile: bool) {
 
     //let guard = pprof::ProfilerGuard::new(100).unwrap();
 
-    let params = MyParams {
+    #[cfg(not(target_arch = "wasm32"))]
+    let parse_pcm = |arg: &str, spec: &str| {
+        adevice_pipe::PcmSpec::parse(spec).unwrap_or_else(|err| {
+            eprintln!("{}: {}", arg, err);
+            std::process::exit(2);
+        })
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    let pipe = match (&args.stdin, &args.fifo) {
+        (Some(spec), _) => Some(adevice_pipe::PipeConfig {
+            path: None,
+            pcm: parse_pcm("--stdin", spec),
+        }),
+        (None, Some(path)) => Some(adevice_pipe::PipeConfig {
+            path: Some(path.clone()),
+            pcm: parse_pcm("--fifo-format", &args.fifo_format),
+        }),
+        (None, None) => None,
+    };
+    #[cfg(not(target_arch = "wasm32"))]
+    let net = args.net.map(|protocol| {
+        let default_format = match protocol {
+            adevice_net::NetProtocol::Rtp => "s16be:2:48000",
+            _ => "s16le:2:48000",
+        };
+        let format = args.net_format.as_deref().unwrap_or(default_format);
+        adevice_net::NetConfig {
+            protocol,
+            bind: args.net_bind.clone(),
+            pcm: parse_pcm("--net-format", format),
+            jitter_ms: args.net_jitter_ms.max(0.0),
+        }
+    });
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let mut input_channels = if args.dual_channel { 2 } else { 1 };
+    // a mono input has nothing for the second channel
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(channels) = pipe
+        .as_ref()
+        .map(|p| p.pcm.channels)
+        .or(net.as_ref().map(|n| n.pcm.channels))
+        .or(args.input_channels.map(|c| c as usize))
+    {
+        input_channels = input_channels.min(channels);
+    }
+    #[cfg(target_arch = "wasm32")]
+    let input_channels = 1;
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let resolution_table = args.analysis.resolution_table.as_ref().map(|table| {
+        resolution::ResolutionConfig::parse_table(table).unwrap_or_else(|err| {
+            eprintln!("--resolution-table: {}", err);
+            std::process::exit(2);
+        })
+    });
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if args.list_devices {
+        print!("{}", adevice_cpal::list_devices());
+        return;
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(addr) = &args.led_listen {
+        if let Err(err) = led::run_listen(addr) {
+            eprintln!("--led-listen: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if !args.spectrogram.is_empty() {
+        let mut sc = spectrogram::SpectrogramConfig::new();
+        sc.method = args.spectrogram_method;
+        sc.scale = args.spectrogram_scale;
+        sc.colormap = args.spectrogram_colormap;
+        sc.db_min = args.spectrogram_db_min;
+        sc.db_max = args.spectrogram_db_max;
+        sc.pixels_per_second = args.spectrogram_pps;
+        sc.height = args.spectrogram_height;
+        sc.num_bins = args.spectrogram_height;
+        sc.min_f = args.spectrogram_min_f;
+        sc.max_f = args.spectrogram_max_f;
+        if let Some(table) = &resolution_table {
+            sc.resolution.table = table.clone();
+            sc.resolution.policy = resolution::ResolutionPolicy::Table;
+        }
+        let error = if !(sc.pixels_per_second > 0.0) {
+            Some("--spectrogram-pps must be positive")
+        } else if sc.height < 16 {
+            Some("--spectrogram-height must be at least 16")
+        } else if !(sc.min_f > 0.0 && sc.min_f < sc.max_f) {
+            Some("--spectrogram-min-f must be positive and below --spectrogram-max-f")
+        } else if !(sc.db_min < sc.db_max) {
+            Some("--spectrogram-db-min must be below --spectrogram-db-max")
+        } else {
+            None
+        };
+        if let Some(error) = error {
+            eprintln!("{}", error);
+            std::process::exit(2);
+        }
+        run_spectrograms(&args.spectrogram, args.spectrogram_dir, &sc);
+        return;
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let record = args.record.map(|path| {
+        let path = std::path::PathBuf::from(path);
+        recorder::RecorderConfig {
+            format: args
+                .record_format
+                .unwrap_or(recorder::RecordFormat::from_path(&path)),
+            path,
+            decimation: args.record_every.max(1),
+            complex: args.record_complex,
+        }
+    });
+    #[cfg(target_arch = "wasm32")]
+    let record = None;
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let replay = args.replay.map(|path| {
+        let path = std::path::Path::new(&path);
+        let recording = replay::Recording::load(path).unwrap_or_else(|err| {
+            eprintln!("--replay: {:#}", err);
+            std::process::exit(2);
+        });
+        Arc::new(recording)
+    });
+    #[cfg(target_arch = "wasm32")]
+    let replay = None;
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let save_settings_on_exit = replay.is_none() && !args.analysis.any();
+
+    let mut settings = settings::Settings::new();
+    if is_mobile {
+        settings.spectrum.num_bins = 50;
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    let headless = args.png.map(|path| {
+        let size = args
+            .png_size
+            .split_once('x')
+            .and_then(|(dx, dy)| Some((dx.parse().ok()?, dy.parse().ok()?)))
+            .filter(|&(dx, dy)| dx > 0 && dy > 0)
+            .unwrap_or_else(|| {
+                eprintln!("--png-size: expected WIDTHxHEIGHT, got '{}'", args.png_size);
+                std::process::exit(2);
+            });
+        HeadlessPng {
+            path: std::path::PathBuf::from(path),
+            dx: size.0,
+            dy: size.1,
+            after_s: args.png_after,
+            every_s: args.png_every,
+        }
+    });
+
+    let mut params = MyParams {
         #[cfg(not(target_arch = "wasm32"))]
-        audio_device: args.input_audio_device,
-        is_mobile,
+        audio: adevice_cpal::AudioDeviceConfig {
+            host: args.audio_host,
+            device: args.input_audio_device,
+            sample_rate: args.input_sample_rate,
+            buffer_size: args.input_buffer_size,
+            channels: args.input_channels,
+            format: args.input_format,
+        },
+        #[cfg(not(target_arch = "wasm32"))]
+        pipe,
+        #[cfg(not(target_arch = "wasm32"))]
+        net,
+        input_channels,
+        #[cfg(not(target_arch = "wasm32"))]
+        generator_wav: args.generator_wav,
+        settings,
+        rate_limit: None,
+        #[cfg(not(target_arch = "wasm32"))]
+        overrun_policy: args.overrun_policy,
+        #[cfg(target_arch = "wasm32")]
+        overrun_policy: appthread::OverrunPolicy::DropNewest,
+        noise_gate: args.noise_gate,
+        record,
+        replay,
+        #[cfg(not(target_arch = "wasm32"))]
+        stream: (args.stream_tcp.is_some() || args.stream_ws.is_some()).then(|| {
+            stream::StreamConfig {
+                bind: args.stream_bind,
+                tcp_port: args.stream_tcp,
+                ws_port: args.stream_ws,
+            }
+        }),
+        #[cfg(not(target_arch = "wasm32"))]
+        osc: args.osc.map(|target| osc::OscConfig {
+            target,
+            prefix: args.osc_prefix,
+            rate_hz: args.osc_rate.max(1.0),
+        }),
+        #[cfg(not(target_arch = "wasm32"))]
+        led: args.led.map(|target| led::LedConfig {
+            target,
+            protocol: args.led_protocol,
+            num_leds: args.led_count,
+            layout: args.led_layout,
+            source: args.led_source,
+            curve: args.led_curve,
+            gamma: args.led_gamma,
+            db_range: args.led_db_range.max(1.0),
+            brightness: args.led_brightness,
+            universe: args.led_universe,
+            spread: args.led_spread,
+            rate_hz: args.led_rate.max(1.0),
+        }),
+        #[cfg(not(target_arch = "wasm32"))]
+        settings_path: args
+            .config
+            .map(std::path::PathBuf::from)
+            .or_else(settings::Settings::default_path),
+        #[cfg(not(target_arch = "wasm32"))]
+        save_settings_on_exit,
     };
+
+    // defaults, then the settings file, then the command line
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let mut input_rate = params.input_sample_rate();
+        if input_rate.is_none() && params.replay.is_none() {
+            // the device opens at its own rate
+            input_rate = adevice_cpal::input_rate(&params.audio);
+        }
+        let input_rate = input_rate.unwrap_or(48000);
+        if let Some(path) = &params.settings_path {
+            let loaded = settings::Settings::load(path).and_then(|settings| match settings {
+                Some(settings) => settings.check_at(input_rate).map(|()| Some(settings)),
+                None => Ok(None),
+            });
+            match loaded {
+                Ok(Some(settings)) => {
+                    println!("settings: loaded {}", path.display());
+                    params.settings = settings;
+                }
+                Ok(None) => {}
+                Err(err) => {
+                    println!("settings: ignoring {}: {}", path.display(), err);
+                    // don't overwrite what the user has to fix
+                    params.save_settings_on_exit = false;
+                }
+            }
+        }
+        if let Some(table) = &resolution_table {
+            let resolution = &mut params.settings.spectrum.resolution;
+            resolution.table = table.clone();
+            resolution.policy = resolution::ResolutionPolicy::Table;
+        }
+        params.rate_limit = params.settings.set_input_rate(input_rate);
+        if let Err(err) = args.analysis.apply(&mut params.settings, input_rate) {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        params.rate_limit = params.settings.set_input_rate(48000);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(png) = headless {
+        run_headless(params, png);
+        return;
+    }
     framework::run::<MyParams, Example>("sbsdft", params);
 }
 
-#[derive(Copy, Clone, TryFromPrimitive, IntoPrimitive)]
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    PartialEq,
+    TryFromPrimitive,
+    IntoPrimitive,
+    Serialize,
+    Deserialize,
+    clap::ValueEnum,
+)]
 #[repr(u8)]
+#[serde(rename_all = "lowercase")]
 pub enum GraphType {
     Line,
     Peaks,
@@ -1507,12 +2448,22 @@ struct SlidingRenderer {
 
     #[cfg(not(target_arch = "wasm32"))]
     audio_io_bridge: Option<Arc<adevice_cpal::SlidingCpal>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    pipe_input: Option<Arc<adevice_pipe::SlidingPipe>>,
+    #[cfg(not(target_arch = "wasm32"))]
+    net_input: Option<Arc<adevice_net::SlidingNet>>,
+    /// input rate the bins are tuned for
+    input_rate: u32,
 
     #[cfg(target_arch = "wasm32")]
     audio_io_bridge: Option<Arc<adevice_web::AdeviceWeb>>,
 
     spectrum_ui: Option<SpectrumUI>,
 
+    generator: generator::SharedGenerator,
+    #[cfg(not(target_arch = "wasm32"))]
+    generator_output: Option<generator::GeneratorOutput>,
+
     #[allow(unused)]
     params: MyParams,
 }
@@ -1523,9 +2474,20 @@ impl SlidingRenderer {
             last_screenx: 100,
             last_screeny: 100,
             audio_io_bridge: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            pipe_input: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            net_input: None,
+            input_rate: 48000,
             spectrum_ui: None,
             render_ticks_passed: 0,
             channels: None,
+            generator: Arc::new(Mutex::new(generator::SignalGenerator::new(
+                generator::GeneratorParams::new(),
+                48000,
+            ))),
+            #[cfg(not(target_arch = "wasm32"))]
+            generator_output: None,
             params: params.clone(),
         };
 
@@ -1540,23 +2502,24 @@ impl SlidingRenderer {
         //     return;
         // }
         kwasm::debug_wasm_mem("init_app_ui");
-        let is_mobile = self.params.is_mobile;
-        let num_bins = if is_mobile { 50 } else { 500 };
-
-        let config = SpectrumConfig {
-            sample_rate: 24000,
-            num_bins,
-            min_f: 40.0,
-            max_f: 12000.0,
-            wave_cycles_resolution: 16.0,
-            resolution_low_f_shelf_hz: 50.0,
-            subtraction_peaks: false,
-            collect_frequency: 5 * 60,
-        };
+        let settings = self.params.settings.clone();
+
+        let mut config = settings.spectrum.clone();
+        let mut input_channels = self.params.input_channels;
+        // a replay brings its own analysis
+        if let Some(recording) = &self.params.replay {
+            config = recording.config.clone();
+            input_channels = recording.channels;
+        }
+        self.input_rate = config.sample_rate * 2;
 
         let mut impls = vec![];
-        for _ in 0..1 {
-            let swdft = ChannelSWDFT::new(&config);
+        for _ in 0..input_channels {
+            let mut swdft = ChannelSWDFT::new(&config);
+            if self.params.replay.is_none() {
+                settings.apply_to_channel(&mut swdft);
+            }
+            swdft.noise_floor.config.gate_peaks = self.params.noise_gate;
             let mut receiver = None;
 
             std::mem::swap(
@@ -1571,6 +2534,7 @@ impl SlidingRenderer {
                 spectrum_receiver: receiver.unwrap(),
                 last_rolling_gain: 1.0,
                 collected_spectrums: VecDeque::new(),
+                traces: traces::SpectrumTraces::new(),
             };
             impls.push(impl_main_thread);
         }
@@ -1580,22 +2544,43 @@ impl SlidingRenderer {
             .map(|c| Arc::downgrade(&c.sliding_rc))
             .collect();
 
-        let font_atlas = {
-            let pixels = include_bytes!("ascii.raw");
-            let size = 256;
-            let rect = KRect::at_origin(size, size, 0);
-            let img = KRGBAImage {
-                dx: size,
-                dy: size,
-                pixels: pixels.to_vec(),
-            };
-            let mut font_atlas = FontAtlas::new();
-            font_atlas.resize_texture(&rect, size, &img);
-
-            font_atlas
-        };
-
-        self.spectrum_ui = Some(SpectrumUI::new(config, font_atlas, impls));
+        let mut ui = SpectrumUI::new(config, font_atlas(), impls);
+        ui.apply_view_settings(&settings);
+        ui.rate_limit = self.params.rate_limit;
+        ui.settings_base = settings.clone();
+        ui.keymap = std::rc::Rc::new(keymap::Keymap::with_overrides(&settings.key_bindings));
+        if let Some(record) = &self.params.record {
+            ui.recorder_config = record.clone();
+            ui.toggle_recording();
+        }
+        if let Some(recording) = &self.params.replay {
+            ui.start_replay(recording.clone());
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(config) = &self.params.stream {
+            match stream::SpectrumServer::start(config.clone()) {
+                Ok(server) => {
+                    println!("stream: {}", server.describe());
+                    ui.stream_server = Some(server);
+                }
+                Err(err) => println!("stream: can't listen on {}: {}", config.bind, err),
+            }
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(config) = &self.params.osc {
+            match osc::OscSender::new(config.clone()) {
+                Ok(sender) => ui.osc = Some(sender),
+                Err(err) => println!("osc: can't send to {}: {}", config.target, err),
+            }
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(config) = &self.params.led {
+            match led::LedSender::new(config.clone()) {
+                Ok(sender) => ui.leds = Some(sender),
+                Err(err) => println!("led: can't send to {}: {}", config.target, err),
+            }
+        }
+        self.spectrum_ui = Some(ui);
         self.channels = Some(channels);
 
         {
@@ -1614,10 +2599,30 @@ impl SlidingRenderer {
         let channels = self.channels.as_ref().unwrap().clone();
         let strong = channels.iter().map(|c| c.upgrade().unwrap()).collect();
         let app = ProcessingApp::new(strong);
+        app.stats.set_policy(self.params.overrun_policy);
 
         let appc = app.clone();
         self.spectrum_ui.as_mut().map(|ui| ui.app = Some(appc));
 
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(config) = &self.params.pipe {
+            let pipe = adevice_pipe::SlidingPipe::new(Arc::new(app.new_sender()), config.clone());
+            pipe.start();
+            self.pipe_input = Some(pipe);
+            return;
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(config) = &self.params.net {
+            match adevice_net::SlidingNet::start(Arc::new(app.new_sender()), config.clone()) {
+                Ok(net) => self.net_input = Some(net),
+                Err(err) => {
+                    eprintln!("net: can't listen on {}: {}", config.bind, err);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+
         #[cfg(not(target_arch = "wasm32"))]
         {
             self.audio_io_bridge = Some(adevice_cpal::SlidingCpal::new(
@@ -1632,7 +2637,11 @@ impl SlidingRenderer {
         }
 
         #[cfg(not(target_arch = "wasm32"))]
-        self.audio_io_bridge.as_mut().map(|v| v.start());
+        if let Some(input) = &self.audio_io_bridge {
+            input.start();
+            let health = input.health.clone();
+            self.spectrum_ui.as_mut().map(|ui| ui.input = Some(health));
+        }
 
         #[cfg(target_arch = "wasm32")]
         self.audio_io_bridge.as_mut().map(|v| v.start());
@@ -1645,9 +2654,120 @@ impl SlidingRenderer {
             self.init_app_ui();
         }
 
-        if self.render_ticks_passed > 6 && self.audio_io_bridge.is_none() {
+        // a replay runs without audio
+        if self.render_ticks_passed > 6 && !self.has_audio_input() && self.params.replay.is_none() {
             self.init_audio();
         }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        self.follow_input_rate();
+    }
+
+    /// Opens the next input device of the host, the analyzer keeps its settings
+    #[cfg(not(target_arch = "wasm32"))]
+    fn cycle_input_device(&mut self) {
+        let input = match &self.audio_io_bridge {
+            Some(input) => input.clone(),
+            None => {
+                println!("input: not reading an audio device");
+                return;
+            }
+        };
+        let names = adevice_cpal::input_device_names(&input.audio.host);
+        if names.is_empty() {
+            println!("input: no devices found");
+            return;
+        }
+        let current = input.health.device.lock().unwrap().clone();
+        let next = names
+            .iter()
+            .position(|name| *name == current)
+            .map_or(0, |i| (i + 1) % names.len());
+        println!(
+            "input: switching to {} ({}/{})",
+            names[next],
+            next + 1,
+            names.len()
+        );
+
+        let mut audio = input.audio.clone();
+        audio.device = Some(names[next].clone());
+        let input = input.switch_to(audio);
+        let health = input.health.clone();
+        self.spectrum_ui.as_mut().map(|ui| ui.input = Some(health));
+        self.audio_io_bridge = Some(input);
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn cycle_input_device(&mut self) {
+        klog!("the browser picks the input device");
+    }
+
+    /// Retunes the bins when the device runs at another rate than they are for
+    #[cfg(not(target_arch = "wasm32"))]
+    fn follow_input_rate(&mut self) {
+        let rate = match &self.audio_io_bridge {
+            Some(input) => input
+                .health
+                .sample_rate
+                .load(std::sync::atomic::Ordering::Relaxed),
+            None => return,
+        };
+        if rate != 0 && rate != self.input_rate {
+            println!("input: {} Hz, retuning the bins", rate);
+            self.input_rate = rate;
+            self.spectrum_ui
+                .as_mut()
+                .map(|ui| ui.change_sample_rate(rate));
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn save_settings(&self) {
+        let path = match &self.params.settings_path {
+            Some(path) => path,
+            None => return,
+        };
+        let ui = match &self.spectrum_ui {
+            Some(ui) => ui,
+            None => return,
+        };
+        if let Some(settings) = ui.requested_settings() {
+            match settings.save(path) {
+                Ok(()) => println!("settings: saved {}", path.display()),
+                Err(err) => println!("settings: can't save {}: {}", path.display(), err),
+            }
+        }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn save_settings(&self) {
+        klog!("settings are not kept in the browser");
+    }
+
+    /// `number` counts from 1, like the keys
+    fn select_preset(&mut self, number: usize) {
+        let preset = match number
+            .checked_sub(1)
+            .and_then(|i| self.params.settings.presets.get(i))
+        {
+            Some(preset) => preset,
+            None => {
+                klog!("no preset {}", number);
+                return;
+            }
+        };
+        self.spectrum_ui
+            .as_mut()
+            .map(|ui| ui.apply_preset(number, preset));
+    }
+
+    fn has_audio_input(&self) -> bool {
+        #[cfg(not(target_arch = "wasm32"))]
+        if self.pipe_input.is_some() || self.net_input.is_some() {
+            return true;
+        }
+        self.audio_io_bridge.is_some()
     }
 
     fn on_resize(&mut self, dx: u32, dy: u32) {
@@ -1661,4 +2781,38 @@ impl SlidingRenderer {
             .as_mut()
             .map(|v| v.on_resolution_scale(delta));
     }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn toggle_generator(&mut self) {
+        if self.generator_output.take().is_none() {
+            self.generator_output = generator::GeneratorOutput::start(
+                self.generator.clone(),
+                &self.params.generator_wav,
+            );
+        }
+        // a WAV file is rewritten on every change, only a device keeps playing
+        let playing = match &self.generator_output {
+            Some(generator::GeneratorOutput::Device(_)) => Some(self.generator.clone()),
+            _ => None,
+        };
+        self.spectrum_ui.as_mut().map(|v| v.generator = playing);
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn toggle_generator(&mut self) {
+        klog!("generator output is not supported in the browser");
+    }
+
+    fn change_generator<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut generator::GeneratorParams),
+    {
+        let mut generator = self.generator.lock().unwrap();
+        f(&mut generator.params);
+        klog!("generator: {}", generator.params.describe());
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(output) = &self.generator_output {
+            output.refresh(&mut generator);
+        }
+    }
 }
@@ -0,0 +1,197 @@
+use std::collections::VecDeque;
+
+use super::sbswdft::SSample;
+
+// Minimum statistics noise floor estimator (R. Martin, 2001), simplified:
+// per-bin magnitudes are smoothed over a short time, then the minimum of the
+// smoothed values over a longer span is tracked in sub-windows, so stationary
+// hum and hiss stay under the floor while tones and transients rise above it.
+// A tone held for longer than the span would become its own minimum, so the
+// floor of a bin is also capped by the median floor of its neighbours.
+
+pub struct NoiseFloorConfig {
+    /// time constant of the per-bin smoothing, seconds
+    pub smoothing_s: f64,
+    /// how long the minimum is searched for, seconds
+    pub span_s: f64,
+    /// number of sub-windows the span is split into
+    pub subwindows: usize,
+    /// the minimum of a noisy value is biased down, compensate for that
+    pub bias: f64,
+    /// how far above the floor a peak has to be, to be reported
+    pub peak_margin: f64,
+    /// drop peaks that don't clear the floor, off unless asked for
+    pub gate_peaks: bool,
+    /// bins on each side whose median caps a bin's floor
+    pub median_bins: usize,
+    /// the floor starts this far below the first frame and the minimum
+    /// search replaces it within a span
+    pub seed: f64,
+}
+
+impl NoiseFloorConfig {
+    pub fn new() -> Self {
+        Self {
+            smoothing_s: 0.05,
+            span_s: 2.0,
+            subwindows: 8,
+            bias: 1.4,
+            peak_margin: 2.0,
+            gate_peaks: false,
+            median_bins: 8,
+            seed: 0.1,
+        }
+    }
+}
+
+pub struct NoiseFloorEstimator {
+    pub config: NoiseFloorConfig,
+
+    smoothed: Vec<f64>,
+    current_min: Vec<f64>,
+    subwindow_mins: VecDeque<Vec<f64>>,
+    frames_in_subwindow: usize,
+
+    floor: Vec<f64>,
+}
+
+impl NoiseFloorEstimator {
+    pub fn new() -> Self {
+        Self {
+            config: NoiseFloorConfig::new(),
+            smoothed: Vec::new(),
+            current_min: Vec::new(),
+            subwindow_mins: VecDeque::new(),
+            frames_in_subwindow: 0,
+            floor: Vec::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.smoothed.clear();
+        self.current_min.clear();
+        self.subwindow_mins.clear();
+        self.frames_in_subwindow = 0;
+        self.floor.clear();
+    }
+
+    /// Per-bin floor, empty until the first frame arrives
+    pub fn floor(&self) -> &[f64] {
+        &self.floor
+    }
+
+    /// True if the value at bin `i` stands out of the noise floor,
+    /// always without `gate_peaks`
+    pub fn is_above(&self, i: usize, value: f64) -> bool {
+        if !self.config.gate_peaks {
+            return true;
+        }
+        match self.floor.get(i) {
+            Some(floor) => value > floor * self.config.peak_margin,
+            None => true,
+        }
+    }
+
+    pub fn update(&mut self, spectrum: &[SSample], collect_frequency: usize) {
+        let len = spectrum.len();
+        if self.smoothed.len() != len {
+            self.reset();
+            self.smoothed.extend(spectrum.iter().map(|s| s.value));
+            let seed = self.config.seed;
+            self.current_min = self.smoothed.iter().map(|v| v * seed).collect();
+            self.floor = self.current_min.clone();
+            return;
+        }
+
+        let c = &self.config;
+        let frame_rate = collect_frequency.max(1) as f64;
+        let alpha = 1.0 - (-1.0 / (c.smoothing_s * frame_rate)).exp();
+        let subwindows = c.subwindows.max(1);
+        let frames_per_subwindow = ((c.span_s * frame_rate) as usize / subwindows).max(1);
+
+        for ((smoothed, min), s) in self
+            .smoothed
+            .iter_mut()
+            .zip(self.current_min.iter_mut())
+            .zip(spectrum.iter())
+        {
+            *smoothed += alpha * (s.value - *smoothed);
+            *min = min.min(*smoothed);
+        }
+        self.frames_in_subwindow += 1;
+
+        if self.frames_in_subwindow >= frames_per_subwindow {
+            self.frames_in_subwindow = 0;
+            self.subwindow_mins.push_back(self.current_min.clone());
+            while self.subwindow_mins.len() > subwindows {
+                self.subwindow_mins.pop_front();
+            }
+            self.current_min.copy_from_slice(&self.smoothed);
+        }
+
+        let bias = c.bias;
+        let median_bins = c.median_bins;
+        let mins: Vec<f64> = (0..len)
+            .map(|i| {
+                let mut min = self.current_min[i];
+                for sub in &self.subwindow_mins {
+                    min = min.min(sub[i]);
+                }
+                min
+            })
+            .collect();
+        let mut window = Vec::with_capacity(2 * median_bins + 1);
+        for (i, floor) in self.floor.iter_mut().enumerate() {
+            window.clear();
+            window.extend_from_slice(
+                &mins[i.saturating_sub(median_bins)..(i + median_bins + 1).min(len)],
+            );
+            window.sort_by(|a, b| a.total_cmp(b));
+            let median = window[window.len() / 2];
+            *floor = mins[i].min(median) * bias;
+        }
+    }
+}
+
+#[test]
+fn test_noise_floor() {
+    // hiss everywhere, a tone held in bins 30-32
+    let frame = |n: usize| {
+        let values: Vec<f64> = (0..64)
+            .map(|i| {
+                let hiss = 0.01 * (1.0 + 0.5 * (((i * 7 + n * 13) % 11) as f64 / 11.0));
+                if (30..=32).contains(&i) {
+                    hiss + 1.0
+                } else {
+                    hiss
+                }
+            })
+            .collect();
+        let meta = super::sbswdft::BinMeta::new();
+        values
+            .iter()
+            .map(|&v| SSample::new(v, &meta))
+            .collect::<Vec<_>>()
+    };
+    let mut nf = NoiseFloorEstimator::new();
+    nf.config.gate_peaks = true;
+
+    // no peak is lost on the first frame
+    nf.update(&frame(0), 60);
+    assert!((0..64).all(|i| nf.floor()[i] < frame(0)[i].value));
+    assert!(nf.is_above(31, 1.0));
+
+    // much longer than the span, the tone still clears the floor
+    for n in 1..60 * 5 {
+        nf.update(&frame(n), 60);
+    }
+    let floor = nf.floor();
+    assert!(floor[31] < 0.05, "floor under the tone {}", floor[31]);
+    assert!(floor[10] > 0.005 && floor[10] < 0.03, "floor {}", floor[10]);
+    assert!(nf.is_above(31, 1.0));
+    assert!(!nf.is_above(10, 0.015));
+
+    // without the gate every peak is reported
+    nf.config.gate_peaks = false;
+    assert!(nf.is_above(10, 0.0));
+}
@@ -0,0 +1,360 @@
+use std::net::UdpSocket;
+
+use super::recorder::BinFrequencies;
+use super::recorder::MAX_PEAKS;
+use super::sbswdft::hz2color;
+use super::sbswdft::ChannelSWDFT;
+use super::sbswdft::Collected;
+use super::sbswdft::SpectrumConfig;
+
+// Open Sound Control output of the first channel, one UDP packet per frame
+// holding a bundle of these messages.
+// Levels are relative to the analyzer's rolling gain, so 1.0 is about the
+// recent average loudness, whatever the input level:
+//   <prefix>/level   f relative level, f dB of the unscaled spectrum power
+//   <prefix>/bands   f x 7, sub, bass, low mid, mid, high mid, presence, brilliance
+//   <prefix>/peaks   i count, then for each peak, loudest first
+//   <prefix>/peak    i index, f Hz, f amplitude, r color (hz2color)
+//   <prefix>/onset   f strength (above 1), on a jump in spectral flux,
+//                    checked every frame
+// Everything but onsets is sent at most `rate_hz` times a second.
+
+/// band edges, Hz
+const BANDS: [f32; 8] = [20.0, 60.0, 250.0, 500.0, 2000.0, 4000.0, 6000.0, 20000.0];
+/// onsets are this many times the average flux, plus the minimum
+const ONSET_RATIO: f32 = 2.5;
+const ONSET_MIN_FLUX: f32 = 0.01;
+const ONSET_HOLDOFF_S: f64 = 0.1;
+
+#[derive(Clone)]
+pub struct OscConfig {
+    /// host:port of the receiver
+    pub target: String,
+    pub prefix: String,
+    pub rate_hz: f32,
+}
+
+impl OscConfig {
+    pub fn new() -> Self {
+        Self {
+            target: "127.0.0.1:9000".to_string(),
+            prefix: "/sbsdft".to_string(),
+            rate_hz: 60.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Float(f32),
+    /// RGBA, red in the low byte like hz2color
+    Color(u32),
+}
+
+/// Appends `s` NUL terminated and padded to 4 bytes
+fn push_padded(out: &mut Vec<u8>, s: &[u8]) {
+    out.extend_from_slice(s);
+    out.push(0);
+    while out.len() % 4 != 0 {
+        out.push(0);
+    }
+}
+
+/// An OSC 1.0 message packet
+pub fn encode_message(address: &str, args: &[OscArg]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(address.len() + 8 + 8 * args.len());
+    push_padded(&mut out, address.as_bytes());
+    let mut tags = vec![b','];
+    tags.extend(args.iter().map(|a| match a {
+        OscArg::Int(_) => b'i',
+        OscArg::Float(_) => b'f',
+        OscArg::Color(_) => b'r',
+    }));
+    push_padded(&mut out, &tags);
+    for arg in args {
+        match arg {
+            OscArg::Int(i) => out.extend_from_slice(&i.to_be_bytes()),
+            OscArg::Float(f) => out.extend_from_slice(&f.to_be_bytes()),
+            // r, g, b, a in that order
+            OscArg::Color(c) => out.extend_from_slice(&c.to_le_bytes()),
+        }
+    }
+    out
+}
+
+/// An OSC 1.0 bundle of encoded `messages`, to be handled immediately
+pub fn encode_bundle(messages: &[Vec<u8>]) -> Vec<u8> {
+    let size = messages.iter().map(|m| 4 + m.len()).sum::<usize>();
+    let mut out = Vec::with_capacity(16 + size);
+    push_padded(&mut out, b"#bundle");
+    // the "immediately" time tag
+    out.extend_from_slice(&1u64.to_be_bytes());
+    for message in messages {
+        out.extend_from_slice(&(message.len() as u32).to_be_bytes());
+        out.extend_from_slice(message);
+    }
+    out
+}
+
+pub struct OscSender {
+    pub config: OscConfig,
+    socket: UdpSocket,
+    bins: BinFrequencies,
+    /// relative spectrum of the previous frame, for the flux
+    previous: Vec<f32>,
+    average_flux: f32,
+    last_onset_s: f64,
+    last_send_s: f64,
+    /// messages of the frame being sent
+    bundle: Vec<Vec<u8>>,
+    pub bundles: usize,
+    pub messages: usize,
+    pub onsets: usize,
+}
+
+impl OscSender {
+    pub fn new(config: OscConfig) -> std::io::Result<Self> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(&config.target)?;
+        Ok(Self {
+            config,
+            socket,
+            bins: BinFrequencies::new(),
+            previous: Vec::new(),
+            average_flux: 0.0,
+            last_onset_s: f64::NEG_INFINITY,
+            last_send_s: f64::NEG_INFINITY,
+            bundle: Vec::new(),
+            bundles: 0,
+            messages: 0,
+            onsets: 0,
+        })
+    }
+
+    fn send(&mut self, name: &str, args: &[OscArg]) {
+        let address = format!("{}/{}", self.config.prefix, name);
+        self.bundle.push(encode_message(&address, args));
+        self.messages += 1;
+    }
+
+    fn flush(&mut self) {
+        if self.bundle.is_empty() {
+            return;
+        }
+        // a missing receiver shows up as refused sends, keep going
+        let _ = self.socket.send(&encode_bundle(&self.bundle));
+        self.bundle.clear();
+        self.bundles += 1;
+    }
+
+    pub fn send_frame(&mut self, collected: &Collected, spectrum: &SpectrumConfig) {
+        let n = spectrum.num_bins as usize;
+        if collected.spectrum.len() < n {
+            return;
+        }
+        if self.bins.update(spectrum) {
+            self.previous.clear();
+        }
+        let gain = 1.0 / collected.cur_rolling_gain.max(1e-12);
+        let relative: Vec<f32> = collected.spectrum[..n]
+            .iter()
+            .map(|s| (s.value * gain) as f32)
+            .collect();
+        let now_s = spectrum.input_seconds(collected.sample_index);
+
+        if self.previous.len() == n {
+            let flux = relative
+                .iter()
+                .zip(&self.previous)
+                .map(|(v, p)| (v - p).max(0.0))
+                .sum::<f32>()
+                / n as f32;
+            let threshold = ONSET_RATIO * self.average_flux + ONSET_MIN_FLUX;
+            if flux > threshold && now_s - self.last_onset_s >= ONSET_HOLDOFF_S {
+                self.last_onset_s = now_s;
+                self.onsets += 1;
+                self.send("onset", &[OscArg::Float(flux / threshold)]);
+            }
+            self.average_flux += (flux - self.average_flux) * 0.05;
+        }
+        self.previous = relative;
+
+        if now_s - self.last_send_s >= 1.0 / self.config.rate_hz as f64 {
+            self.last_send_s = now_s;
+            self.send_levels(collected, gain);
+        }
+        self.flush();
+    }
+
+    fn send_levels(&mut self, collected: &Collected, gain: f64) {
+        let n = self.previous.len();
+        let power = ChannelSWDFT::power_of_spectrum(&collected.spectrum[..n]);
+        self.send(
+            "level",
+            &[
+                OscArg::Float((power * gain) as f32),
+                OscArg::Float((20.0 * (power + 1e-12).log10()) as f32),
+            ],
+        );
+
+        let bands: Vec<OscArg> = BANDS
+            .windows(2)
+            .map(|edges| {
+                let (sum, count) = self
+                    .bins
+                    .frequencies
+                    .iter()
+                    .zip(&self.previous)
+                    .filter(|(f, _)| **f >= edges[0] && **f < edges[1])
+                    .fold((0.0, 0), |(sum, count), (_, v)| (sum + v * v, count + 1));
+                OscArg::Float(if count > 0 {
+                    (sum / count as f32).sqrt()
+                } else {
+                    0.0
+                })
+            })
+            .collect();
+        self.send("bands", &bands);
+
+        let mut peaks: Vec<(f32, f32)> = match &collected.peaks {
+            Some(peaks) => peaks
+                .iter()
+                .filter_map(|p| {
+                    let f = self
+                        .bins
+                        .frequencies
+                        .get(usize::try_from(p.probe_index).ok()?)?;
+                    Some((*f, (p.value * gain) as f32))
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        peaks.sort_by(|a, b| b.1.total_cmp(&a.1));
+        peaks.truncate(MAX_PEAKS);
+        self.send("peaks", &[OscArg::Int(peaks.len() as i32)]);
+        for (i, (f, amplitude)) in peaks.into_iter().enumerate() {
+            self.send(
+                "peak",
+                &[
+                    OscArg::Int(i as i32),
+                    OscArg::Float(f),
+                    OscArg::Float(amplitude),
+                    OscArg::Color(hz2color(f)),
+                ],
+            );
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "{} {}, {} messages in {} bundles, {} onsets",
+            self.config.target, self.config.prefix, self.messages, self.bundles, self.onsets
+        )
+    }
+}
+
+#[test]
+fn test_osc_sender() {
+    assert_eq!(
+        encode_message("/a", &[OscArg::Int(1), OscArg::Float(0.5)]),
+        b"/a\0\0,if\0\0\0\0\x01\x3f\0\0\0".to_vec()
+    );
+
+    // (address, type tags, args)
+    fn decode(packet: &[u8]) -> (String, String, Vec<OscArg>) {
+        let string = |at: usize| {
+            let end = at + packet[at..].iter().position(|b| *b == 0).unwrap();
+            let s = String::from_utf8(packet[at..end].to_vec()).unwrap();
+            (s, (end + 4) & !3)
+        };
+        let (address, at) = string(0);
+        let (tags, mut at) = string(at);
+        let mut args = Vec::new();
+        for tag in tags[1..].bytes() {
+            let word: [u8; 4] = packet[at..at + 4].try_into().unwrap();
+            at += 4;
+            args.push(match tag {
+                b'i' => OscArg::Int(i32::from_be_bytes(word)),
+                b'f' => OscArg::Float(f32::from_be_bytes(word)),
+                b'r' => OscArg::Color(u32::from_le_bytes(word)),
+                _ => panic!("unexpected tag {}", tag),
+            });
+        }
+        assert_eq!(at, packet.len());
+        (address, tags, args)
+    }
+
+    let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+    listener
+        .set_read_timeout(Some(std::time::Duration::from_secs(5)))
+        .unwrap();
+    let mut config = OscConfig::new();
+    config.target = listener.local_addr().unwrap().to_string();
+    config.prefix = "/test".to_string();
+    let mut osc = OscSender::new(config).unwrap();
+
+    let spectrum = SpectrumConfig {
+        num_bins: 200,
+        ..SpectrumConfig::new()
+    };
+    let mut swdft = ChannelSWDFT::new(&spectrum);
+    // a pure tone is a one bin plateau with NC, simple_peaks wants a slope
+    swdft.spectrum_bins = ChannelSWDFT::make_spectrum_bins(0, &spectrum);
+    swdft.should_colorize = false;
+    let w = std::f64::consts::TAU * 440.0 / 48000.0;
+    let signal: Vec<f32> = (0..24000)
+        .map(|n| (0.5 * (n as f64 * w).sin()) as f32)
+        .collect();
+
+    // quiet frames, then the tone starts
+    swdft.process_input(&vec![0.0; 4800]);
+    let mut quiet = swdft.collect_spectrum();
+    quiet.cur_rolling_gain = 0.01;
+    for sample_index in [0, 100, 200] {
+        quiet.sample_index = sample_index;
+        osc.send_frame(&quiet, &spectrum);
+    }
+    swdft.process_input(&signal);
+    let mut tone = swdft.collect_spectrum();
+    tone.cur_rolling_gain = 0.01;
+    tone.sample_index = 24000;
+    osc.send_frame(&tone, &spectrum);
+    assert_eq!(osc.onsets, 1);
+
+    let mut buf = [0; 4096];
+    let mut received = Vec::new();
+    for _ in 0..osc.bundles {
+        let len = listener.recv(&mut buf).unwrap();
+        let bundle = &buf[..len];
+        assert_eq!(bundle[..16], *b"#bundle\0\0\0\0\0\0\0\0\x01");
+        let mut at = 16;
+        while at < len {
+            let size = u32::from_be_bytes(bundle[at..at + 4].try_into().unwrap()) as usize;
+            received.push(decode(&bundle[at + 4..at + 4 + size]));
+            at += 4 + size;
+        }
+        assert_eq!(at, len);
+    }
+    assert_eq!(received.len(), osc.messages);
+    // rate limited levels for the quiet frames, everything with the tone
+    assert_eq!(osc.bundles, 2);
+    // the first frame's level, bands and peaks, then the tone's
+    let (address, tags, _) = &received[0];
+    assert_eq!((address.as_str(), tags.as_str()), ("/test/level", ",ff"));
+    assert_eq!(received[1].1, ",fffffff");
+    let onset = received.iter().position(|m| m.0 == "/test/onset").unwrap();
+    assert!(matches!(received[onset].2[..], [OscArg::Float(s)] if s > 1.0));
+    let peak = received[onset..]
+        .iter()
+        .find(|m| m.0 == "/test/peak")
+        .map(|m| m.2.clone())
+        .unwrap();
+    let f = match peak[..] {
+        [OscArg::Int(0), OscArg::Float(f), OscArg::Float(_), OscArg::Color(_)] => f,
+        _ => panic!("unexpected peak {:?}", peak),
+    };
+    // bins are 2.9% apart here, the DFT peak can sit a couple of bins off
+    assert!((f / 440.0 - 1.0).abs() < 0.1, "{:?}", &received[onset..]);
+    assert_eq!(peak[3], OscArg::Color(hz2color(f)));
+}
@@ -0,0 +1,507 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use num_enum::IntoPrimitive;
+use num_enum::TryFromPrimitive;
+
+use super::sbswdft::ChannelSWDFT;
+use super::sbswdft::Collected;
+use super::sbswdft::SpectrumConfig;
+
+// Writes collected frames to disk.
+// Every frame carries its frame index, channel, input sample index, capture
+// time, per-bin magnitudes (as displayed, A-weighted), up to MAX_PEAKS peaks
+// and optionally the per-bin complex values (DFT method only, not weighted).
+//
+// csv:    '#' comment lines with the config, a header row of bin frequencies,
+//         one row per frame, peaks as "hz:value;hz:value"
+// npy:    structured array, one record per frame, bin frequencies go to a
+//         sidecar "<name>.freq.npy"
+// binary: little endian
+//         header: b"SBSDFTSP", version u32, sample_rate u32, num_bins u32,
+//                 min_f f32, max_f f32, wave_cycles_resolution f32,
+//                 resolution_low_f_shelf_hz f32, resolution policy u8,
+//                 flags u8 (1 = complex values), reserved u16,
+//                 num_bins x frequency f32
+//         frame:  frame u64, sample_index u64, capture_ms f64, channel u16,
+//                 peak count u16, num_bins x magnitude f32,
+//                 [num_bins x (re f32, im f32)], peak count x (hz f32, value f32)
+//
+// A config change that moves the bins starts a new file.
+
+/// peaks stored per frame, the npy record has a fixed size
+pub const MAX_PEAKS: usize = 16;
+
+const BINARY_MAGIC: &[u8; 8] = b"SBSDFTSP";
+const BINARY_VERSION: u32 = 1;
+
+/// the npy header is rewritten with the frame count this often
+const NPY_PATCH_EVERY: usize = 32;
+
+#[derive(Copy, Clone, Debug, PartialEq, TryFromPrimitive, IntoPrimitive, clap::ValueEnum)]
+#[repr(u8)]
+pub enum RecordFormat {
+    Csv,
+    Npy,
+    Binary,
+}
+
+impl RecordFormat {
+    /// Guesses the format from a file extension, csv if unknown
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("npy") => RecordFormat::Npy,
+            Some("bin") => RecordFormat::Binary,
+            _ => RecordFormat::Csv,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RecorderConfig {
+    pub path: PathBuf,
+    pub format: RecordFormat,
+    /// record every n-th frame
+    pub decimation: usize,
+    pub complex: bool,
+}
+
+impl RecorderConfig {
+    pub fn new() -> Self {
+        Self {
+            path: PathBuf::from("sbsdft_spectrum.csv"),
+            format: RecordFormat::Csv,
+            decimation: 1,
+            complex: false,
+        }
+    }
+}
+
+/// Frame encoder, generic over the output so it can be tested in memory
+pub struct SpectrumWriter<W: Write + Seek> {
+    out: W,
+    format: RecordFormat,
+    complex: bool,
+    frequencies: Vec<f32>,
+    npy_header_len: usize,
+    pub frames: usize,
+}
+
+impl<W: Write + Seek> SpectrumWriter<W> {
+    pub fn new(
+        mut out: W,
+        format: RecordFormat,
+        complex: bool,
+        config: &SpectrumConfig,
+    ) -> std::io::Result<Self> {
+        let frequencies = bin_frequencies(config);
+        let mut npy_header_len = 0;
+        match format {
+            RecordFormat::Csv => {
+                writeln!(
+                    out,
+                    "# sample_rate {}, bins {}, {:.2}..{:.2} Hz, resolution: {}",
+                    config.sample_rate,
+                    config.num_bins,
+                    config.min_f,
+                    config.max_f,
+                    config.resolution.describe(config)
+                )?;
+                write!(out, "frame,channel,sample_index,capture_ms")?;
+                for f in &frequencies {
+                    write!(out, ",{:.2}", f)?;
+                }
+                if complex {
+                    for f in &frequencies {
+                        write!(out, ",re@{:.2},im@{:.2}", f, f)?;
+                    }
+                }
+                writeln!(out, ",peaks")?;
+            }
+            RecordFormat::Npy => {
+                let header = npy_header(&frame_descr(frequencies.len(), complex), 0);
+                npy_header_len = header.len();
+                out.write_all(&header)?;
+            }
+            RecordFormat::Binary => {
+                out.write_all(BINARY_MAGIC)?;
+                out.write_all(&BINARY_VERSION.to_le_bytes())?;
+                out.write_all(&config.sample_rate.to_le_bytes())?;
+                out.write_all(&(frequencies.len() as u32).to_le_bytes())?;
+                for x in [
+                    config.min_f,
+                    config.max_f,
+                    config.wave_cycles_resolution,
+                    config.resolution_low_f_shelf_hz,
+                ] {
+                    out.write_all(&x.to_le_bytes())?;
+                }
+                let policy: u8 = config.resolution.policy.into();
+                out.write_all(&[policy, complex as u8, 0, 0])?;
+                for f in &frequencies {
+                    out.write_all(&f.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(Self {
+            out,
+            format,
+            complex,
+            frequencies,
+            npy_header_len,
+            frames: 0,
+        })
+    }
+
+    pub fn frequencies(&self) -> &Vec<f32> {
+        &self.frequencies
+    }
+
+    pub fn write(&mut self, channel: usize, collected: &Collected) -> std::io::Result<()> {
+        let n = self.frequencies.len();
+        let peaks: Vec<(f32, f32)> = match &collected.peaks {
+            Some(peaks) => peaks
+                .iter()
+                .filter_map(|p| {
+                    let f = self.frequencies.get(usize::try_from(p.probe_index).ok()?)?;
+                    Some((*f, p.value as f32))
+                })
+                .take(MAX_PEAKS)
+                .collect(),
+            None => Vec::new(),
+        };
+        let complex = |i: usize| match &collected.complex {
+            Some(c) => (c[i].re as f32, c[i].im as f32),
+            None => (0.0, 0.0),
+        };
+
+        let out = &mut self.out;
+        match self.format {
+            RecordFormat::Csv => {
+                write!(
+                    out,
+                    "{},{},{},{:.3}",
+                    collected.frame_index, channel, collected.sample_index, collected.capture_ms
+                )?;
+                for s in &collected.spectrum[..n] {
+                    write!(out, ",{:e}", s.value as f32)?;
+                }
+                if self.complex {
+                    for i in 0..n {
+                        let (re, im) = complex(i);
+                        write!(out, ",{:e},{:e}", re, im)?;
+                    }
+                }
+                let peaks: Vec<String> = peaks
+                    .iter()
+                    .map(|(f, v)| format!("{:.2}:{:e}", f, v))
+                    .collect();
+                writeln!(out, ",{}", peaks.join(";"))?;
+            }
+            RecordFormat::Npy => {
+                out.write_all(&(collected.frame_index as u64).to_le_bytes())?;
+                out.write_all(&(channel as u16).to_le_bytes())?;
+                out.write_all(&collected.sample_index.to_le_bytes())?;
+                out.write_all(&collected.capture_ms.to_le_bytes())?;
+                for s in &collected.spectrum[..n] {
+                    out.write_all(&(s.value as f32).to_le_bytes())?;
+                }
+                for i in 0..MAX_PEAKS {
+                    let (f, v) = peaks.get(i).cloned().unwrap_or((f32::NAN, f32::NAN));
+                    out.write_all(&f.to_le_bytes())?;
+                    out.write_all(&v.to_le_bytes())?;
+                }
+                if self.complex {
+                    for i in 0..n {
+                        let (re, im) = complex(i);
+                        out.write_all(&re.to_le_bytes())?;
+                        out.write_all(&im.to_le_bytes())?;
+                    }
+                }
+            }
+            RecordFormat::Binary => {
+                out.write_all(&(collected.frame_index as u64).to_le_bytes())?;
+                out.write_all(&collected.sample_index.to_le_bytes())?;
+                out.write_all(&collected.capture_ms.to_le_bytes())?;
+                out.write_all(&(channel as u16).to_le_bytes())?;
+                out.write_all(&(peaks.len() as u16).to_le_bytes())?;
+                for s in &collected.spectrum[..n] {
+                    out.write_all(&(s.value as f32).to_le_bytes())?;
+                }
+                if self.complex {
+                    for i in 0..n {
+                        let (re, im) = complex(i);
+                        out.write_all(&re.to_le_bytes())?;
+                        out.write_all(&im.to_le_bytes())?;
+                    }
+                }
+                for (f, v) in &peaks {
+                    out.write_all(&f.to_le_bytes())?;
+                    out.write_all(&v.to_le_bytes())?;
+                }
+            }
+        }
+        self.frames += 1;
+
+        if self.format == RecordFormat::Npy && self.frames % NPY_PATCH_EVERY == 0 {
+            self.patch_npy_header()?;
+        }
+        Ok(())
+    }
+
+    /// npy keeps the frame count in its header
+    fn patch_npy_header(&mut self) -> std::io::Result<()> {
+        let header = npy_header(
+            &frame_descr(self.frequencies.len(), self.complex),
+            self.frames,
+        );
+        debug_assert_eq!(header.len(), self.npy_header_len);
+        self.out.seek(SeekFrom::Start(0))?;
+        self.out.write_all(&header)?;
+        self.out.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> std::io::Result<W> {
+        if self.format == RecordFormat::Npy {
+            self.patch_npy_header()?;
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Records frames of all channels to a file
+pub struct SpectrumRecorder {
+    pub config: RecorderConfig,
+    pub path: PathBuf,
+    writer: Option<SpectrumWriter<BufWriter<File>>>,
+}
+
+impl SpectrumRecorder {
+    pub fn start(config: RecorderConfig, spectrum: &SpectrumConfig) -> std::io::Result<Self> {
+        let mut recorder = Self {
+            config,
+            path: PathBuf::new(),
+            writer: None,
+        };
+        recorder.open(spectrum)?;
+        Ok(recorder)
+    }
+
+    fn open(&mut self, spectrum: &SpectrumConfig) -> std::io::Result<()> {
+        let path = free_path(&self.config.path);
+        let file = BufWriter::new(File::create(&path)?);
+        let writer = SpectrumWriter::new(file, self.config.format, self.config.complex, spectrum)?;
+        if self.config.format == RecordFormat::Npy {
+            write_npy_f32(&path.with_extension("freq.npy"), writer.frequencies())?;
+        }
+        println!("recorder: writing {}", path.display());
+        self.path = path;
+        self.writer = Some(writer);
+        Ok(())
+    }
+
+    pub fn frames(&self) -> usize {
+        self.writer.as_ref().map(|w| w.frames).unwrap_or(0)
+    }
+
+    pub fn record(&mut self, channel: usize, collected: &Collected) -> std::io::Result<()> {
+        if collected.frame_index % self.config.decimation.max(1) != 0 {
+            return Ok(());
+        }
+        match &mut self.writer {
+            // frames of the old bins may still be queued after a config change
+            Some(w) if w.frequencies().len() == collected.spectrum.len() => {
+                w.write(channel, collected)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Starts a new file when the bins moved
+    pub fn reconfigure(&mut self, spectrum: &SpectrumConfig) -> std::io::Result<()> {
+        if let Some(w) = &self.writer {
+            if *w.frequencies() == bin_frequencies(spectrum) {
+                return Ok(());
+            }
+        }
+        self.finish()?;
+        self.open(spectrum)
+    }
+
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        if let Some(w) = self.writer.take() {
+            let frames = w.frames;
+            w.finish()?;
+            println!("recorder: {} frames in {}", frames, self.path.display());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SpectrumRecorder {
+    fn drop(&mut self) {
+        if let Err(err) = self.finish() {
+            println!("recorder: {}", err);
+        }
+    }
+}
+
+pub fn bin_frequencies(c: &SpectrumConfig) -> Vec<f32> {
+    let n = c.num_bins as f32;
+    (0..c.num_bins)
+        .map(|i| ChannelSWDFT::num_probe_x_to_freq(c, i as f32 / n))
+        .collect()
+}
+
+/// bin_frequencies of the last config seen, for the network outputs
+pub struct BinFrequencies {
+    pub frequencies: Arc<Vec<f32>>,
+    /// (num_bins, min_f, max_f) of `frequencies`
+    key: (u32, f32, f32),
+}
+
+impl BinFrequencies {
+    pub fn new() -> Self {
+        Self {
+            frequencies: Arc::new(Vec::new()),
+            key: (0, 0.0, 0.0),
+        }
+    }
+
+    /// Rebuilds the frequencies when the bins moved, returns true if they did
+    pub fn update(&mut self, spectrum: &SpectrumConfig) -> bool {
+        let key = (spectrum.num_bins, spectrum.min_f, spectrum.max_f);
+        if key == self.key {
+            return false;
+        }
+        self.key = key;
+        self.frequencies = Arc::new(bin_frequencies(spectrum));
+        true
+    }
+}
+
+impl Default for BinFrequencies {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// `path`, or the first of "name.1.ext", "name.2.ext", ... that doesn't exist
+fn free_path(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("spectrum");
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let mut candidate = path.to_path_buf();
+    let mut i = 1;
+    while candidate.exists() {
+        candidate = path.with_file_name(format!("{}.{}.{}", stem, i, ext));
+        i += 1;
+    }
+    candidate
+}
+
+fn frame_descr(num_bins: usize, complex: bool) -> String {
+    let mut descr = format!(
+        "[('frame', '<u8'), ('channel', '<u2'), ('sample_index', '<u8'), ('capture_ms', '<f8'), \
+         ('magnitude', '<f4', ({},)), ('peaks', '<f4', ({}, 2))",
+        num_bins, MAX_PEAKS
+    );
+    if complex {
+        descr += &format!(", ('complex', '<c8', ({},))", num_bins);
+    }
+    descr + "]"
+}
+
+/// npy 1.0 preamble, padded so that any frame count fits in the same length
+fn npy_header(descr: &str, count: usize) -> Vec<u8> {
+    let dict = |count: &str| {
+        format!(
+            "{{'descr': {}, 'fortran_order': False, 'shape': ({},), }}",
+            descr, count
+        )
+    };
+    let longest = dict(&u64::MAX.to_string()).len();
+    let mut text = dict(&count.to_string());
+    // magic, version, header length, the dict and a newline, to a multiple of 64
+    let total = (10 + longest + 1 + 63) / 64 * 64;
+    while 10 + text.len() + 1 < total {
+        text.push(' ');
+    }
+    text.push('\n');
+
+    let mut header = b"\x93NUMPY\x01\x00".to_vec();
+    header.extend_from_slice(&(text.len() as u16).to_le_bytes());
+    header.extend_from_slice(text.as_bytes());
+    header
+}
+
+fn write_npy_f32(path: &Path, values: &[f32]) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(&npy_header("'<f4'", values.len()))?;
+    for v in values {
+        out.write_all(&v.to_le_bytes())?;
+    }
+    out.flush()
+}
+
+#[test]
+fn test_recorder_formats() {
+    use std::io::Cursor;
+
+    let config = SpectrumConfig {
+        num_bins: 50,
+        ..SpectrumConfig::new()
+    };
+    let mut swdft = ChannelSWDFT::new(&config);
+    swdft.spectrum_bins = ChannelSWDFT::make_spectrum_bins(0, &config);
+    swdft.should_colorize = false;
+    let signal: Vec<f32> = (0..4800)
+        .map(|n| (0.3 * (n as f64 * 0.1).sin()) as f32)
+        .collect();
+    swdft.process_input(&signal);
+    let collected = swdft.collect_spectrum();
+    let num_peaks = collected
+        .peaks
+        .as_ref()
+        .map(|p| p.len().min(MAX_PEAKS))
+        .unwrap_or(0);
+
+    let write = |format, complex| {
+        let mut w = SpectrumWriter::new(Cursor::new(Vec::new()), format, complex, &config).unwrap();
+        w.write(0, &collected).unwrap();
+        w.write(1, &collected).unwrap();
+        w.finish().unwrap().into_inner()
+    };
+
+    let csv = String::from_utf8(write(RecordFormat::Csv, true)).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[1].split(',').count(), 4 + 3 * 50 + 1);
+    assert!(lines[3].starts_with(&format!("{},1,", collected.frame_index)));
+
+    let npy = write(RecordFormat::Npy, false);
+    let header_len = 10 + u16::from_le_bytes([npy[8], npy[9]]) as usize;
+    assert_eq!(header_len % 64, 0);
+    let header = String::from_utf8_lossy(&npy[10..header_len]);
+    assert!(header.contains("'shape': (2,)"));
+    let record = 8 + 2 + 8 + 8 + 4 * 50 + 8 * MAX_PEAKS;
+    assert_eq!(npy.len(), header_len + 2 * record);
+
+    let bin = write(RecordFormat::Binary, true);
+    assert_eq!(&bin[..8], BINARY_MAGIC);
+    let header = 8 + 4 * 3 + 4 * 4 + 4 + 4 * 50;
+    let frame = 8 + 8 + 8 + 2 + 2 + 4 * 50 + 8 * 50 + 8 * num_peaks;
+    assert_eq!(bin.len(), header + 2 * frame);
+    let f0 = f32::from_le_bytes(bin[40..44].try_into().unwrap());
+    assert!((f0 - 40.0).abs() < 1e-3);
+}
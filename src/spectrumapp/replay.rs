@@ -0,0 +1,673 @@
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+use anyhow::bail;
+use anyhow::Context;
+
+use super::recorder::RecordFormat;
+use super::recorder::MAX_PEAKS;
+use super::resolution::ResolutionConfig;
+use super::resolution::ResolutionPolicy;
+use super::sbswdft::BinMeta;
+use super::sbswdft::ChannelSWDFT;
+use super::sbswdft::Collected;
+use super::sbswdft::ComplexF64;
+use super::sbswdft::SPeak;
+use super::sbswdft::SSample;
+use super::sbswdft::SpectrumBinsState;
+use super::sbswdft::SpectrumConfig;
+use super::sbswdft::WindowType;
+use super::spectrumui::RendererMsg;
+use super::spectrumui::StateSnapshot;
+
+// Plays a recorder file back into the viewer, without audio.
+// The whole file is loaded, a frame is sent to its channel's renderer queue
+// once the replay clock passes its capture time, like the processing thread
+// would have sent it. csv and npy files don't keep the sample rate and the
+// bin range, those are rebuilt from the bin frequencies.
+
+pub struct RecordedFrame {
+    pub frame_index: u64,
+    pub channel: usize,
+    pub sample_index: u64,
+    pub capture_ms: f64,
+    pub magnitudes: Vec<f32>,
+    pub complex: Option<Vec<ComplexF64>>,
+    /// (hz, value)
+    pub peaks: Vec<(f32, f32)>,
+}
+
+pub struct Recording {
+    pub config: SpectrumConfig,
+    pub frequencies: Vec<f32>,
+    pub frames: Vec<RecordedFrame>,
+    pub channels: usize,
+    /// ms from the first frame, never decreasing
+    times: Vec<f64>,
+}
+
+/// csv and npy files don't keep it
+const DEFAULT_SAMPLE_RATE: u32 = 24000;
+/// sanity limit for bin counts read from a file header
+const MAX_BINS: usize = 1 << 16;
+
+impl Recording {
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let file = File::open(path).with_context(|| format!("{}", path.display()))?;
+        let mut input = BufReader::new(file);
+        match RecordFormat::from_path(path) {
+            RecordFormat::Csv => Self::read_csv(input),
+            RecordFormat::Binary => Self::read_binary(&mut input),
+            RecordFormat::Npy => {
+                let freq_path = path.with_extension("freq.npy");
+                let mut freq_input = BufReader::new(
+                    File::open(&freq_path).with_context(|| format!("{}", freq_path.display()))?,
+                );
+                let (descr, count) = read_npy_header(&mut freq_input)?;
+                if !descr.starts_with("'<f4'") {
+                    bail!("{}: expected float32 frequencies", freq_path.display());
+                }
+                if count > MAX_BINS {
+                    bail!(
+                        "{}: {} bins, at most {} are supported",
+                        freq_path.display(),
+                        count,
+                        MAX_BINS
+                    );
+                }
+                let frequencies = read_f32s(&mut freq_input, count)?;
+                Self::read_npy(&mut input, frequencies)
+            }
+        }
+    }
+
+    pub fn read_binary<R: Read>(input: &mut R) -> Result<Self, anyhow::Error> {
+        let mut magic = [0u8; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != b"SBSDFTSP" {
+            bail!("not a spectrum recording");
+        }
+        let version = read_u32(input)?;
+        if version != 1 {
+            bail!("unsupported recording version {}", version);
+        }
+        let sample_rate = read_u32(input)?;
+        let num_bins = read_u32(input)? as usize;
+        if num_bins > MAX_BINS {
+            bail!("{} bins, at most {} are supported", num_bins, MAX_BINS);
+        }
+        let fields = read_f32s(input, 4)?;
+        let mut flags = [0u8; 4];
+        input.read_exact(&mut flags)?;
+        let frequencies = read_f32s(input, num_bins)?;
+
+        let mut resolution = ResolutionConfig::new();
+        resolution.policy = ResolutionPolicy::try_from(flags[0]).unwrap_or(ResolutionPolicy::Shelf);
+        let config = SpectrumConfig {
+            sample_rate,
+            num_bins: num_bins as u32,
+            min_f: fields[0],
+            max_f: fields[1],
+            wave_cycles_resolution: fields[2],
+            resolution_low_f_shelf_hz: fields[3],
+            subtraction_peaks: false,
+            collect_frequency: 0,
+            resolution,
+        };
+        let has_complex = flags[1] & 1 != 0;
+
+        let mut frames = Vec::new();
+        loop {
+            let mut head = [0u8; 28];
+            match input.read_exact(&mut head) {
+                Ok(()) => {}
+                Err(err) if err.kind() == std::io::ErrorKind::UnexpectedEof => break,
+                Err(err) => return Err(err.into()),
+            }
+            let u64_at = |i: usize| u64::from_le_bytes(head[i..i + 8].try_into().unwrap());
+            let u16_at = |i: usize| u16::from_le_bytes(head[i..i + 2].try_into().unwrap());
+            let magnitudes = read_f32s(input, num_bins)?;
+            let complex = if has_complex {
+                Some(read_complex(input, num_bins)?)
+            } else {
+                None
+            };
+            let peaks = read_f32s(input, 2 * u16_at(26) as usize)?;
+            frames.push(RecordedFrame {
+                frame_index: u64_at(0),
+                sample_index: u64_at(8),
+                capture_ms: f64::from_bits(u64_at(16)),
+                channel: u16_at(24) as usize,
+                magnitudes,
+                complex,
+                peaks: peaks.chunks(2).map(|p| (p[0], p[1])).collect(),
+            });
+        }
+        Ok(Self::new(config, frequencies, frames))
+    }
+
+    pub fn read_csv<R: BufRead>(input: R) -> Result<Self, anyhow::Error> {
+        let mut sample_rate = DEFAULT_SAMPLE_RATE;
+        let mut frequencies: Vec<f32> = Vec::new();
+        let mut has_complex = false;
+        let mut frames = Vec::new();
+
+        for line in input.lines() {
+            let line = line?;
+            if let Some(comment) = line.strip_prefix('#') {
+                if let Some(rest) = comment.trim().strip_prefix("sample_rate ") {
+                    let number = rest.split(',').next().unwrap_or("");
+                    sample_rate = number.trim().parse().unwrap_or(DEFAULT_SAMPLE_RATE);
+                }
+                continue;
+            }
+            let fields: Vec<&str> = line.split(',').collect();
+            if fields.first() == Some(&"frame") {
+                frequencies = fields[4..]
+                    .iter()
+                    .take_while(|f| !f.starts_with("re@") && **f != "peaks")
+                    .map(|f| f.parse())
+                    .collect::<Result<_, _>>()
+                    .context("bad frequency header")?;
+                has_complex = fields.iter().any(|f| f.starts_with("re@"));
+                continue;
+            }
+            let n = frequencies.len();
+            let expected = 4 + n + if has_complex { 2 * n } else { 0 } + 1;
+            if fields.len() != expected {
+                bail!("expected {} columns, got {}", expected, fields.len());
+            }
+            let numbers: Vec<f64> = fields[..expected - 1]
+                .iter()
+                .map(|f| f.parse())
+                .collect::<Result<_, _>>()
+                .with_context(|| format!("bad row: {}", fields[0]))?;
+            let complex = if has_complex {
+                Some(
+                    numbers[4 + n..]
+                        .chunks(2)
+                        .map(|c| ComplexF64 { re: c[0], im: c[1] })
+                        .collect(),
+                )
+            } else {
+                None
+            };
+            let peaks = fields[expected - 1]
+                .split(';')
+                .filter_map(|p| {
+                    let (f, v) = p.split_once(':')?;
+                    Some((f.parse().ok()?, v.parse().ok()?))
+                })
+                .collect();
+            frames.push(RecordedFrame {
+                frame_index: numbers[0] as u64,
+                channel: numbers[1] as usize,
+                sample_index: numbers[2] as u64,
+                capture_ms: numbers[3],
+                magnitudes: numbers[4..4 + n].iter().map(|v| *v as f32).collect(),
+                complex,
+                peaks,
+            });
+        }
+        if frequencies.is_empty() {
+            bail!("no header row");
+        }
+        let config = Self::config_from_frequencies(sample_rate, &frequencies);
+        Ok(Self::new(config, frequencies, frames))
+    }
+
+    pub fn read_npy<R: Read>(input: &mut R, frequencies: Vec<f32>) -> Result<Self, anyhow::Error> {
+        let (descr, count) = read_npy_header(input)?;
+        let n = frequencies.len();
+        if !descr.contains(&format!("('magnitude', '<f4', ({},))", n)) {
+            bail!("recording doesn't match its {} frequencies", n);
+        }
+        let has_complex = descr.contains("'complex'");
+
+        let mut frames = Vec::new();
+        for _ in 0..count {
+            let mut head = [0u8; 26];
+            input.read_exact(&mut head)?;
+            let u64_at = |i: usize| u64::from_le_bytes(head[i..i + 8].try_into().unwrap());
+            let magnitudes = read_f32s(input, n)?;
+            let peaks = read_f32s(input, 2 * MAX_PEAKS)?;
+            let complex = if has_complex {
+                Some(read_complex(input, n)?)
+            } else {
+                None
+            };
+            frames.push(RecordedFrame {
+                frame_index: u64_at(0),
+                channel: u16::from_le_bytes([head[8], head[9]]) as usize,
+                sample_index: u64_at(10),
+                capture_ms: f64::from_bits(u64_at(18)),
+                magnitudes,
+                complex,
+                peaks: peaks
+                    .chunks(2)
+                    .filter(|p| !p[0].is_nan())
+                    .map(|p| (p[0], p[1]))
+                    .collect(),
+            });
+        }
+        let config = Self::config_from_frequencies(DEFAULT_SAMPLE_RATE, &frequencies);
+        Ok(Self::new(config, frequencies, frames))
+    }
+
+    /// bins sit at min_f * (max_f / min_f)^(i / n)
+    fn config_from_frequencies(sample_rate: u32, frequencies: &[f32]) -> SpectrumConfig {
+        let n = frequencies.len();
+        let min_f = frequencies[0];
+        let max_f = if n > 1 {
+            let ratio = frequencies[n - 1] as f64 / min_f as f64;
+            (min_f as f64 * ratio.powf(n as f64 / (n - 1) as f64)) as f32
+        } else {
+            min_f * 2.0
+        };
+        SpectrumConfig {
+            sample_rate,
+            num_bins: n as u32,
+            min_f,
+            max_f,
+            collect_frequency: 0,
+            ..SpectrumConfig::new()
+        }
+    }
+
+    fn new(mut config: SpectrumConfig, frequencies: Vec<f32>, frames: Vec<RecordedFrame>) -> Self {
+        let channels = frames.iter().map(|f| f.channel + 1).max().unwrap_or(1);
+
+        // capture times if they were stamped, else the input sample count
+        let stamped = frames.iter().all(|f| f.capture_ms > 0.0);
+        let input_rate = config.input_rate();
+        let mut times = Vec::with_capacity(frames.len());
+        let mut last = 0.0;
+        if let Some(first) = frames.first() {
+            for f in &frames {
+                let t = if stamped {
+                    f.capture_ms - first.capture_ms
+                } else {
+                    f.sample_index.saturating_sub(first.sample_index) as f64 * 1000.0 / input_rate
+                };
+                last = t.max(last);
+                times.push(last);
+            }
+        }
+
+        let per_channel = frames.len() / channels;
+        config.collect_frequency = if last > 0.0 {
+            (per_channel as f64 * 1000.0 / last).round() as usize
+        } else {
+            60
+        };
+
+        Self {
+            config,
+            frequencies,
+            frames,
+            channels,
+            times,
+        }
+    }
+
+    pub fn duration_ms(&self) -> f64 {
+        self.times.last().cloned().unwrap_or(0.0)
+    }
+}
+
+pub struct Replay {
+    pub recording: Arc<Recording>,
+    pub playing: bool,
+    pub speed: f64,
+    /// replay clock, ms from the start of the recording
+    pub position_ms: f64,
+    /// next frame to send
+    cursor: usize,
+    last_tick_ms: Option<f64>,
+    metas: Vec<BinMeta>,
+    rolling_gain: Vec<f64>,
+    senders: Vec<Sender<RendererMsg>>,
+    pub colorize: bool,
+}
+
+impl Replay {
+    /// `senders` feed the renderer queues of the channels, in channel order
+    pub fn new(recording: Arc<Recording>, senders: Vec<Sender<RendererMsg>>) -> Self {
+        let metas = recording
+            .frequencies
+            .iter()
+            .map(|f| {
+                let mut meta = BinMeta::new();
+                meta.reinit(*f as f64, recording.config.sample_rate);
+                meta
+            })
+            .collect();
+        let channels = senders.len();
+        let mut replay = Self {
+            recording,
+            playing: true,
+            speed: 1.0,
+            position_ms: 0.0,
+            cursor: 0,
+            last_tick_ms: None,
+            metas,
+            rolling_gain: vec![0.0; channels],
+            senders,
+            colorize: true,
+        };
+        replay.seek_to(0.0);
+        replay
+    }
+
+    pub fn toggle_pause(&mut self) {
+        if !self.playing && self.cursor == self.recording.frames.len() {
+            self.seek_to(0.0);
+        }
+        self.playing = !self.playing;
+        self.last_tick_ms = None;
+    }
+
+    pub fn change_speed(&mut self, faster: bool) {
+        let speed = if faster {
+            self.speed * 2.0
+        } else {
+            self.speed * 0.5
+        };
+        self.speed = speed.clamp(1.0 / 16.0, 16.0);
+    }
+
+    pub fn seek(&mut self, delta_ms: f64) {
+        self.seek_to(self.position_ms + delta_ms);
+    }
+
+    /// Jumps to `position_ms`, the next tick shows the frames of that moment
+    pub fn seek_to(&mut self, position_ms: f64) {
+        let times = &self.recording.times;
+        self.position_ms = position_ms.clamp(0.0, self.recording.duration_ms());
+        let shown = times.partition_point(|t| *t <= self.position_ms);
+        self.cursor = match shown {
+            0 => 0,
+            _ => times.partition_point(|t| *t < times[shown - 1]),
+        };
+        self.last_tick_ms = None;
+
+        for sender in &self.senders {
+            let _ = sender.send(RendererMsg::ConfigUpdate(self.recording.config.clone()));
+        }
+    }
+
+    /// Frames due at `now_ms`, advances the clock while playing
+    pub fn advance(&mut self, now_ms: f64) -> std::ops::Range<usize> {
+        let last = self.last_tick_ms.replace(now_ms);
+        if self.playing {
+            // don't race ahead after a stall
+            let dt = last.map(|l| (now_ms - l).clamp(0.0, 250.0)).unwrap_or(0.0);
+            self.position_ms =
+                (self.position_ms + dt * self.speed).min(self.recording.duration_ms());
+        }
+
+        let start = self.cursor;
+        self.cursor += self.recording.times[start..].partition_point(|t| *t <= self.position_ms);
+        if self.cursor == self.recording.frames.len() {
+            self.playing = false;
+        }
+        start..self.cursor
+    }
+
+    pub fn tick(&mut self, now_ms: f64) {
+        for i in self.advance(now_ms) {
+            self.send(i);
+        }
+    }
+
+    fn send(&mut self, i: usize) {
+        let channel = self.recording.frames[i].channel;
+        if channel < self.senders.len() {
+            let collected = self.to_collected(i);
+            let _ = self.senders[channel].send(RendererMsg::NewSpectrum(collected));
+        }
+    }
+
+    pub fn to_collected(&mut self, i: usize) -> Collected {
+        let recording = self.recording.clone();
+        let frame = &recording.frames[i];
+        let mut spectrum: Vec<SSample> = frame
+            .magnitudes
+            .iter()
+            .zip(self.metas.iter())
+            .map(|(v, meta)| SSample::new(*v as f64, meta))
+            .collect();
+        let peaks: Vec<SPeak> = frame
+            .peaks
+            .iter()
+            .map(|(f, v)| {
+                let probe = recording
+                    .frequencies
+                    .partition_point(|x| x < f)
+                    .min(self.metas.len() - 1);
+                SPeak {
+                    probe_index: probe as i32,
+                    value: *v as f64,
+                    color: self.metas[probe].color.clone(),
+                    octave: self.metas[probe].octave,
+                    alpha: 1.0,
+                }
+            })
+            .collect();
+        if self.colorize {
+            ChannelSWDFT::colorize_spectrum(&mut spectrum, &peaks);
+        }
+
+        // the processing thread's auto gain, settled from the first frame
+        let power = ChannelSWDFT::power_of_spectrum(&spectrum);
+        let gain = &mut self.rolling_gain[frame.channel];
+        if *gain == 0.0 {
+            *gain = power + 0.00001;
+        } else {
+            *gain += (power + 0.00001 - *gain) * 0.01;
+        }
+
+        let config = &recording.config;
+        Collected {
+            cur_rolling_gain: *gain,
+            spectrum,
+            peaks: if peaks.is_empty() { None } else { Some(peaks) },
+            rendered: None,
+            snapshot: StateSnapshot {
+                current_algo: if frame.complex.is_some() {
+                    SpectrumBinsState::DFT
+                } else {
+                    SpectrumBinsState::NC
+                },
+                window_type: WindowType::BlackmanNutall,
+                collect_every: 1,
+                collect_frequency: config.collect_frequency,
+                window_kernel_len: 1,
+            },
+            noise_floor: None,
+            complex: frame.complex.clone(),
+            frame_index: frame.frame_index as usize,
+            distortion: None,
+            sample_index: frame.sample_index,
+            capture_ms: frame.capture_ms,
+        }
+    }
+
+    /// One line summary for the overlay
+    pub fn describe(&self) -> String {
+        let state = if self.playing {
+            "playing"
+        } else if self.cursor == self.recording.frames.len() {
+            "ended"
+        } else {
+            "paused"
+        };
+        format!(
+            "{:.1} / {:.1} s, x{}, {}, frame {}/{}",
+            self.position_ms / 1000.0,
+            self.recording.duration_ms() / 1000.0,
+            self.speed,
+            state,
+            self.cursor,
+            self.recording.frames.len()
+        )
+    }
+}
+
+fn read_u32<R: Read>(input: &mut R) -> std::io::Result<u32> {
+    let mut b = [0u8; 4];
+    input.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+fn read_f32s<R: Read>(input: &mut R, n: usize) -> std::io::Result<Vec<f32>> {
+    // grows with the data read, a bad count in a short file can't allocate it all
+    let len = 4 * n as u64;
+    let mut b = Vec::new();
+    input.take(len).read_to_end(&mut b)?;
+    if (b.len() as u64) < len {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(b.chunks(4)
+        .map(|c| f32::from_le_bytes(c.try_into().unwrap()))
+        .collect())
+}
+
+fn read_complex<R: Read>(input: &mut R, n: usize) -> std::io::Result<Vec<ComplexF64>> {
+    Ok(read_f32s(input, 2 * n)?
+        .chunks(2)
+        .map(|c| ComplexF64 {
+            re: c[0] as f64,
+            im: c[1] as f64,
+        })
+        .collect())
+}
+
+/// The descr and the length of a one-dimensional npy 1.0 array
+fn read_npy_header<R: Read>(input: &mut R) -> Result<(String, usize), anyhow::Error> {
+    let mut preamble = [0u8; 10];
+    input.read_exact(&mut preamble)?;
+    if &preamble[..8] != b"\x93NUMPY\x01\x00" {
+        bail!("not an npy 1.0 file");
+    }
+    let mut text = vec![0u8; u16::from_le_bytes([preamble[8], preamble[9]]) as usize];
+    input.read_exact(&mut text)?;
+    let text = String::from_utf8_lossy(&text);
+
+    let descr = text
+        .split_once("'descr': ")
+        .and_then(|(_, rest)| rest.split_once(", 'fortran_order'"))
+        .map(|(descr, _)| descr.to_string())
+        .context("npy header without descr")?;
+    let count = text
+        .split_once("'shape': (")
+        .and_then(|(_, rest)| rest.split_once(','))
+        .and_then(|(count, _)| count.trim().parse().ok())
+        .context("npy header without shape")?;
+    Ok((descr, count))
+}
+
+#[test]
+fn test_replay_roundtrip() {
+    use super::recorder::SpectrumWriter;
+    use std::io::Cursor;
+
+    let config = SpectrumConfig {
+        num_bins: 40,
+        ..SpectrumConfig::new()
+    };
+    let mut swdft = ChannelSWDFT::new(&config);
+    swdft.spectrum_bins = ChannelSWDFT::make_spectrum_bins(0, &config);
+    swdft.should_colorize = false;
+    let signal: Vec<f32> = (0..4800)
+        .map(|n| (0.3 * (n as f64 * 0.1).sin()) as f32)
+        .collect();
+
+    let mut collected = Vec::new();
+    for (i, chunk) in signal.chunks(480).enumerate() {
+        swdft.process_input(chunk);
+        let mut c = swdft.collect_spectrum();
+        c.frame_index = i;
+        c.sample_index = 480 * (i as u64 + 1);
+        c.capture_ms = 0.0;
+        collected.push(c);
+    }
+
+    for format in [RecordFormat::Csv, RecordFormat::Npy, RecordFormat::Binary] {
+        let mut w = SpectrumWriter::new(Cursor::new(Vec::new()), format, true, &config).unwrap();
+        for c in &collected {
+            w.write(0, c).unwrap();
+        }
+        let bytes = w.finish().unwrap().into_inner();
+        let recording = match format {
+            RecordFormat::Csv => Recording::read_csv(Cursor::new(bytes)).unwrap(),
+            RecordFormat::Npy => {
+                let frequencies = super::recorder::bin_frequencies(&config);
+                Recording::read_npy(&mut Cursor::new(bytes), frequencies).unwrap()
+            }
+            RecordFormat::Binary => Recording::read_binary(&mut Cursor::new(bytes)).unwrap(),
+        };
+        assert_eq!(recording.frames.len(), collected.len());
+        assert_eq!(recording.config.num_bins, 40);
+        assert!((recording.config.max_f - 12000.0).abs() < 1.0);
+        // 10 ms blocks at the 48 kHz input rate
+        assert!((recording.duration_ms() - 90.0).abs() < 1e-9);
+        for (r, c) in recording.frames.iter().zip(collected.iter()) {
+            assert_eq!(r.sample_index, c.sample_index);
+            for (a, b) in r.magnitudes.iter().zip(c.spectrum.iter()) {
+                assert!((*a as f64 - b.value).abs() <= 1e-5 * b.value.abs().max(1e-3));
+            }
+            let (a, b) = (
+                &r.complex.as_ref().unwrap()[7],
+                &c.complex.as_ref().unwrap()[7],
+            );
+            assert!((a.re - b.re).abs() <= 1e-5 * b.re.abs().max(1e-3));
+        }
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        let mut replay = Replay::new(Arc::new(recording), vec![tx]);
+        replay.colorize = false;
+        assert_eq!(replay.advance(1000.0), 0..1);
+        assert_eq!(replay.advance(1025.0), 1..3);
+        replay.toggle_pause();
+        assert_eq!(replay.advance(2000.0), 3..3);
+        replay.toggle_pause();
+        replay.speed = 2.0;
+        replay.advance(2000.0);
+        assert_eq!(replay.advance(2020.0), 3..7);
+        replay.seek(-35.0);
+        assert_eq!(replay.position_ms, 30.0);
+        assert_eq!(replay.advance(3000.0), 3..4);
+        replay.seek(1000.0);
+        assert_eq!(replay.advance(3000.0), 9..10);
+        assert!(replay.describe().contains("ended"));
+        let c = replay.to_collected(5);
+        assert_eq!(c.spectrum.len(), 40);
+        assert_eq!(c.sample_index, collected[5].sample_index);
+        assert!((c.spectrum[20].value - collected[5].spectrum[20].value).abs() < 1e-3);
+        drop(replay);
+        // a config update at the start and one per seek
+        assert_eq!(rx.try_iter().count(), 3);
+    }
+
+    // bin counts from a corrupt header
+    let mut w = SpectrumWriter::new(
+        Cursor::new(Vec::new()),
+        RecordFormat::Binary,
+        false,
+        &config,
+    )
+    .unwrap();
+    w.write(0, &collected[0]).unwrap();
+    let bytes = w.finish().unwrap().into_inner();
+    for num_bins in [u32::MAX, 60000] {
+        let mut corrupt = bytes.clone();
+        corrupt[16..20].copy_from_slice(&num_bins.to_le_bytes());
+        assert!(Recording::read_binary(&mut Cursor::new(corrupt)).is_err());
+    }
+}
@@ -0,0 +1,206 @@
+use num_enum::IntoPrimitive;
+use num_enum::TryFromPrimitive;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+use super::sbswdft::DftBin;
+use super::sbswdft::SpectrumConfig;
+
+// How long the window of a bin at a given frequency is.
+// Every policy is driven by the config's `wave_cycles_resolution`
+// (or `bandwidth_hz`), so the resolution keys work the same for all of them.
+
+#[derive(
+    Copy, Clone, Debug, PartialEq, TryFromPrimitive, IntoPrimitive, Serialize, Deserialize,
+)]
+#[repr(u8)]
+#[serde(rename_all = "snake_case")]
+pub enum ResolutionPolicy {
+    /// `20 + 4r + r*sr/(shelf+f)`, constant-Q with a low frequency shelf
+    Shelf,
+    /// r cycles of every frequency
+    ConstantQ,
+    /// the same bandwidth everywhere, like an FFT
+    ConstantBandwidth,
+    /// constant bandwidth below the crossover, constant-Q above it
+    Hybrid,
+    /// the shelf formula, with windows no longer than the latency cap
+    LatencyCap,
+    /// cycles interpolated from a frequency table
+    Table,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResolutionConfig {
+    pub policy: ResolutionPolicy,
+    /// bin bandwidth of the constant bandwidth policy
+    pub bandwidth_hz: f32,
+    pub crossover_hz: f32,
+    pub max_latency_ms: f32,
+    /// (frequency Hz, cycles) points, sorted by frequency
+    pub table: Vec<(f32, f32)>,
+}
+
+/// shortest window any policy gives
+const MIN_WINDOW: usize = 16;
+/// windows are warm started from the ring, don't outgrow it
+pub const MAX_WINDOW: usize = 1 << 15;
+
+impl Default for ResolutionConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ResolutionConfig {
+    pub fn new() -> Self {
+        Self {
+            policy: ResolutionPolicy::Shelf,
+            bandwidth_hz: 10.0,
+            crossover_hz: 300.0,
+            max_latency_ms: 100.0,
+            table: vec![(20.0, 3.0), (200.0, 8.0), (2000.0, 24.0), (20000.0, 48.0)],
+        }
+    }
+
+    /// Parses "hz:cycles,hz:cycles,..."
+    pub fn parse_table(text: &str) -> Result<Vec<(f32, f32)>, String> {
+        let mut table = Vec::new();
+        for point in text.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            let (hz, cycles) = point
+                .split_once(':')
+                .ok_or(format!("expected hz:cycles, got '{}'", point))?;
+            let hz: f32 = hz
+                .trim()
+                .parse()
+                .map_err(|_| format!("bad frequency '{}'", hz))?;
+            let cycles: f32 = cycles
+                .trim()
+                .parse()
+                .map_err(|_| format!("bad cycles '{}'", cycles))?;
+            if !hz.is_finite() || !cycles.is_finite() {
+                return Err(format!("'{}' must be finite", point));
+            }
+            if hz <= 0.0 || cycles <= 0.0 {
+                return Err(format!("'{}' must be positive", point));
+            }
+            table.push((hz, cycles));
+        }
+        if table.is_empty() {
+            return Err("empty resolution table".to_string());
+        }
+        table.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(table)
+    }
+
+    pub fn cycle_policy(&mut self) {
+        let n: u8 = self.policy.into();
+        self.policy = match ResolutionPolicy::try_from(n + 1) {
+            Ok(x) => x,
+            Err(_) => ResolutionPolicy::try_from(0).unwrap(),
+        }
+    }
+
+    /// Cycles at `freq` from the table, interpolated on a log frequency axis
+    pub fn table_cycles(&self, freq: f32) -> f32 {
+        let table = &self.table;
+        match (table.first(), table.last()) {
+            (Some(first), _) if freq <= first.0 => first.1,
+            (_, Some(last)) if freq >= last.0 => last.1,
+            (None, _) | (_, None) => 16.0,
+            _ => {
+                let i = table.iter().position(|p| p.0 > freq).unwrap();
+                let (f0, c0) = table[i - 1];
+                let (f1, c1) = table[i];
+                let x = (freq / f0).ln() / (f1 / f0).ln();
+                c0 + (c1 - c0) * x
+            }
+        }
+    }
+
+    /// One line summary for the overlay
+    pub fn describe(&self, c: &SpectrumConfig) -> String {
+        let r = c.wave_cycles_resolution;
+        match self.policy {
+            ResolutionPolicy::Shelf => format!(
+                "shelf, r = {:.2} wavelengths, shelf {:.0} Hz",
+                r, c.resolution_low_f_shelf_hz
+            ),
+            ResolutionPolicy::ConstantQ => format!("constant-Q, {:.2} cycles", r),
+            ResolutionPolicy::ConstantBandwidth => {
+                format!("constant bandwidth, {:.2} Hz", self.bandwidth_hz)
+            }
+            ResolutionPolicy::Hybrid => format!(
+                "hybrid, {:.2} cycles above {:.0} Hz, {:.2} Hz below",
+                r,
+                self.crossover_hz,
+                self.crossover_hz / r
+            ),
+            ResolutionPolicy::LatencyCap => format!(
+                "shelf r = {:.2}, capped at {:.0} ms",
+                r, self.max_latency_ms
+            ),
+            ResolutionPolicy::Table => format!("table of {} points", self.table.len()),
+        }
+    }
+}
+
+/// Window length in samples for a bin at `freq_hz`
+pub fn window_size(c: &SpectrumConfig, freq_hz: f32) -> usize {
+    (wanted_window_size(c, freq_hz) as usize).clamp(MIN_WINDOW, MAX_WINDOW)
+}
+
+/// Window length the policy asks for, before the limits
+pub fn wanted_window_size(c: &SpectrumConfig, freq_hz: f32) -> f32 {
+    let sr = c.sample_rate as f32;
+    let r = c.wave_cycles_resolution;
+    let res = &c.resolution;
+    let f = freq_hz.max(1.0);
+    match res.policy {
+        ResolutionPolicy::Shelf => {
+            DftBin::window_size(r, sr, freq_hz, c.resolution_low_f_shelf_hz) as f32
+        }
+        ResolutionPolicy::ConstantQ => r * sr / f,
+        ResolutionPolicy::ConstantBandwidth => sr / res.bandwidth_hz.max(0.1),
+        ResolutionPolicy::Hybrid => r * sr / f.max(res.crossover_hz),
+        ResolutionPolicy::LatencyCap => {
+            let len = DftBin::window_size(r, sr, freq_hz, c.resolution_low_f_shelf_hz) as f32;
+            len.min(res.max_latency_ms * 0.001 * sr)
+        }
+        ResolutionPolicy::Table => res.table_cycles(f) * sr / f,
+    }
+}
+
+#[test]
+fn test_resolution_policies() {
+    let mut c = SpectrumConfig::new();
+    assert_eq!(
+        window_size(&c, 1000.0),
+        DftBin::window_size(16.0, 24000.0, 1000.0, 50.0)
+    );
+
+    c.resolution.policy = ResolutionPolicy::ConstantQ;
+    assert_eq!(window_size(&c, 1000.0), 384);
+    assert_eq!(window_size(&c, 2000.0), 192);
+
+    c.resolution.policy = ResolutionPolicy::ConstantBandwidth;
+    assert_eq!(window_size(&c, 100.0), 2400);
+    assert_eq!(window_size(&c, 5000.0), 2400);
+
+    c.resolution.policy = ResolutionPolicy::Hybrid;
+    assert_eq!(window_size(&c, 100.0), window_size(&c, 300.0));
+    assert_eq!(window_size(&c, 3000.0), 128);
+
+    c.resolution.policy = ResolutionPolicy::LatencyCap;
+    assert_eq!(window_size(&c, 40.0), 2400);
+
+    c.resolution.policy = ResolutionPolicy::Table;
+    c.resolution.table = ResolutionConfig::parse_table("100:4, 1000:40").unwrap();
+    assert_eq!(window_size(&c, 50.0), 1920);
+    assert_eq!(window_size(&c, 1000.0), 960);
+    assert!((c.resolution.table_cycles(316.23) - 22.0).abs() < 0.01);
+    assert!(ResolutionConfig::parse_table("100-4").is_err());
+    assert!(ResolutionConfig::parse_table("nan:3").is_err());
+    assert!(ResolutionConfig::parse_table("100:inf").is_err());
+}
@@ -2,9 +2,17 @@ extern crate lazy_static;
 
 use crate::spectrumapp::spectrumui::StateSnapshot;
 
+use super::distortion;
+use super::distortion::DistortionConfig;
+use super::distortion::DistortionReport;
+use super::noisefloor::NoiseFloorEstimator;
+use super::resolution;
+use super::resolution::ResolutionConfig;
 use super::spectrumui::RendererMsg;
 use super::PosColVertex;
 use lazy_static::lazy_static;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
 use std::sync::mpsc::Receiver;
 use std::sync::mpsc::Sender;
 use std::sync::Mutex;
@@ -479,12 +487,7 @@ impl DftBin {
         self.reinit_exact(
             freq,
             phase_shift_per_sample_to_fixed_point64(freq / sample_rate),
-            Self::window_size(
-                c.wave_cycles_resolution,
-                sample_rate as f32,
-                freq as f32,
-                c.resolution_low_f_shelf_hz,
-            ),
+            resolution::window_size(c, freq as f32),
             empty,
         );
     }
@@ -566,6 +569,20 @@ impl DftBin {
         self.partial_sums_pos = self.partial_sums_pos % self.length;
     }
 
+    /// Drops the window and refills it with the newest samples of `ring`,
+    /// a whole window if the ring has that much history
+    pub fn warm_start(&mut self, ring: &ChannelRing) {
+        for sum in self.partial_sums.iter_mut() {
+            *sum = Default::default();
+        }
+        self.state_sum_real = 0;
+        self.state_sum_imag = 0;
+        self.current_length = 0;
+
+        let n = self.length.min(ring.ring_length - 1);
+        self.advance(n, &ring.ring_samples, ring.ring_offset);
+    }
+
     #[allow(unused)]
     pub fn magnitude_squared_all(&self) -> f64 {
         let mut val_real = (self.state_sum_real / (self.length as i64)) as f64;
@@ -752,7 +769,8 @@ impl DftBin {
                     *(partial_sums.get_unchecked_mut(partial_sums_pos)) = c;
                 }
             }
-            current_phase += pulsation;
+            // wraps every 32 turns, the fixed point phase is taken modulo one turn
+            current_phase = current_phase.wrapping_add(pulsation);
         }
         self.partial_sums_pos = partial_sums_pos;
 
@@ -903,10 +921,28 @@ pub struct ChannelSWDFT {
 
     pub spectrum_bins: SpectrumBins,
     //pub measure_bins: VecDeque<MeasureBin>,
+    pub noise_floor: NoiseFloorEstimator,
+    /// THD/SINAD analysis of the dominant tone, DFT method only
+    pub distortion: Option<DistortionConfig>,
+    /// input samples (per channel, input sample rate) fed so far
+    pub sample_index: u64,
+    /// wall-clock capture time of the newest fed sample, ms
+    pub capture_ms: f64,
 }
 
-#[derive(Copy, Clone, TryFromPrimitive, IntoPrimitive, PartialEq)]
+#[derive(
+    Copy,
+    Clone,
+    Debug,
+    TryFromPrimitive,
+    IntoPrimitive,
+    PartialEq,
+    Serialize,
+    Deserialize,
+    clap::ValueEnum,
+)]
 #[repr(u8)]
+#[serde(rename_all = "snake_case")]
 pub enum WindowType {
     Rect,
     BlackmanNutall,
@@ -926,6 +962,45 @@ pub struct Collected {
     pub peaks: Option<Vec<SPeak>>,
     pub rendered: Option<Vec<PosColVertex>>,
     pub snapshot: StateSnapshot,
+    /// per-bin noise floor estimate, same scale as `spectrum` values
+    pub noise_floor: Option<Vec<f64>>,
+    /// per-bin complex values, only for the DFT method
+    pub complex: Option<Vec<ComplexF64>>,
+    /// counts collected frames, equal on channels that were fed the same blocks
+    pub frame_index: usize,
+    /// set when distortion analysis is on and a dominant tone was found
+    pub distortion: Option<DistortionReport>,
+    /// input sample index (input sample rate) of the newest sample in the frame
+    pub sample_index: u64,
+    /// wall-clock time that sample was captured, ms since the Unix epoch
+    pub capture_ms: f64,
+}
+
+#[cfg(test)]
+impl Collected {
+    /// A frame of just `values`, for tests of what consumes frames
+    pub fn from_values(values: &[f64], collect_frequency: usize) -> Self {
+        let meta = BinMeta::new();
+        Self {
+            cur_rolling_gain: 1.0,
+            spectrum: values.iter().map(|v| SSample::new(*v, &meta)).collect(),
+            peaks: None,
+            rendered: None,
+            snapshot: StateSnapshot {
+                current_algo: SpectrumBinsState::NC,
+                window_type: WindowType::BlackmanNutall,
+                collect_every: 1,
+                collect_frequency,
+                window_kernel_len: 1,
+            },
+            noise_floor: None,
+            complex: None,
+            frame_index: 0,
+            distortion: None,
+            sample_index: 0,
+            capture_ms: 0.0,
+        }
+    }
 }
 #[derive(Clone)]
 pub struct SColor {
@@ -950,6 +1025,17 @@ pub struct SSample {
     octave: f32,
 }
 
+impl SSample {
+    /// A sample colored like a bin at `meta`, for frames that weren't collected here
+    pub fn new(value: f64, meta: &BinMeta) -> Self {
+        Self {
+            value,
+            color: meta.color.clone(),
+            octave: meta.octave,
+        }
+    }
+}
+
 pub struct RawSSample {
     pub complex: ComplexI64,
     pub color: SColor,
@@ -967,7 +1053,8 @@ pub struct SPeak {
     pub alpha: f32,
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SpectrumConfig {
     pub sample_rate: u32,
     pub num_bins: u32,                  // 800
@@ -977,6 +1064,106 @@ pub struct SpectrumConfig {
     pub resolution_low_f_shelf_hz: f32, // 80.0
     pub subtraction_peaks: bool,
     pub collect_frequency: usize,
+    pub resolution: ResolutionConfig,
+}
+
+impl SpectrumConfig {
+    pub fn new() -> Self {
+        Self {
+            sample_rate: 24000,
+            num_bins: 500,
+            min_f: 40.0,
+            max_f: 12000.0,
+            wave_cycles_resolution: 16.0,
+            resolution_low_f_shelf_hz: 50.0,
+            subtraction_peaks: false,
+            collect_frequency: 5 * 60,
+            resolution: ResolutionConfig::new(),
+        }
+    }
+
+    /// The analysis runs at half of the input rate
+    pub fn input_rate(&self) -> f64 {
+        2.0 * self.sample_rate as f64
+    }
+
+    /// Seconds of input up to `sample_index`
+    pub fn input_seconds(&self, sample_index: u64) -> f64 {
+        sample_index as f64 / self.input_rate()
+    }
+}
+
+impl Default for SpectrumConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_warm_start() {
+    let config = SpectrumConfig {
+        num_bins: 100,
+        ..SpectrumConfig::new()
+    };
+    let mut swdft = ChannelSWDFT::new(&config);
+    swdft.spectrum_bins = ChannelSWDFT::make_spectrum_bins(0, &config);
+    swdft.should_colorize = false;
+
+    let signal: Vec<f32> = (0..48000)
+        .map(|n| (0.3 * (n as f64 * 0.05).sin() + 0.2 * (n as f64 * 0.7).sin()) as f32)
+        .collect();
+    for chunk in signal.chunks(512) {
+        swdft.process_input(chunk);
+    }
+    let settled = swdft.collect_spectrum().spectrum;
+
+    // fresh bins, as after a zoom or a method switch
+    swdft.spectrum_bins = ChannelSWDFT::make_spectrum_bins(0, &config);
+    swdft.warm_start_bins();
+    let warm = swdft.collect_spectrum().spectrum;
+
+    // bins restart at another phase, sine table rounding differs a bit
+    let max = settled.iter().map(|s| s.value).fold(0.0, f64::max);
+    for (a, b) in settled.iter().zip(warm.iter()) {
+        assert!((a.value - b.value).abs() <= 1e-3 * max);
+    }
+}
+
+#[test]
+fn test_long_block() {
+    let config = SpectrumConfig {
+        num_bins: 100,
+        ..SpectrumConfig::new()
+    };
+    let mut swdft = ChannelSWDFT::new(&config);
+    swdft.spectrum_bins = ChannelSWDFT::make_spectrum_bins(0, &config);
+    swdft.should_colorize = false;
+
+    // one block takes every bin's phase around many times
+    let signal: Vec<f32> = (0..16384)
+        .map(|n| (0.5 * (std::f64::consts::TAU * 9000.0 * n as f64 / 48000.0).sin()) as f32)
+        .collect();
+    swdft.process_input(&signal);
+    let spectrum = swdft.collect_spectrum().spectrum;
+
+    let max_index = (0..spectrum.len())
+        .max_by(|&a, &b| spectrum[a].value.total_cmp(&spectrum[b].value))
+        .unwrap();
+    if let SpectrumBins::DFT(bins) = &swdft.spectrum_bins {
+        let f = bins[max_index].bin.frequency64(config.sample_rate as f64);
+        assert!((f - 9000.0).abs() < 9000.0 * 0.05, "{}", f);
+    }
+}
+
+#[test]
+fn test_colorize_edges() {
+    // the averaging window hangs over both ends of the spectrum
+    let mut meta = BinMeta::new();
+    meta.color = SColor::new(0xff2080c0);
+    let mut spectrum: Vec<SSample> = (0..40).map(|_| SSample::new(1.0, &meta)).collect();
+    ChannelSWDFT::colorize_spectrum(&mut spectrum, &Vec::new());
+    let middle = spectrum[20].color.rgba;
+    assert!(spectrum.iter().all(|s| s.color.rgba == middle));
 }
 
 #[test]
@@ -1065,12 +1252,7 @@ impl ChannelSWDFT {
 
         let sample_rate = c.sample_rate as f64;
 
-        let window_size = DftBin::window_size(
-            c.wave_cycles_resolution,
-            sample_rate as f32,
-            freq_hz as f32,
-            c.resolution_low_f_shelf_hz,
-        );
+        let window_size = resolution::window_size(c, freq_hz as f32);
         // NC frequency delta
         let delta_freq = sample_rate / (window_size as f64);
 
@@ -1088,6 +1270,8 @@ impl ChannelSWDFT {
         //self.config = config.clone();
 
         Self::reinit_spectrum(&mut self.spectrum_bins, &self.config);
+        self.warm_start_bins();
+        self.noise_floor.reset();
         let _res = self
             .collected_spectrums_sender
             .lock()
@@ -1095,6 +1279,26 @@ impl ChannelSWDFT {
             .send(RendererMsg::ConfigUpdate(self.config.clone()));
     }
 
+    /// New or retuned bins would take a whole window to settle,
+    /// replay the ring's history into them instead
+    pub fn warm_start_bins(&mut self) {
+        use rayon::prelude::*;
+        let ring = &self.rings[1];
+        match &mut self.spectrum_bins {
+            SpectrumBins::DFT(dft_bins) => {
+                dft_bins.par_iter_mut().for_each(|bin| {
+                    bin.bin.warm_start(ring);
+                });
+            }
+            SpectrumBins::NC(nc_bins) => {
+                nc_bins.par_iter_mut().for_each(|bin| {
+                    bin.bina.warm_start(ring);
+                    bin.binb.warm_start(ring);
+                });
+            }
+        }
+    }
+
     pub fn reinit_spectrum(spectrum_bins: &mut SpectrumBins, config: &SpectrumConfig) {
         match spectrum_bins {
             SpectrumBins::DFT(dft_bins) => {
@@ -1160,6 +1364,10 @@ impl ChannelSWDFT {
             collected_counter: 0,
             paused: false,
             should_colorize: true,
+            noise_floor: NoiseFloorEstimator::new(),
+            distortion: None,
+            sample_index: 0,
+            capture_ms: 0.0,
         };
         s.set_collect_frequency(config.collect_frequency);
         //s.reinit_my_spectrum(&config);
@@ -1223,6 +1431,7 @@ impl ChannelSWDFT {
                 let mut bin = bin.unwrap();
 
                 Self::init_regular_bin(&self.config, &mut bin, freq);
+                bin.bin.warm_start(&self.rings[1]);
 
                 Self::push_backfront(dft_bins, bin, !left);
             }
@@ -1231,6 +1440,8 @@ impl ChannelSWDFT {
                 let mut bin = bin.unwrap();
 
                 Self::init_nc_bin(&self.config, &mut bin, freq);
+                bin.bina.warm_start(&self.rings[1]);
+                bin.binb.warm_start(&self.rings[1]);
 
                 Self::push_backfront(nc_bins, bin, !left);
             }
@@ -1283,6 +1494,7 @@ impl ChannelSWDFT {
             c.min_f = min_f;
             c.max_f = max_f;
         }
+        self.noise_floor.reset();
 
         let _res = self
             .collected_spectrums_sender
@@ -1438,30 +1650,39 @@ impl ChannelSWDFT {
 
         let subtraction_peaks = self.config.subtraction_peaks;
         use rayon::prelude::*;
-        let (mut spectrum, mut peaks) = rayon::join(
+        let ((mut spectrum, complex), mut peaks) = rayon::join(
             || {
-                let spectrum: Vec<SSample> = match &self.spectrum_bins {
+                let spectrum: (Vec<SSample>, Option<Vec<ComplexF64>>) = match &self.spectrum_bins {
                     SpectrumBins::DFT(dft_bins) => {
-                        dft_bins
+                        let (spectrum, complex) = dft_bins
                             .par_iter()
                             .map(|s| {
-                                let (mut magnitude, _) = s.bin.sum_magnitude_kerneled(kernel);
+                                let (mut magnitude, mut complex) =
+                                    s.bin.sum_magnitude_kerneled(kernel);
 
                                 magnitude = magnitude.sqrt();
                                 magnitude /= kernel_sum;
                                 magnitude *= s.meta.a_weight;
                                 //val /= s.length as f64;
 
-                                SSample {
-                                    value: magnitude,
-                                    color: s.meta.color.clone(),
-                                    octave: s.meta.octave,
-                                }
+                                // not A-weighted, cross-channel ratios don't need it
+                                complex.re /= kernel_sum;
+                                complex.im /= kernel_sum;
+
+                                (
+                                    SSample {
+                                        value: magnitude,
+                                        color: s.meta.color.clone(),
+                                        octave: s.meta.octave,
+                                    },
+                                    complex,
+                                )
                             })
-                            .collect()
+                            .unzip();
+                        (spectrum, Some(complex))
                     }
                     SpectrumBins::NC(nc_bins) => {
-                        nc_bins
+                        let spectrum = nc_bins
                             .par_iter()
                             .map(|s| {
                                 // NC method
@@ -1501,7 +1722,8 @@ impl ChannelSWDFT {
                                     octave: s.meta.octave,
                                 }
                             })
-                            .collect()
+                            .collect();
+                        (spectrum, None)
                     }
                 };
 
@@ -1510,21 +1732,7 @@ impl ChannelSWDFT {
             || {
                 if subtraction_peaks {
                     if let SpectrumBins::DFT(dft_bins) = &self.spectrum_bins {
-                        let mut raw_spectrum: Vec<RawSSample> = dft_bins
-                            .par_iter()
-                            .map(|s| {
-                                let complex = s.bin.sum_ranged_all();
-
-                                RawSSample {
-                                    complex,
-                                    color: s.meta.color.clone(),
-                                    //octave: s.octave,
-                                    inv_lengthf: 1.0 / s.bin.lengthf,
-                                    a_weight: (s.meta.a_weight * s.meta.a_weight * 10000.0) as i64,
-                                    length: s.bin.length as u32,
-                                }
-                            })
-                            .collect();
+                        let mut raw_spectrum = Self::raw_spectrum(dft_bins);
 
                         Some(self.subtraction_peaks(&mut raw_spectrum))
                     } else {
@@ -1564,15 +1772,50 @@ impl ChannelSWDFT {
             window_kernel_len: self.collector.kernel.len(),
         };
 
+        let distortion = match (&self.distortion, &self.spectrum_bins) {
+            (Some(config), SpectrumBins::DFT(dft_bins)) => distortion::analyze(
+                config,
+                dft_bins,
+                Self::raw_spectrum(dft_bins),
+                self.config.sample_rate,
+            ),
+            _ => None,
+        };
+
         Collected {
             cur_rolling_gain: self.rolling_gain,
             spectrum,
             peaks,
             rendered: None,
             snapshot,
+            noise_floor: None,
+            complex,
+            frame_index: self.collected_counter,
+            distortion,
+            sample_index: self.sample_index,
+            capture_ms: self.capture_ms,
         }
     }
 
+    pub fn raw_spectrum(dft_bins: &VecDeque<RegularBin>) -> Vec<RawSSample> {
+        use rayon::prelude::*;
+        dft_bins
+            .par_iter()
+            .map(|s| {
+                let complex = s.bin.sum_ranged_all();
+
+                RawSSample {
+                    complex,
+                    color: s.meta.color.clone(),
+                    //octave: s.octave,
+                    inv_lengthf: 1.0 / s.bin.lengthf,
+                    a_weight: (s.meta.a_weight * s.meta.a_weight * 10000.0) as i64,
+                    length: s.bin.length as u32,
+                }
+            })
+            .collect()
+    }
+
     pub fn subtraction_peaks(&self, spectrum: &mut Vec<RawSSample>) -> Vec<SPeak> {
         let mut peaks = Vec::new();
 
@@ -1625,6 +1868,7 @@ impl ChannelSWDFT {
                 subtracted_phase,
                 spectrum,
                 &dft_bins,
+                120,
             );
             peak
         } else {
@@ -1656,6 +1900,7 @@ impl ChannelSWDFT {
 
     // integrate sin(at+c)cos(ct+d)dt
     #[inline]
+    #[allow(unused)]
     pub fn integral_cos(t1: f64, t2: f64, a: f64, b: f64, c: f64, d: f64) -> f64 {
         let diff = a - c;
         let phases = b - d;
@@ -1666,6 +1911,7 @@ impl ChannelSWDFT {
     }
     // integrate sin(at+c)sin(ct+d)dt
     #[inline]
+    #[allow(unused)]
     pub fn integral_sin(t1: f64, t2: f64, a: f64, b: f64, c: f64, d: f64) -> f64 {
         let diff = a - c;
         let phases = b - d;
@@ -1730,18 +1976,16 @@ impl ChannelSWDFT {
         subtracted_phase: f64,
         spectrum: &mut Vec<RawSSample>,
         bins: &VecDeque<RegularBin>,
+        half_range: i32,
     ) {
-        let subtracted_f = fixed_point_to_phase_shift_per_sample(subtracted_pulse) as f64;
-        let subtracted_f = subtracted_f;
-
-        let fixedp_phase_mul = 1.0 / (PHASE_DITHER as f64 * FIXED_POINT_FRACTIONAL as f64);
-
-        const TAU: f64 = std::f64::consts::TAU;
+        let subtracted_f = fixed_point_to_phase_shift_per_sample64(subtracted_pulse);
+        // sums grow with the length, mag was measured over the subtracted bin
+        let inv_lengthf = bins[max_index as usize].bin.inv_lengthf;
 
         use rayon::prelude::*;
 
-        let ai = max_index - 120;
-        let bi = max_index + 120;
+        let ai = max_index - half_range;
+        let bi = max_index + half_range;
 
         let ai = ai.max(0) as usize;
         let bi = bi.min(bins.len() as i32) as usize;
@@ -1756,20 +2000,10 @@ impl ChannelSWDFT {
                     s.complex.re = 0;
                     s.complex.im = 0;
                 } else {
-                    let bin = &bins[i];
-                    let ll = bin.bin.lengthf;
-
-                    let f1 = fixed_point_to_phase_shift_per_sample(bin.bin.pulsation) as f64;
-                    let f2 = subtracted_f;
+                    let response = Self::sine_response(&bins[i], subtracted_f, subtracted_phase);
 
-                    let p1 = -(bin.bin.current_phase as f64 * fixedp_phase_mul);
-                    let p2 = subtracted_phase;
-
-                    let aac = Self::integral_cos(0.0, ll, TAU * f1, TAU * p1, TAU * f2, TAU * p2);
-                    let aas = Self::integral_sin(0.0, ll, TAU * f1, TAU * p1, TAU * f2, TAU * p2);
-
-                    let ss = bin.bin.inv_lengthf * aas * mag * 2.0;
-                    let cc = bin.bin.inv_lengthf * aac * mag * 2.0;
+                    let ss = inv_lengthf * response.re * mag;
+                    let cc = inv_lengthf * response.im * mag;
 
                     s.complex.re -= (ss * QUANTIZER_LEVELS_F64) as i64;
                     s.complex.im -= (cc * QUANTIZER_LEVELS_F64) as i64;
@@ -1777,6 +2011,46 @@ impl ChannelSWDFT {
             });
     }
 
+    /// Sums of `bin` for a sine of `subtracted_f` cycles per sample with
+    /// amplitude 2 and `subtracted_phase` in cycles, as subtract_peak measures it
+    pub fn sine_response(bin: &RegularBin, subtracted_f: f64, subtracted_phase: f64) -> ComplexF64 {
+        const TAU: f64 = std::f64::consts::TAU;
+
+        let omega = TAU * subtracted_f;
+        let bin_omega = TAU * fixed_point_to_phase_shift_per_sample64(bin.bin.pulsation);
+
+        // phases at the newest sample, current_phase already points at the next one
+        let phase = -TAU * subtracted_phase - omega;
+        let bin_phase = bin.bin.current_phase as f64 * FIXED_POINT_PHASE_MULTIPLIER_TAU - bin_omega;
+
+        // sum_ranged_all leaves out the oldest partial sum
+        let length = bin.bin.current_length.max(2) - 1;
+
+        // the sine is half at +omega and half at -omega
+        let image = Self::geometric_sum(-(omega + bin_omega), length).rotate(phase + bin_phase);
+        let direct = Self::geometric_sum(omega - bin_omega, length).rotate(bin_phase - phase);
+        ComplexF64 {
+            re: image.re + direct.re,
+            im: image.im + direct.im,
+        }
+    }
+
+    /// Σ e^(jkx), k = 0..length
+    fn geometric_sum(x: f64, length: usize) -> ComplexF64 {
+        let l = length as f64;
+        let half = 0.5 * x;
+        let den = half.sin();
+        if den.abs() < 1e-12 {
+            return ComplexF64 { re: l, im: 0.0 };
+        }
+        let m = (l * half).sin() / den;
+        let arg = half * (l - 1.0);
+        ComplexF64 {
+            re: m * arg.cos(),
+            im: m * arg.sin(),
+        }
+    }
+
     /// Old algoritgm of peak subtraction
     #[allow(unused)]
     pub fn subtract_sine2(
@@ -1844,7 +2118,10 @@ impl ChannelSWDFT {
             let val = *s;
 
             //if val > 0.000001 && val < last_val && last_val > lastlast_val {
-            if val < last_val && last_val > lastlast_val {
+            if val < last_val
+                && last_val > lastlast_val
+                && self.noise_floor.is_above(i, spectrum[i].value)
+            {
                 let octave = spectrum[i].octave; //self.spectrum_bins[i].octave;
 
                 let real_val = spectrum[i].value;
@@ -1997,7 +2274,8 @@ impl ChannelSWDFT {
 
                 spectrum[i].color.rgba = calc_rgba;
 
-                let a = i - half_range;
+                // wraps before the first bin, get() skips it
+                let a = i.wrapping_sub(half_range);
                 let b = i + half_range;
 
                 spectrum.get(a).map(|s| del_sample(s, &mut avg));
@@ -2211,7 +2489,8 @@ impl ChannelSWDFT {
         power_sum.sqrt() / (samples.len() as f64)
     }
 
-    pub fn on_input(&mut self, input: &[f32]) {
+    /// `capture_ms` is the wall-clock time of the last sample of `input`
+    pub fn on_input(&mut self, input: &[f32], capture_ms: f64) {
         //let len = self.last_samples.len();
 
         const SPECTRUM_POWER_AGC: bool = true;
@@ -2230,12 +2509,18 @@ impl ChannelSWDFT {
                 let (now_process, next) = remaining.split_at(rem);
                 self.process_input(now_process);
                 remaining = next;
+                self.sample_index += rem as u64;
+                let input_rate = self.config.input_rate();
+                self.capture_ms = capture_ms - 1000.0 * remaining.len() as f64 / input_rate;
                 self.samples_to_collect_remaining -= rem;
 
                 if self.samples_to_collect_remaining <= 0 {
                     self.samples_to_collect_remaining = self.collect_every;
 
-                    let spectrum = self.collect_spectrum();
+                    let mut spectrum = self.collect_spectrum();
+                    self.noise_floor
+                        .update(&spectrum.spectrum, self.collect_frequency);
+                    spectrum.noise_floor = Some(self.noise_floor.floor().to_vec());
                     if SPECTRUM_POWER_AGC
                         && self.collected_counter % (self.collect_frequency / 60) == 0
                     {
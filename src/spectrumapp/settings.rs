@@ -0,0 +1,465 @@
+use std::path::Path;
+use std::path::PathBuf;
+
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+
+use super::keymap::Binding;
+use super::resolution;
+use super::sbswdft::ChannelSWDFT;
+use super::sbswdft::SpectrumBins;
+use super::sbswdft::SpectrumConfig;
+use super::sbswdft::WindowType;
+use super::traces::TracesConfig;
+use super::transfer;
+use super::GraphType;
+
+// Analyzer and view settings kept between runs, as JSON in
+// ~/.config/sbsdft/settings.json unless --config points elsewhere.
+// Fields missing from the file, e.g. written by an older version,
+// keep their defaults, and so do values that make no sense.
+// Presets on the 1-9 keys live in the same file; a file without
+// "presets" gets the built-in ones. The range saved is the one asked for,
+// even while a low input rate holds the analyzer below it.
+
+/// most bins a file, a preset or --bins may ask for
+pub const MAX_BINS: u32 = 20000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SpectrumMethod {
+    Dft,
+    Nc,
+}
+
+impl SpectrumMethod {
+    /// kind of ChannelSWDFT::make_spectrum_bins
+    pub fn kind(&self) -> u8 {
+        match self {
+            SpectrumMethod::Dft => 0,
+            SpectrumMethod::Nc => 1,
+        }
+    }
+
+    pub fn of(bins: &SpectrumBins) -> Self {
+        match bins {
+            SpectrumBins::DFT(_) => SpectrumMethod::Dft,
+            SpectrumBins::NC(_) => SpectrumMethod::Nc,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// its sample rate follows the input and isn't restored
+    pub spectrum: SpectrumConfig,
+    pub window_type: WindowType,
+    pub subdivisions: i32,
+    pub method: SpectrumMethod,
+    pub colorize: bool,
+    pub graph_type: GraphType,
+    pub logarithmic_scale: bool,
+    /// of the peak-hold trace, dB per second
+    pub peak_decay_db_per_s: f64,
+    /// N of the average trace
+    pub average_frames: usize,
+    /// time constant of the transfer function averaging, seconds
+    pub transfer_averaging_s: f64,
+    /// bound to the 1-9 keys in order
+    pub presets: Vec<Preset>,
+    /// changes to the default keymap
+    pub key_bindings: Vec<Binding>,
+}
+
+/// A frequency range the input's sample rate holds below the one asked for
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RateLimit {
+    /// min_f, max_f asked for
+    pub asked: (f32, f32),
+    /// what the rate allows of it
+    pub got: (f32, f32),
+}
+
+/// A named analyzer and view setup, switched to as a whole
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Preset {
+    pub name: String,
+    /// its sample rate is ignored, the input decides it
+    pub spectrum: SpectrumConfig,
+    pub window_type: WindowType,
+    pub subdivisions: i32,
+    pub method: SpectrumMethod,
+    pub graph_type: GraphType,
+    pub logarithmic_scale: bool,
+}
+
+impl Preset {
+    pub fn new() -> Self {
+        let settings = Settings::new_without_presets();
+        Self {
+            name: "unnamed".to_string(),
+            spectrum: settings.spectrum,
+            window_type: settings.window_type,
+            subdivisions: settings.subdivisions,
+            method: settings.method,
+            graph_type: settings.graph_type,
+            logarithmic_scale: settings.logarithmic_scale,
+        }
+    }
+
+    /// Bass detail, full-range music and speech band
+    pub fn builtin() -> Vec<Self> {
+        let mut bass = Self::new();
+        bass.name = "bass detail".to_string();
+        bass.spectrum.min_f = 20.0;
+        bass.spectrum.max_f = 320.0;
+        bass.spectrum.num_bins = 300;
+        bass.spectrum.wave_cycles_resolution = 24.0;
+        bass.spectrum.resolution_low_f_shelf_hz = 20.0;
+
+        let mut music = Self::new();
+        music.name = "music".to_string();
+
+        let mut speech = Self::new();
+        speech.name = "speech".to_string();
+        speech.spectrum.min_f = 80.0;
+        speech.spectrum.max_f = 8000.0;
+        speech.spectrum.num_bins = 400;
+        speech.spectrum.wave_cycles_resolution = 10.0;
+        speech.graph_type = GraphType::Fill;
+
+        vec![bass, music, speech]
+    }
+}
+
+impl Default for Preset {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Settings {
+    pub fn new() -> Self {
+        Self {
+            presets: Preset::builtin(),
+            ..Self::new_without_presets()
+        }
+    }
+
+    fn new_without_presets() -> Self {
+        let traces = TracesConfig::new();
+        Self {
+            spectrum: SpectrumConfig::new(),
+            window_type: WindowType::BlackmanNutall,
+            subdivisions: 6,
+            method: SpectrumMethod::Nc,
+            colorize: true,
+            graph_type: GraphType::Line,
+            logarithmic_scale: false,
+            peak_decay_db_per_s: traces.peak_decay_db_per_s,
+            average_frames: traces.average_frames,
+            transfer_averaging_s: transfer::DEFAULT_AVERAGING_S,
+            presets: Vec::new(),
+            key_bindings: Vec::new(),
+        }
+    }
+
+    /// $XDG_CONFIG_HOME or ~/.config, %APPDATA% on Windows
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(dir.join("sbsdft").join("settings.json"))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut s: Self = serde_json::from_str(text).map_err(|err| err.to_string())?;
+        s.fix();
+        Ok(s)
+    }
+
+    /// None if there is no file yet
+    pub fn load(path: &Path) -> Result<Option<Self>, String> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text).map(Some),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let text = serde_json::to_string_pretty(self).unwrap();
+        std::fs::write(path, text + "\n")
+    }
+
+    /// Resets what the analyzer can't run with to the defaults
+    fn fix(&mut self) {
+        let defaults = Self::new_without_presets();
+        Self::fix_spectrum(&mut self.spectrum);
+        if self.subdivisions < 1 {
+            self.subdivisions = defaults.subdivisions;
+        }
+        if !(self.peak_decay_db_per_s > 0.0 && self.peak_decay_db_per_s.is_finite()) {
+            self.peak_decay_db_per_s = defaults.peak_decay_db_per_s;
+        }
+        if self.average_frames < 1 {
+            self.average_frames = defaults.average_frames;
+        }
+        if !(self.transfer_averaging_s > 0.0 && self.transfer_averaging_s.is_finite()) {
+            self.transfer_averaging_s = defaults.transfer_averaging_s;
+        }
+        for preset in &mut self.presets {
+            Self::fix_spectrum(&mut preset.spectrum);
+            if preset.subdivisions < 1 {
+                preset.subdivisions = defaults.subdivisions;
+            }
+        }
+    }
+
+    fn fix_spectrum(c: &mut SpectrumConfig) {
+        let d = &SpectrumConfig::new();
+        if !(2..=MAX_BINS).contains(&c.num_bins) {
+            c.num_bins = d.num_bins;
+        }
+        if !(c.min_f > 0.0 && c.min_f < c.max_f) {
+            c.min_f = d.min_f;
+            c.max_f = d.max_f;
+        }
+        if !(c.wave_cycles_resolution > 0.0) {
+            c.wave_cycles_resolution = d.wave_cycles_resolution;
+        }
+        if !(c.resolution_low_f_shelf_hz >= 0.0) {
+            c.resolution_low_f_shelf_hz = d.resolution_low_f_shelf_hz;
+        }
+        if c.collect_frequency == 0 {
+            c.collect_frequency = d.collect_frequency;
+        }
+    }
+
+    /// What the analyzer can't run with at its sample rate
+    pub fn check_spectrum(c: &SpectrumConfig) -> Result<(), String> {
+        if c.collect_frequency > c.sample_rate as usize / 2 {
+            return Err(format!(
+                "collect frequency {} is above {}, a spectrum every other sample at {} Hz",
+                c.collect_frequency,
+                c.sample_rate / 2,
+                c.sample_rate
+            ));
+        }
+        // the longest window is warm started from the ring
+        let longest = resolution::wanted_window_size(c, c.min_f);
+        if longest > resolution::MAX_WINDOW as f32 {
+            return Err(format!(
+                "the window at min_f {} Hz would be {:.0} samples, the ring holds {}, raise min_f or lower the cycles",
+                c.min_f,
+                longest,
+                resolution::MAX_WINDOW
+            ));
+        }
+        Ok(())
+    }
+
+    /// check_spectrum once at `input_rate`
+    pub fn check_at(&self, input_rate: u32) -> Result<(), String> {
+        let mut settings = self.clone();
+        settings.set_input_rate(input_rate);
+        Self::check_spectrum(&settings.spectrum)
+    }
+
+    /// Takes over everything `preset` holds, the sample rate and colors stay
+    pub fn apply_preset(&mut self, preset: &Preset) -> Option<RateLimit> {
+        let input_rate = self.spectrum.sample_rate * 2;
+        self.spectrum = preset.spectrum.clone();
+        self.window_type = preset.window_type;
+        self.subdivisions = preset.subdivisions;
+        self.method = preset.method;
+        self.graph_type = preset.graph_type;
+        self.logarithmic_scale = preset.logarithmic_scale;
+        self.set_input_rate(input_rate)
+    }
+
+    /// Runs the analysis at half of `input_rate`, pulls the range below its Nyquist frequency
+    pub fn set_input_rate(&mut self, input_rate: u32) -> Option<RateLimit> {
+        let c = &mut self.spectrum;
+        c.sample_rate = input_rate / 2;
+        let nyquist = c.sample_rate as f32 / 2.0;
+        if c.max_f <= nyquist {
+            return None;
+        }
+        let asked = (c.min_f, c.max_f);
+        c.max_f = nyquist;
+        c.min_f = c.min_f.min(nyquist / 2.0);
+        Some(RateLimit {
+            asked,
+            got: (c.min_f, c.max_f),
+        })
+    }
+
+    /// Puts back the range asked for, unless it was changed since `limit`
+    pub fn restore_range(&mut self, limit: Option<RateLimit>) {
+        let c = &mut self.spectrum;
+        if let Some(limit) = limit {
+            if (c.min_f, c.max_f) == limit.got {
+                (c.min_f, c.max_f) = limit.asked;
+            }
+        }
+    }
+
+    /// Window, method and colors of a channel already on `spectrum`
+    pub fn apply_to_channel(&self, dft: &mut ChannelSWDFT) {
+        dft.collector = ChannelSWDFT::init_collector(self.subdivisions, self.window_type);
+        dft.should_colorize = self.colorize;
+        if SpectrumMethod::of(&dft.spectrum_bins) != self.method {
+            dft.spectrum_bins = ChannelSWDFT::make_spectrum_bins(self.method.kind(), &dft.config);
+        }
+    }
+
+    /// Moves a running channel over to `spectrum`, with fresh bins even when
+    /// their number or method didn't change
+    pub fn retune_channel(&self, dft: &mut ChannelSWDFT) {
+        dft.config = self.spectrum.clone();
+        dft.set_collect_frequency(self.spectrum.collect_frequency);
+        dft.spectrum_bins = ChannelSWDFT::make_spectrum_bins(self.method.kind(), &dft.config);
+        self.apply_to_channel(dft);
+        dft.reinit_my_spectrum();
+    }
+
+    /// What `dft` runs with, the view settings are left as they are
+    pub fn capture_channel(&mut self, dft: &ChannelSWDFT) {
+        self.spectrum = dft.config.clone();
+        self.spectrum.collect_frequency = dft.collect_frequency;
+        self.window_type = dft.collector.windowtype;
+        self.method = SpectrumMethod::of(&dft.spectrum_bins);
+        self.colorize = dft.should_colorize;
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[test]
+fn test_settings() {
+    let mut s = Settings::new();
+    s.spectrum.min_f = 100.0;
+    s.spectrum.max_f = 5000.0;
+    s.spectrum.resolution.policy = super::resolution::ResolutionPolicy::ConstantQ;
+    s.window_type = WindowType::LogNormal;
+    s.graph_type = GraphType::Fill;
+    let text = serde_json::to_string(&s).unwrap();
+    assert_eq!(Settings::parse(&text), Ok(s.clone()));
+
+    // an older file, and one with nonsense in it
+    let old = Settings::parse(r#"{"spectrum": {"min_f": 20.0}, "colorize": false}"#).unwrap();
+    assert_eq!(old.spectrum.min_f, 20.0);
+    assert_eq!(old.spectrum.num_bins, SpectrumConfig::new().num_bins);
+    assert!(!old.colorize);
+    assert_eq!(old.window_type, WindowType::BlackmanNutall);
+    let bad = r#"{"spectrum": {"min_f": 900.0, "max_f": 100.0, "collect_frequency": 0}, "subdivisions": -2}"#;
+    let bad = Settings::parse(bad).unwrap();
+    assert_eq!(bad.spectrum.min_f, 40.0);
+    assert_eq!(bad.spectrum.collect_frequency, 300);
+    assert_eq!(bad.subdivisions, 6);
+    let huge = r#"{"spectrum": {"num_bins": 5000000, "resolution_low_f_shelf_hz": -10.0},
+        "presets": [{"spectrum": {"num_bins": 5000000}}]}"#;
+    let huge = Settings::parse(huge).unwrap();
+    assert_eq!(huge.spectrum.num_bins, 500);
+    assert_eq!(huge.spectrum.resolution_low_f_shelf_hz, 50.0);
+    assert_eq!(huge.presets[0].spectrum.num_bins, 500);
+    let traces = Settings::parse(r#"{"peak_decay_db_per_s": 5.0, "average_frames": 8}"#).unwrap();
+    assert_eq!(
+        (traces.peak_decay_db_per_s, traces.average_frames),
+        (5.0, 8)
+    );
+    let bad = r#"{"peak_decay_db_per_s": -1.0, "average_frames": 0, "transfer_averaging_s": 0.0}"#;
+    let bad = Settings::parse(bad).unwrap();
+    assert_eq!((bad.peak_decay_db_per_s, bad.average_frames), (20.0, 32));
+    assert_eq!(bad.transfer_averaging_s, 1.0);
+    assert!(Settings::parse(r#"{"method": "fft"}"#).is_err());
+    // parses, but the analyzer can't run it
+    let long = r#"{"spectrum": {"min_f": 1.0, "wave_cycles_resolution": 200.0}}"#;
+    let long = Settings::parse(long).unwrap();
+    assert!(long.check_at(48000).unwrap_err().contains("ring holds"));
+    assert_eq!(Settings::new().check_at(48000), Ok(()));
+
+    // the analysis follows the input's rate, and goes back up with it
+    let limit = s.set_input_rate(16000);
+    let config = s.spectrum.clone();
+    assert_eq!(
+        (config.sample_rate, config.max_f, config.min_f),
+        (8000, 4000.0, 100.0)
+    );
+    let mut raised = s.clone();
+    raised.restore_range(limit);
+    assert_eq!(raised.set_input_rate(48000), None);
+    assert_eq!(
+        (raised.spectrum.min_f, raised.spectrum.max_f),
+        (100.0, 5000.0)
+    );
+    // not when the range was changed in between
+    raised.spectrum.max_f = 3000.0;
+    raised.restore_range(limit);
+    assert_eq!(raised.spectrum.max_f, 3000.0);
+
+    let mut dft = ChannelSWDFT::new(&config);
+    s.method = SpectrumMethod::Dft;
+    s.colorize = false;
+    s.apply_to_channel(&mut dft);
+    dft.set_collect_frequency(120);
+    let mut captured = Settings::new();
+    captured.capture_channel(&dft);
+    assert_eq!(captured.method, SpectrumMethod::Dft);
+    assert_eq!(captured.window_type, WindowType::LogNormal);
+    assert!(!captured.colorize);
+    assert_eq!(captured.spectrum.collect_frequency, 120);
+
+    let path = std::env::temp_dir().join(format!("sbsdft_settings_{}", std::process::id()));
+    let file = path.join("settings.json");
+    assert_eq!(Settings::load(&file), Ok(None));
+    s.save(&file).unwrap();
+    assert_eq!(Settings::load(&file), Ok(Some(s)));
+    std::fs::remove_dir_all(&path).unwrap();
+}
+
+#[test]
+fn test_presets() {
+    let s = Settings::parse("{}").unwrap();
+    assert_eq!(s.presets, Preset::builtin());
+    let text = r#"{"presets": [{"name": "hum", "spectrum": {"min_f": 45.0, "max_f": 200.0,
+        "num_bins": 0}, "method": "dft"}, {"name": "all", "spectrum": {"max_f": 20000.0}}]}"#;
+    let s = Settings::parse(text).unwrap();
+    assert_eq!(s.presets.len(), 2);
+    let hum = &s.presets[0];
+    assert_eq!((hum.name.as_str(), hum.spectrum.min_f), ("hum", 45.0));
+    assert_eq!(hum.spectrum.num_bins, SpectrumConfig::new().num_bins);
+    assert_eq!(hum.window_type, WindowType::BlackmanNutall);
+
+    // keeps running at the input's rate, pulled below its Nyquist frequency
+    let mut settings = Settings::new();
+    settings.colorize = false;
+    settings.set_input_rate(32000);
+    settings.apply_preset(&hum);
+    assert_eq!(settings.method, SpectrumMethod::Dft);
+    assert_eq!(settings.spectrum.sample_rate, 16000);
+    assert!(!settings.colorize);
+    let limit = settings.apply_preset(&s.presets[1]);
+    assert_eq!(limit.map(|l| l.asked.1), Some(20000.0));
+    assert_eq!(settings.spectrum.max_f, 8000.0);
+
+    let mut dft = ChannelSWDFT::new(&Settings::new().spectrum);
+    settings.retune_channel(&mut dft);
+    assert_eq!(dft.config, settings.spectrum);
+    assert_eq!(SpectrumMethod::of(&dft.spectrum_bins), SpectrumMethod::Nc);
+    match &dft.spectrum_bins {
+        SpectrumBins::NC(bins) => assert_eq!(bins.len(), settings.spectrum.num_bins as usize),
+        SpectrumBins::DFT(_) => unreachable!(),
+    }
+}
@@ -0,0 +1,271 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use super::myvertex::PosColTexVertex;
+use super::myvertex::PosColVertex;
+use super::texture::KRGBAImage;
+
+// CPU rasterizer for what SpectrumUI::render produces, for machines without
+// a GPU. It follows the two wgpu pipelines:
+// - PosColVertex: quads of 4 vertices in 1/8 pixels, triangles (0, 1, 3) and
+//   (3, 2, 1), alpha blended
+// - PosColTexVertex: triangles in pixels, the font atlas sampled nearest,
+//   texels with alpha < 0.5 discarded, the rest written opaque
+// Blending is done in linear light and stored sRGB encoded, like on the
+// sRGB surface the window normally gets.
+
+pub struct SoftRaster {
+    pub dx: u32,
+    pub dy: u32,
+    /// linear rgb
+    pixels: Vec<[f32; 3]>,
+    font: KRGBAImage,
+    /// sRGB to linear
+    decode: [f32; 256],
+}
+
+impl SoftRaster {
+    pub fn new(dx: u32, dy: u32, font: KRGBAImage) -> Self {
+        let mut decode = [0.0; 256];
+        for (i, d) in decode.iter_mut().enumerate() {
+            *d = srgb_to_linear(i as f32 / 255.0);
+        }
+        Self {
+            dx,
+            dy,
+            pixels: vec![[0.0; 3]; (dx * dy) as usize],
+            font,
+            decode,
+        }
+    }
+
+    pub fn resize(&mut self, dx: u32, dy: u32) {
+        self.dx = dx;
+        self.dy = dy;
+        self.pixels = vec![[0.0; 3]; (dx * dy) as usize];
+    }
+
+    /// Clears to black and draws a frame, like one render pass
+    pub fn render(&mut self, pc: &[PosColVertex], pct: &[PosColTexVertex]) {
+        self.pixels.fill([0.0; 3]);
+        self.draw_pos_col(pc);
+        self.draw_pos_col_tex(pct);
+    }
+
+    /// Overwrites a pixel with an sRGB color, for images drawn on the CPU
+    pub fn put_pixel(&mut self, x: u32, y: u32, rgb: [u8; 3]) {
+        if x < self.dx && y < self.dy {
+            let linear = rgb.map(|c| self.decode[c as usize]);
+            self.pixels[(y * self.dx + x) as usize] = linear;
+        }
+    }
+
+    pub fn draw_pos_col(&mut self, verts: &[PosColVertex]) {
+        let (dx, dy) = (self.dx, self.dy);
+        let pixels = &mut self.pixels;
+        for quad in verts.chunks_exact(4) {
+            for tri in [[0, 1, 3], [3, 2, 1]] {
+                let v = tri.map(|i| &quad[i]);
+                let pos = v.map(|v| [v.pos[0] as f32 / 8.0, v.pos[1] as f32 / 8.0]);
+                let col = v.map(|v| unpack(v.color));
+                raster_triangle(dx, dy, pos, |i, w| {
+                    let c = interpolate(&col, w);
+                    let a = c[3];
+                    let dst = &mut pixels[i];
+                    for k in 0..3 {
+                        dst[k] = c[k] * a + dst[k] * (1.0 - a);
+                    }
+                });
+            }
+        }
+    }
+
+    pub fn draw_pos_col_tex(&mut self, verts: &[PosColTexVertex]) {
+        let (dx, dy) = (self.dx, self.dy);
+        let pixels = &mut self.pixels;
+        let font = &self.font;
+        let decode = &self.decode;
+        for v in verts.chunks_exact(3) {
+            let pos = [v[0].pos, v[1].pos, v[2].pos];
+            let col = [unpack(v[0].color), unpack(v[1].color), unpack(v[2].color)];
+            let tex = [v[0].tex, v[1].tex, v[2].tex];
+            raster_triangle(dx, dy, pos, |i, w| {
+                let u = tex[0][0] * w[0] + tex[1][0] * w[1] + tex[2][0] * w[2];
+                let t = tex[0][1] * w[0] + tex[1][1] * w[1] + tex[2][1] * w[2];
+                let x = ((u * font.dx as f32) as i64).clamp(0, font.dx as i64 - 1) as usize;
+                let y = ((t * font.dy as f32) as i64).clamp(0, font.dy as i64 - 1) as usize;
+                let texel = &font.pixels[4 * (y * font.dx as usize + x)..][..4];
+                if texel[3] < 128 {
+                    return;
+                }
+                let c = interpolate(&col, w);
+                for k in 0..3 {
+                    pixels[i][k] = c[k] * decode[texel[k] as usize];
+                }
+            });
+        }
+    }
+
+    /// RGBA8, sRGB encoded, opaque
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.pixels.len() * 4);
+        for p in &self.pixels {
+            for k in 0..3 {
+                out.push((linear_to_srgb(p[k].clamp(0.0, 1.0)) * 255.0 + 0.5) as u8);
+            }
+            out.push(0xff);
+        }
+        out
+    }
+
+    pub fn save_png(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.dx, self.dy);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.to_rgba8())?;
+        Ok(())
+    }
+}
+
+/// Unorm8x4, red in the low byte
+fn unpack(color: u32) -> [f32; 4] {
+    [0, 8, 16, 24].map(|shift| ((color >> shift) & 0xff) as f32 / 255.0)
+}
+
+fn interpolate(col: &[[f32; 4]; 3], w: [f32; 3]) -> [f32; 4] {
+    let mut c = [0.0; 4];
+    for k in 0..4 {
+        c[k] = col[0][k] * w[0] + col[1][k] * w[1] + col[2][k] * w[2];
+    }
+    c
+}
+
+fn srgb_to_linear(x: f32) -> f32 {
+    if x <= 0.04045 {
+        x / 12.92
+    } else {
+        ((x + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(x: f32) -> f32 {
+    if x <= 0.0031308 {
+        x * 12.92
+    } else {
+        1.055 * x.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn edge(a: [f32; 2], b: [f32; 2], p: [f32; 2]) -> f32 {
+    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+}
+
+/// Pixel centers on an edge go to one of the two triangles sharing it
+fn owns_edge(a: [f32; 2], b: [f32; 2]) -> bool {
+    let (ex, ey) = (b[0] - a[0], b[1] - a[1]);
+    ey > 0.0 || (ey == 0.0 && ex < 0.0)
+}
+
+/// Calls `fragment` with the pixel index and barycentric weights of every
+/// pixel center inside the triangle, either winding
+fn raster_triangle<F>(dx: u32, dy: u32, mut p: [[f32; 2]; 3], mut fragment: F)
+where
+    F: FnMut(usize, [f32; 3]),
+{
+    let mut area = edge(p[0], p[1], p[2]);
+    let mut swapped = false;
+    if area < 0.0 {
+        p.swap(1, 2);
+        area = -area;
+        swapped = true;
+    }
+    if !(area > 0.0) || !area.is_finite() {
+        return;
+    }
+    let min = |k: usize| p.iter().map(|v| v[k]).fold(f32::INFINITY, f32::min);
+    let max = |k: usize| p.iter().map(|v| v[k]).fold(f32::NEG_INFINITY, f32::max);
+    let x0 = (min(0) - 0.5).ceil().max(0.0) as u32;
+    let y0 = (min(1) - 0.5).ceil().max(0.0) as u32;
+    let x1 = ((max(0) - 0.5).floor() + 1.0).clamp(0.0, dx as f32) as u32;
+    let y1 = ((max(1) - 0.5).floor() + 1.0).clamp(0.0, dy as f32) as u32;
+
+    let edges = [(p[1], p[2]), (p[2], p[0]), (p[0], p[1])];
+    let owned = edges.map(|(a, b)| owns_edge(a, b));
+    for y in y0..y1 {
+        for x in x0..x1 {
+            let q = [x as f32 + 0.5, y as f32 + 0.5];
+            let e = edges.map(|(a, b)| edge(a, b, q));
+            let inside = (0..3).all(|k| e[k] > 0.0 || (e[k] == 0.0 && owned[k]));
+            if inside {
+                let mut w = e.map(|e| e / area);
+                if swapped {
+                    w.swap(1, 2);
+                }
+                fragment((y * dx + x) as usize, w);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_soft_raster() {
+    let font = KRGBAImage {
+        dx: 2,
+        dy: 2,
+        pixels: vec![
+            0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0, //
+            0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff,
+        ],
+    };
+    let mut raster = SoftRaster::new(8, 8, font);
+
+    // quads go around their corners, two half transparent ones overlap
+    let quad = |x0: i16, y0: i16, x1: i16, y1: i16, color: u32| {
+        [(x0, y0), (x1, y0), (x1, y1), (x0, y1)].map(|(x, y)| PosColVertex {
+            pos: [8 * x, 8 * y],
+            color,
+        })
+    };
+    let mut pc = Vec::new();
+    pc.extend_from_slice(&quad(0, 0, 4, 4, 0x800000ff));
+    pc.extend_from_slice(&quad(2, 2, 6, 6, 0x8000ff00));
+
+    // a glyph of the top left atlas texel over the right half
+    let t = |x: f32, y: f32, u: f32, v: f32| PosColTexVertex {
+        pos: [x, y],
+        color: 0xffffffff,
+        tex: [u, v],
+    };
+    let pct = vec![
+        t(6.0, 0.0, 0.0, 0.0),
+        t(8.0, 0.0, 0.49, 0.0),
+        t(6.0, 2.0, 0.0, 0.49),
+        t(8.0, 0.0, 0.49, 0.0),
+        t(8.0, 2.0, 0.49, 0.49),
+        t(6.0, 2.0, 0.0, 0.49),
+    ];
+    raster.render(&pc, &pct);
+    let img = raster.to_rgba8();
+    let px = |x: usize, y: usize| &img[4 * (y * 8 + x)..][..4];
+
+    let a = 128.0 / 255.0;
+    let red = (linear_to_srgb(a) * 255.0 + 0.5) as u8;
+    assert_eq!(px(0, 0), &[red, 0, 0, 0xff]);
+    assert_eq!(px(3, 0), &[red, 0, 0, 0xff]);
+    assert_eq!(px(4, 0), &[0, 0, 0, 0xff]);
+    // the green quad is drawn over the red one
+    let mixed_red = (linear_to_srgb(a * (1.0 - a)) * 255.0 + 0.5) as u8;
+    assert_eq!(px(3, 3), &[mixed_red, red, 0, 0xff]);
+    assert_eq!(px(5, 5), &[0, red, 0, 0xff]);
+    assert_eq!(px(7, 1), &[0xff, 0xff, 0xff, 0xff]);
+    assert_eq!(px(7, 2), &[0, 0, 0, 0xff]);
+    // every pixel of a quad exactly once, no seams along the diagonal
+    for y in 0..2 {
+        for x in 0..2 {
+            assert_eq!(px(x, y), px(1, 0));
+        }
+    }
+}
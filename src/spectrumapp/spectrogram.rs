@@ -0,0 +1,401 @@
+use std::io::Read;
+use std::path::Path;
+use std::rc::Rc;
+
+use anyhow::bail;
+use anyhow::Context;
+
+use super::fontrenderer::FontRenderer;
+use super::myvertex::PosColTexVertex;
+use super::resolution::ResolutionConfig;
+use super::sbswdft::a_weighting;
+use super::sbswdft::hz2color_rgb;
+use super::sbswdft::ChannelSWDFT;
+use super::sbswdft::SpectrumConfig;
+use super::softraster::SoftRaster;
+use super::spectrumui::RendererMsg;
+use super::spectrumui::SpectrumUI;
+
+// Offline spectrogram of a whole audio file. The file is fed to the same
+// ChannelSWDFT as the live view as fast as it goes, every collected frame
+// lands in the image column of its sample index (the loudest frame wins),
+// and the image is drawn with the software rasterizer.
+
+#[derive(Copy, Clone, Debug, PartialEq, clap::ValueEnum)]
+pub enum FreqScale {
+    Log,
+    Linear,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, clap::ValueEnum)]
+pub enum Colormap {
+    Inferno,
+    Viridis,
+    Gray,
+    /// the bin colors of the live view, brightness by level
+    Hue,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, clap::ValueEnum)]
+pub enum Method {
+    /// Neighbour Components, the super-resolution method
+    Nc,
+    Dft,
+}
+
+#[derive(Clone)]
+pub struct SpectrogramConfig {
+    pub method: Method,
+    pub scale: FreqScale,
+    pub colormap: Colormap,
+    /// dB relative to a full scale sine, A-weighted like the live view
+    pub db_min: f32,
+    pub db_max: f32,
+    pub pixels_per_second: f32,
+    pub height: u32,
+    pub num_bins: u32,
+    pub min_f: f32,
+    /// lowered to the Nyquist frequency of the analysis
+    pub max_f: f32,
+    pub resolution: ResolutionConfig,
+}
+
+impl SpectrogramConfig {
+    pub fn new() -> Self {
+        Self {
+            method: Method::Nc,
+            scale: FreqScale::Log,
+            colormap: Colormap::Inferno,
+            db_min: -100.0,
+            db_max: 0.0,
+            pixels_per_second: 50.0,
+            height: 600,
+            num_bins: 600,
+            min_f: 40.0,
+            max_f: 12000.0,
+            resolution: ResolutionConfig::new(),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        format!(
+            "{:?}, {:?} scale, {:.0} .. {:.0} dBFS, {} px/s",
+            self.method, self.scale, self.db_min, self.db_max, self.pixels_per_second
+        )
+    }
+}
+
+pub struct Spectrogram {
+    pub config: SpectrumConfig,
+    /// bin values of each image column
+    pub columns: Vec<Vec<f32>>,
+    /// bin value of a full scale sine, 0 dB
+    pub full_scale: f64,
+}
+
+/// Reads a WAV file mixed down to mono, returns the samples and sample rate.
+/// Other formats are refused, there is no decoder for them
+pub fn read_wav(path: &Path) -> Result<(Vec<f32>, u32), anyhow::Error> {
+    let mut magic = [0u8; 12];
+    std::fs::File::open(path)
+        .and_then(|mut f| f.read_exact(&mut magic))
+        .with_context(|| format!("can't open {}", path.display()))?;
+    if &magic[0..4] != b"RIFF" || &magic[8..12] != b"WAVE" {
+        bail!(
+            "{} is not a WAV file, only WAV input is supported",
+            path.display()
+        );
+    }
+    let mut reader =
+        hound::WavReader::open(path).with_context(|| format!("can't open {}", path.display()))?;
+    let spec = reader.spec();
+    let interleaved: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = 1.0 / (1_i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .samples::<i32>()
+                .map(|s| s.map(|s| s as f32 * scale))
+                .collect::<Result<_, _>>()?
+        }
+    };
+    let channels = spec.channels.max(1) as usize;
+    let mono = interleaved
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+        .collect();
+    Ok((mono, spec.sample_rate))
+}
+
+fn analyzer(config: &SpectrumConfig, method: Method) -> ChannelSWDFT {
+    let mut swdft = ChannelSWDFT::new(config);
+    let kind = match method {
+        Method::Dft => 0,
+        Method::Nc => 1,
+    };
+    swdft.spectrum_bins = ChannelSWDFT::make_spectrum_bins(kind, config);
+    swdft.should_colorize = false;
+    swdft
+}
+
+/// Bin value of a full scale sine, without the A-weighting
+fn full_scale(config: &SpectrumConfig, method: Method) -> f64 {
+    let mut swdft = analyzer(config, method);
+    let freq = 1000.0_f32.clamp(config.min_f, config.max_f);
+    let input_rate = config.input_rate();
+    let w = std::f64::consts::TAU * freq as f64 / input_rate;
+    let signal: Vec<f32> = (0..input_rate as usize)
+        .map(|n| (n as f64 * w).sin() as f32)
+        .collect();
+    for block in signal.chunks(4096) {
+        swdft.process_input(block);
+    }
+    let max = swdft
+        .collect_spectrum()
+        .spectrum
+        .iter()
+        .map(|s| s.value)
+        .fold(0.0, f64::max);
+    max / a_weighting(freq)
+}
+
+/// Runs the analyzer over `samples` at `sample_rate`
+pub fn analyze(samples: &[f32], sample_rate: u32, sc: &SpectrogramConfig) -> Spectrogram {
+    // the analysis runs at half of the input rate
+    let config = SpectrumConfig {
+        sample_rate: sample_rate / 2,
+        num_bins: sc.num_bins,
+        min_f: sc.min_f,
+        max_f: sc.max_f.min(sample_rate as f32 / 4.0),
+        // at least one frame per column, AGC needs 60 or more
+        collect_frequency: (sc.pixels_per_second.ceil() as usize).max(60),
+        resolution: sc.resolution.clone(),
+        ..SpectrumConfig::new()
+    };
+    let full_scale = full_scale(&config, sc.method);
+
+    let mut swdft = analyzer(&config, sc.method);
+    let receiver = swdft
+        .collected_spectrums_receiver
+        .lock()
+        .unwrap()
+        .take()
+        .unwrap();
+
+    let seconds = samples.len() as f64 / sample_rate as f64;
+    let pps = sc.pixels_per_second as f64;
+    let num_columns = ((seconds * pps).ceil() as usize).max(1);
+    let mut columns: Vec<Vec<f32>> = vec![Vec::new(); num_columns];
+    for block in samples.chunks(4096) {
+        swdft.on_input(block, 0.0);
+        for msg in receiver.try_iter() {
+            if let RendererMsg::NewSpectrum(collected) = msg {
+                let t = collected.sample_index as f64 / sample_rate as f64;
+                let column = &mut columns[((t * pps) as usize).min(num_columns - 1)];
+                if column.is_empty() {
+                    column.extend(collected.spectrum.iter().map(|s| s.value as f32));
+                } else {
+                    for (c, s) in column.iter_mut().zip(collected.spectrum.iter()) {
+                        *c = c.max(s.value as f32);
+                    }
+                }
+            }
+        }
+    }
+
+    // columns shorter than a collect period repeat the one before
+    for i in 1..num_columns {
+        if columns[i].is_empty() {
+            columns[i] = columns[i - 1].clone();
+        }
+    }
+    for column in columns.iter_mut().filter(|c| c.is_empty()) {
+        column.resize(config.num_bins as usize, 0.0);
+    }
+
+    Spectrogram {
+        config,
+        columns,
+        full_scale,
+    }
+}
+
+/// sRGB, `t` from 0 to 1
+fn colormap(map: Colormap, t: f32, freq_hz: f32) -> [u8; 3] {
+    const INFERNO: [[f32; 3]; 5] = [
+        [0.0, 0.0, 4.0],
+        [87.0, 16.0, 110.0],
+        [188.0, 55.0, 84.0],
+        [249.0, 142.0, 9.0],
+        [252.0, 255.0, 164.0],
+    ];
+    const VIRIDIS: [[f32; 3]; 5] = [
+        [68.0, 1.0, 84.0],
+        [59.0, 82.0, 139.0],
+        [33.0, 145.0, 140.0],
+        [94.0, 201.0, 98.0],
+        [253.0, 231.0, 37.0],
+    ];
+    let gradient = |stops: &[[f32; 3]]| {
+        let x = t * (stops.len() - 1) as f32;
+        let i = (x as usize).min(stops.len() - 2);
+        let frac = x - i as f32;
+        [0, 1, 2].map(|k| (stops[i][k] + (stops[i + 1][k] - stops[i][k]) * frac) as u8)
+    };
+    match map {
+        Colormap::Inferno => gradient(&INFERNO),
+        Colormap::Viridis => gradient(&VIRIDIS),
+        Colormap::Gray => [(t * 255.0) as u8; 3],
+        Colormap::Hue => {
+            let (r, g, b) = hz2color_rgb(freq_hz);
+            [r, g, b].map(|c| (c * t * 255.0) as u8)
+        }
+    }
+}
+
+impl Spectrogram {
+    /// Row `y` (0 is the top) of a `height` pixel image, in Hz
+    fn row_frequency(&self, sc: &SpectrogramConfig, y: u32, height: u32) -> f32 {
+        let (min_f, max_f) = (self.config.min_f, self.config.max_f);
+        let t = 1.0 - (y as f32 + 0.5) / height as f32;
+        match sc.scale {
+            FreqScale::Log => ChannelSWDFT::exp_interpolate(min_f, max_f, t),
+            FreqScale::Linear => min_f + (max_f - min_f) * t,
+        }
+    }
+
+    /// The image, one column per `pixels_per_second`, with frequency and time labels
+    pub fn render(&self, sc: &SpectrogramConfig, title: &str) -> SoftRaster {
+        let (dx, dy) = (self.columns.len() as u32, sc.height);
+        let mut raster = SoftRaster::new(dx, dy, super::font_image());
+        let (min_f, max_f) = (self.config.min_f, self.config.max_f);
+        let n = self.config.num_bins as usize;
+
+        // bins are spaced like the log scale, rows look up a fractional bin
+        let rows: Vec<(usize, usize, f32, f32)> = (0..dy)
+            .map(|y| {
+                let f = self.row_frequency(sc, y, dy);
+                let x = (ChannelSWDFT::exp_inverse(min_f, max_f, f) * n as f32)
+                    .clamp(0.0, (n - 1) as f32);
+                let i0 = x as usize;
+                (i0, (i0 + 1).min(n - 1), x - i0 as f32, f)
+            })
+            .collect();
+        let range = (sc.db_max - sc.db_min).max(1e-3);
+        for (x, column) in self.columns.iter().enumerate() {
+            for (y, &(i0, i1, frac, f)) in rows.iter().enumerate() {
+                let v = column[i0] + (column[i1] - column[i0]) * frac;
+                let db = 20.0 * (v as f64 / self.full_scale + 1e-12).log10() as f32;
+                let t = ((db - sc.db_min) / range).clamp(0.0, 1.0);
+                raster.put_pixel(x as u32, y as u32, colormap(sc.colormap, t, f));
+            }
+        }
+
+        let mut pct: Vec<PosColTexVertex> = Vec::new();
+        let mut fr = FontRenderer::new(Rc::new(super::font_atlas()), &mut pct);
+
+        let mut last_y = f32::INFINITY;
+        for (pos, f, precision) in SpectrumUI::make_divisions_grid(dy as f32, min_f, max_f) {
+            let y = match sc.scale {
+                FreqScale::Log => dy as f32 - pos,
+                FreqScale::Linear => dy as f32 * (1.0 - (f - min_f) / (max_f - min_f)),
+            };
+            if y < 14.0 || y > dy as f32 - 14.0 || (last_y - y).abs() < 12.0 {
+                continue;
+            }
+            last_y = y;
+            for x in (0..dx).step_by(4) {
+                raster.put_pixel(x, y as u32, [0x60; 3]);
+            }
+            let label = format!("- {:.1$} Hz", f, precision as usize);
+            fr.draw_string(&label, 2.0, y - 4.0, 0xffffffff, true);
+        }
+
+        let pps = sc.pixels_per_second;
+        let steps = [
+            0.1, 0.2, 0.5, 1.0, 2.0, 5.0, 10.0, 15.0, 30.0, 60.0, 120.0, 300.0,
+        ];
+        let step = steps.into_iter().find(|s| s * pps >= 80.0).unwrap_or(600.0);
+        let mut t = step;
+        while t * pps < dx as f32 - 40.0 {
+            fr.draw_string(
+                &format!("^ {} s", t),
+                t * pps - 3.0,
+                dy as f32 - 9.0,
+                0xffffffff,
+                true,
+            );
+            t += step;
+        }
+
+        fr.draw_string(
+            &format!("{}  {}", title, sc.describe()),
+            2.0,
+            2.0,
+            0xffffffff,
+            true,
+        );
+
+        raster.draw_pos_col_tex(&pct);
+        raster
+    }
+}
+
+/// Reads `input`, analyzes it and saves the spectrogram to `output`
+pub fn render_file(
+    input: &Path,
+    output: &Path,
+    sc: &SpectrogramConfig,
+) -> Result<(), anyhow::Error> {
+    let (samples, sample_rate) = read_wav(input)?;
+    if samples.is_empty() {
+        bail!("{} has no samples", input.display());
+    }
+    let spectrogram = analyze(&samples, sample_rate, sc);
+    let title = input.file_name().unwrap_or_default().to_string_lossy();
+    spectrogram
+        .render(sc, &title)
+        .save_png(output)
+        .with_context(|| format!("can't save {}", output.display()))
+}
+
+#[test]
+fn test_spectrogram() {
+    let mut sc = SpectrogramConfig::new();
+    sc.pixels_per_second = 100.0;
+    sc.height = 200;
+    sc.num_bins = 200;
+
+    // a -6 dBFS 2 kHz tone for half a second
+    let sample_rate = 48000;
+    let w = std::f64::consts::TAU * 2000.0 / sample_rate as f64;
+    let signal: Vec<f32> = (0..sample_rate / 2)
+        .map(|n| (0.5 * (n as f64 * w).sin()) as f32)
+        .collect();
+
+    for method in [Method::Nc, Method::Dft] {
+        sc.method = method;
+        let spectrogram = analyze(&signal, sample_rate, &sc);
+        assert_eq!(spectrogram.columns.len(), 50);
+        assert_eq!(spectrogram.config.max_f, 12000.0);
+
+        let last = spectrogram.columns.last().unwrap();
+        let (peak, value) =
+            last.iter()
+                .enumerate()
+                .fold((0, 0.0), |a, (i, v)| if *v > a.1 { (i, *v) } else { a });
+        let f = ChannelSWDFT::num_probe_x_to_freq(&spectrogram.config, peak as f32 / 200.0);
+        assert!((f / 2000.0 - 1.0).abs() < 0.03);
+        let db = 20.0 * (value as f64 / spectrogram.full_scale / a_weighting(f)).log10();
+        assert!((db + 6.02).abs() < 1.0, "{:?}: {} dB", method, db);
+
+        let raster = spectrogram.render(&sc, "test");
+        assert_eq!((raster.dx, raster.dy), (50, 200));
+    }
+
+    let path = std::env::temp_dir().join("sbsdft_test_spectrogram.mp3");
+    std::fs::write(&path, b"ID3\x04\0\0\0\0\0\0 not a wav").unwrap();
+    let err = read_wav(&path).unwrap_err().to_string();
+    std::fs::remove_file(&path).ok();
+    assert!(err.contains("only WAV"), "{}", err);
+}
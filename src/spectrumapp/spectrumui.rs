@@ -1,4 +1,5 @@
 use super::appstate::get_app_state;
+use super::appstate::get_app_state_detail;
 use super::appstate::AppState;
 use super::appthread::AppFunc;
 use super::appthread::AppMsg;
@@ -9,6 +10,11 @@ use super::sbswdft::Collected;
 use super::sbswdft::SpectrumBins;
 use super::sbswdft::SpectrumBinsState;
 use super::sbswdft::WindowType;
+use super::traces::AverageMode;
+use super::traces::SpectrumTraces;
+use super::traces::TraceKind;
+use super::traces::TracesConfig;
+use super::transfer::TransferFunction;
 
 use super::FontRenderer;
 use super::GraphType;
@@ -18,8 +24,29 @@ use std::collections::VecDeque;
 use std::sync::mpsc::Receiver;
 use std::sync::Mutex;
 
+#[cfg(not(target_arch = "wasm32"))]
+use super::adevice_cpal::StreamHealth;
 use super::displayparams::DisplayParams;
+use super::distortion::DistortionConfig;
+use super::distortion::DistortionReport;
 use super::fontrenderer::FontAtlas;
+use super::generator::SharedGenerator;
+use super::keymap::Action;
+use super::keymap::Keymap;
+use super::kwasm;
+#[cfg(not(target_arch = "wasm32"))]
+use super::led::LedSender;
+#[cfg(not(target_arch = "wasm32"))]
+use super::osc::OscSender;
+use super::recorder::RecorderConfig;
+use super::recorder::SpectrumRecorder;
+use super::replay::Recording;
+use super::replay::Replay;
+use super::settings::Preset;
+use super::settings::RateLimit;
+use super::settings::Settings;
+#[cfg(not(target_arch = "wasm32"))]
+use super::stream::SpectrumServer;
 
 use super::sbswdft::SlidingImpl;
 use super::sbswdft::SpectrumConfig;
@@ -42,13 +69,53 @@ pub struct SpectrumUI {
     zoom: f32,
     graph_type: GraphType,
     dragging: bool,
+    /// which button is dragging
+    drag_left: bool,
     last_drag_pos: cgmath::Vector2<f32>,
     font_atlas: Rc<FontAtlas>,
 
     subdivisions: i32,
     logarithmic_scale: bool,
+    traces_config: TracesConfig,
+    show_noise_floor: bool,
+    subtract_noise_floor: bool,
+    transfer: Option<TransferFunction>,
+    show_transfer: bool,
+    show_distortion: bool,
+    /// latest analysis of the first channel
+    distortion: Option<DistortionReport>,
+    /// smoothed capture to screen latency of the first channel
+    latency_ms: f64,
+    drawn_sample_index: u64,
+    /// set while the test signal generator plays
+    pub generator: Option<SharedGenerator>,
+    pub recorder_config: RecorderConfig,
+    recorder: Option<SpectrumRecorder>,
+    /// set while a recording plays instead of the audio input
+    pub replay: Option<Replay>,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub stream_server: Option<SpectrumServer>,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub osc: Option<OscSender>,
+    #[cfg(not(target_arch = "wasm32"))]
+    pub leds: Option<LedSender>,
+    /// the audio device feeding the analyzer
+    #[cfg(not(target_arch = "wasm32"))]
+    pub input: Option<Arc<StreamHealth>>,
+    /// name of the preset just switched to, until when it is shown (ms)
+    preset_shown: Option<(String, f64)>,
+    /// what the keys do, for the hints and the help
+    pub keymap: Rc<Keymap>,
+    show_help: bool,
+    /// set when the input's rate holds the range below the one asked for
+    pub rate_limit: Option<RateLimit>,
+    /// settings at start, for what the channels don't hold: presets and key bindings
+    pub settings_base: Settings,
 }
 
+/// how long a preset's name stays on screen
+const PRESET_SHOWN_MS: f64 = 2000.0;
+
 pub enum RendererMsg {
     NewSpectrum(Collected),
     ConfigUpdate(SpectrumConfig),
@@ -59,6 +126,7 @@ pub struct SlidingChannel {
     pub spectrum_receiver: Receiver<RendererMsg>,
     pub last_rolling_gain: f64,
     pub collected_spectrums: VecDeque<Collected>,
+    pub traces: SpectrumTraces,
 }
 
 pub struct StateSnapshot {
@@ -75,6 +143,11 @@ impl SpectrumUI {
         font_atlas: FontAtlas,
         sliding_impls: Vec<SlidingChannel>,
     ) -> Self {
+        let transfer = if sliding_impls.len() >= 2 {
+            Some(TransferFunction::new(0, 1))
+        } else {
+            None
+        };
         let celled = sliding_impls.into_iter().map(|s| RefCell::new(s)).collect();
         Self {
             app: None,
@@ -95,11 +168,38 @@ impl SpectrumUI {
             zoom: 0.0,
             graph_type: GraphType::Line,
             dragging: false,
+            drag_left: true,
             last_drag_pos: cgmath::vec2(0.0, 0.0),
             font_atlas: Rc::new(font_atlas),
 
             subdivisions: 6,
             logarithmic_scale: false,
+            traces_config: TracesConfig::new(),
+            show_noise_floor: false,
+            subtract_noise_floor: false,
+            transfer,
+            show_transfer: false,
+            show_distortion: false,
+            distortion: None,
+            latency_ms: 0.0,
+            drawn_sample_index: 0,
+            generator: None,
+            recorder_config: RecorderConfig::new(),
+            recorder: None,
+            replay: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            stream_server: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            osc: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            leds: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            input: None,
+            preset_shown: None,
+            keymap: Rc::new(Keymap::new()),
+            show_help: false,
+            rate_limit: None,
+            settings_base: Settings::new(),
         }
     }
     pub fn on_resize(&mut self, dx: u32, dy: u32) {
@@ -124,6 +224,50 @@ impl SpectrumUI {
                 match &mut *channel {
                     SlidingImpl::DFT(dft) => {
                         dft.config.wave_cycles_resolution *= scale;
+                        dft.config.resolution.bandwidth_hz /= scale;
+                    }
+                }
+            }
+        }));
+
+        self.reinit_spectrum();
+    }
+
+    pub fn cycle_resolution_policy(&mut self) {
+        self.run_main(Box::new(move |app| {
+            for sliding_arc in &app.sliding_channels {
+                let mut channel = sliding_arc.lock().unwrap();
+
+                match &mut *channel {
+                    SlidingImpl::DFT(dft) => {
+                        dft.config.resolution.cycle_policy();
+                    }
+                }
+            }
+        }));
+
+        self.reinit_spectrum();
+    }
+
+    /// The input now runs at `input_rate`, retunes the bins for the analysis at half of it
+    pub fn change_sample_rate(&mut self, input_rate: u32) {
+        let mut settings = match self.settings() {
+            Some(settings) => settings,
+            None => return,
+        };
+        // a range held down by a lower rate goes back up with it
+        settings.restore_range(self.rate_limit);
+        self.rate_limit = settings.set_input_rate(input_rate);
+        let c = settings.spectrum;
+        self.run_main(Box::new(move |app| {
+            for sliding_arc in &app.sliding_channels {
+                let mut channel = sliding_arc.lock().unwrap();
+
+                match &mut *channel {
+                    SlidingImpl::DFT(dft) => {
+                        dft.config.sample_rate = c.sample_rate;
+                        dft.config.max_f = c.max_f;
+                        dft.config.min_f = c.min_f;
                     }
                 }
             }
@@ -132,6 +276,12 @@ impl SpectrumUI {
         self.reinit_spectrum();
     }
 
+    pub fn cycle_overrun_policy(&mut self) {
+        if let Some(app) = &self.app {
+            app.stats.cycle_policy();
+        }
+    }
+
     pub fn run_main(&self, f: Box<AppFunc>) {
         if let Some(app) = &self.app {
             let _ = app.main_priority_tx.try_send(AppMsg::RunFunc(f));
@@ -153,6 +303,88 @@ impl SpectrumUI {
         }));
     }
 
+    /// Current analyzer and view settings, None without channels
+    pub fn settings(&self) -> Option<Settings> {
+        let channel = self.sliding_impls.first()?;
+        let mut settings = self.settings_base.clone();
+        match &*channel.borrow().sliding_rc.lock().unwrap() {
+            SlidingImpl::DFT(dft) => settings.capture_channel(dft),
+        }
+        settings.subdivisions = self.subdivisions;
+        settings.graph_type = self.graph_type;
+        settings.logarithmic_scale = self.logarithmic_scale;
+        settings.peak_decay_db_per_s = self.traces_config.peak_decay_db_per_s;
+        settings.average_frames = self.traces_config.average_frames;
+        if let Some(transfer) = &self.transfer {
+            settings.transfer_averaging_s = transfer.config.averaging_s;
+        }
+        Some(settings)
+    }
+
+    /// What to keep for the next run, the range asked for rather than what the rate allows
+    pub fn requested_settings(&self) -> Option<Settings> {
+        let mut settings = self.settings()?;
+        settings.restore_range(self.rate_limit);
+        Some(settings)
+    }
+
+    /// The view part of `settings`, the channels are set up by the caller
+    pub fn apply_view_settings(&mut self, settings: &Settings) {
+        self.subdivisions = settings.subdivisions;
+        self.graph_type = settings.graph_type;
+        self.logarithmic_scale = settings.logarithmic_scale;
+        self.traces_config.peak_decay_db_per_s = settings.peak_decay_db_per_s;
+        self.traces_config.average_frames = settings.average_frames;
+        if let Some(transfer) = &mut self.transfer {
+            transfer.config.averaging_s = settings.transfer_averaging_s;
+        }
+    }
+
+    /// Switches the analyzer and the view to preset `number` in one step
+    pub fn apply_preset(&mut self, number: usize, preset: &Preset) {
+        if self.replay.is_some() {
+            println!("presets don't apply to a replay");
+            return;
+        }
+        let mut settings = match self.settings() {
+            Some(settings) => settings,
+            None => return,
+        };
+        let rate_limit = settings.apply_preset(preset);
+        if let Err(err) = Settings::check_spectrum(&settings.spectrum) {
+            println!("preset {}: {}", number, err);
+            return;
+        }
+        self.rate_limit = rate_limit;
+        self.apply_view_settings(&settings);
+        self.run_main(Box::new(move |app| {
+            for sliding_arc in &app.sliding_channels {
+                let mut channel = sliding_arc.lock().unwrap();
+                match &mut *channel {
+                    SlidingImpl::DFT(dft) => settings.retune_channel(dft),
+                }
+            }
+        }));
+        println!("preset {}: {}", number, preset.name);
+        let text = format!("preset {}: {}", number, preset.name);
+        self.preset_shown = Some((text, kwasm::now_ms() + PRESET_SHOWN_MS));
+    }
+
+    pub fn toggle_help(&mut self) {
+        self.show_help = !self.show_help;
+    }
+
+    /// "[Q/Up/Down]      resolution: ..." with the keys from the keymap
+    fn hud_line(&self, actions: &[Action], name: &str, value: String) -> String {
+        let keys = self.keymap.keys(actions);
+        let keys = if keys.is_empty() {
+            keys
+        } else {
+            format!("[{}]", keys)
+        };
+        format!("{:<12} {:>19}: {}", keys, name, value)
+    }
+
     pub fn toggle_peaks(&mut self) {
         let n: u8 = self.graph_type.into();
         self.graph_type = match GraphType::try_from(n + 1) {
@@ -164,6 +396,112 @@ impl SpectrumUI {
         self.logarithmic_scale = !self.logarithmic_scale;
     }
 
+    pub fn toggle_trace(&mut self, kind: TraceKind) {
+        let c = &mut self.traces_config;
+        match kind {
+            TraceKind::PeakHold => c.peak_hold = !c.peak_hold,
+            TraceKind::MaxHold => c.max_hold = !c.max_hold,
+            TraceKind::MinHold => c.min_hold = !c.min_hold,
+            TraceKind::Average => {
+                let n: u8 = c.average.into();
+                c.average = match AverageMode::try_from(n + 1) {
+                    Ok(x) => x,
+                    Err(_) => AverageMode::try_from(0).unwrap(),
+                }
+            }
+        }
+        // a trace that comes back on starts from scratch
+        for sliding_cell in &self.sliding_impls {
+            sliding_cell.borrow_mut().traces.clear(kind);
+        }
+    }
+
+    pub fn toggle_noise_floor(&mut self) {
+        self.show_noise_floor = !self.show_noise_floor;
+    }
+
+    pub fn toggle_subtract_noise_floor(&mut self) {
+        self.subtract_noise_floor = !self.subtract_noise_floor;
+        for sliding_cell in &self.sliding_impls {
+            sliding_cell.borrow_mut().traces.reset();
+        }
+    }
+
+    pub fn toggle_transfer(&mut self) {
+        if let Some(transfer) = &mut self.transfer {
+            self.show_transfer = !self.show_transfer;
+            transfer.reset();
+        } else {
+            println!("transfer function needs two input channels");
+        }
+    }
+
+    pub fn swap_transfer_channels(&mut self) {
+        if let Some(transfer) = &mut self.transfer {
+            transfer.swap_channels();
+        }
+    }
+
+    pub fn toggle_recording(&mut self) {
+        if let Some(mut recorder) = self.recorder.take() {
+            if let Err(err) = recorder.finish() {
+                println!("recorder: {}", err);
+            }
+            return;
+        }
+        match SpectrumRecorder::start(self.recorder_config.clone(), &self.zoom_config) {
+            Ok(recorder) => self.recorder = Some(recorder),
+            Err(err) => println!(
+                "recorder: can't write {}: {}",
+                self.recorder_config.path.display(),
+                err
+            ),
+        }
+    }
+
+    /// Feeds the channels from `recording` instead of their processing threads
+    pub fn start_replay(&mut self, recording: Arc<Recording>) {
+        let senders = self
+            .sliding_impls
+            .iter()
+            .map(|cell| {
+                let (sender, receiver) = std::sync::mpsc::channel();
+                cell.borrow_mut().spectrum_receiver = receiver;
+                sender
+            })
+            .collect();
+        self.replay = Some(Replay::new(recording, senders));
+    }
+
+    pub fn change_replay<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut Replay),
+    {
+        if let Some(replay) = &mut self.replay {
+            f(replay);
+        }
+    }
+
+    pub fn toggle_distortion(&mut self) {
+        self.show_distortion = !self.show_distortion;
+        self.distortion = None;
+        let enabled = self.show_distortion;
+        self.run_main(Box::new(move |app| {
+            for sliding_arc in &app.sliding_channels {
+                let mut channel = sliding_arc.lock().unwrap();
+                match &mut *channel {
+                    SlidingImpl::DFT(dft) => {
+                        dft.distortion = if enabled {
+                            Some(DistortionConfig::new())
+                        } else {
+                            None
+                        };
+                    }
+                }
+            }
+        }));
+    }
+
     pub fn cycle_method(&mut self) {
         self.run_main(Box::new(move |app| {
             for sliding_arc in &app.sliding_channels {
@@ -178,6 +516,7 @@ impl SpectrumUI {
                         method = (method + 1) % 2;
                         let bins = ChannelSWDFT::make_spectrum_bins(method, &dft.config);
                         dft.spectrum_bins = bins;
+                        dft.warm_start_bins();
                     }
                 }
             }
@@ -415,7 +754,8 @@ impl SpectrumUI {
         }));
     }
 
-    pub fn on_cursor_move(&mut self, position: (f64, f64)) {
+    /// While a button is held, which one and the whole bins dragged over
+    pub fn on_cursor_move(&mut self, position: (f64, f64)) -> Option<(bool, i32)> {
         let position_vec = cgmath::vec2(position.0 as f32, position.1 as f32);
 
         self.mouse_pos = position_vec;
@@ -423,18 +763,14 @@ impl SpectrumUI {
         if self.dragging {
             let delta = position_vec - self.last_drag_pos;
 
-            let threshold = self.display_params.dx as f32 / self._init_config.num_bins as f32;
+            let threshold = self.display_params.dx as f32 / self.zoom_config.num_bins as f32;
             if delta.x.abs() >= threshold {
                 let drag_bins = (delta.x / threshold) as i32;
 
                 self.last_drag_pos.x += drag_bins as f32 * threshold;
 
                 //println!("drag_bins: {}", drag_bins);
-                if drag_bins > 0 {
-                    self.move_spectrum(true, drag_bins);
-                } else if drag_bins < 0 {
-                    self.move_spectrum(false, -drag_bins);
-                }
+                return Some((self.drag_left, drag_bins));
             }
         }
         //let l_channel = self.sliding_impl.lock();
@@ -449,10 +785,16 @@ impl SpectrumUI {
         //     self.mouse_pos.x / self.display_params.dx as f32,
         // );
         //drop(l_channel);
+        None
     }
 
     pub fn on_mouse_click(&mut self, pressed: bool, left: bool) {
         //println!("click, pressed:{} left:{}", pressed, left);
+        if pressed || left == self.drag_left {
+            self.dragging = pressed;
+            self.drag_left = left;
+            self.last_drag_pos = self.mouse_pos;
+        }
         if left {
             if pressed {
                 let zoom_config = &mut self.zoom_config;
@@ -462,8 +804,6 @@ impl SpectrumUI {
                 );
                 println!("frequency: {:.2} Hz", freq);
             }
-            self.dragging = pressed;
-            self.last_drag_pos = self.mouse_pos;
 
             if pressed {
                 if self.mouse_pos.y > self.display_params.dy as f32 - 30.0 {
@@ -514,7 +854,7 @@ impl SpectrumUI {
         (pc, pct)
     }
 
-    fn make_divisions_grid(dx: f32, min_f: f32, max_f: f32) -> Vec<(f32, f32, i8)> {
+    pub fn make_divisions_grid(dx: f32, min_f: f32, max_f: f32) -> Vec<(f32, f32, i8)> {
         let mut v = Vec::new();
 
         // let n = 4;
@@ -692,7 +1032,7 @@ impl SpectrumUI {
             let state_str = if app_state == AppState::Playing {
                 ""
             } else {
-                state_string = format!("state: {:?}", app_state);
+                state_string = format!("state: {:?} {}", app_state, get_app_state_detail());
                 &state_string
             };
             fr.draw_string(
@@ -703,17 +1043,49 @@ impl SpectrumUI {
                 .as_str(),
                 2.0,
                 2.0,
-                0xffaaffaa,
+                if app_state == AppState::AudioError {
+                    0xff5050ff
+                } else {
+                    0xffaaffaa
+                },
                 false,
             );
 
-            fr.draw_string(
-                format!("[key: left/right/+/-/P/S]").as_str(),
-                2.0,
-                offset + 2.0,
-                0xffaaffaa,
-                false,
-            );
+            let help = self.keymap.keys(&[Action::ToggleHelp]);
+            if !help.is_empty() {
+                fr.draw_string(
+                    format!("[{}] keys", help).as_str(),
+                    2.0,
+                    offset + 2.0,
+                    0xffaaffaa,
+                    false,
+                );
+            }
+
+            if let Some((text, until)) = &self.preset_shown {
+                if kwasm::now_ms() < *until {
+                    let x = self.display_params.gui_dx as f32 / 2.0 - 4.0 * text.len() as f32;
+                    let y = self.display_params.gui_dy as f32 / 3.0;
+                    fr.draw_string(text, x.max(2.0), y, 0xffffffff, false);
+                }
+            }
+        }
+    }
+
+    /// Every binding in two columns, made from the keymap
+    fn render_gui_help(&self, pct: &mut Vec<PosColTexVertex>) {
+        if !self.show_help {
+            return;
+        }
+        let mut fr = FontRenderer::new(self.font_atlas.clone(), pct);
+        fr.ui_scale = self.gui_scale as f32;
+        let lines = self.keymap.help_lines();
+        let rows = (lines.len() + 1) / 2;
+        let column = self.display_params.gui_dx as f32 / 2.0;
+        for (i, line) in lines.iter().enumerate() {
+            let x = 2.0 + column * (i / rows) as f32;
+            let y = 22.0 + 10.0 * (i % rows) as f32;
+            fr.draw_string(line, x, y, 0xffffffff, false);
         }
     }
 
@@ -725,14 +1097,19 @@ impl SpectrumUI {
         //dft: &mut ChannelSWDFT,
         gain: f64,
     ) {
-        if self.divisions_hz {
+        if self.divisions_hz && !self.show_help {
             let mut fr = FontRenderer::new(self.font_atlas.clone(), pct);
             fr.ui_scale = self.gui_scale as f32;
             let offset = 10.0;
             fr.draw_string(
-                format!(
-                    "[up/down]        resolution: r = {:.2} wavelengths",
-                    self.zoom_config.wave_cycles_resolution
+                self.hud_line(
+                    &[
+                        Action::CycleResolutionPolicy,
+                        Action::ResolutionUp,
+                        Action::ResolutionDown,
+                    ],
+                    "resolution",
+                    self.zoom_config.resolution.describe(&self.zoom_config),
                 )
                 .as_str(),
                 2.0,
@@ -742,9 +1119,10 @@ impl SpectrumUI {
             );
 
             fr.draw_string(
-                format!(
-                    " [M]                   method: {}",
-                    snapshot.current_algo.describe()
+                self.hud_line(
+                    &[Action::CycleMethod],
+                    "method",
+                    snapshot.current_algo.describe().to_string(),
                 )
                 .as_str(),
                 2.0,
@@ -757,14 +1135,16 @@ impl SpectrumUI {
             //     SpectrumBins::DFT(_bins) => {
             if snapshot.current_algo == SpectrumBinsState::DFT {
                 fr.draw_string(
-                    format!(
-                        " [O]                   window: {}",
+                    self.hud_line(
+                        &[Action::CycleWindow],
+                        "window",
                         match snapshot.window_type {
                             WindowType::Rect => "Rect",
                             WindowType::BlackmanNutall => "Blackman-Nuttall",
                             WindowType::ExpBlackman => "Exp * Blackman",
                             WindowType::LogNormal => "LogNormal",
                         }
+                        .to_string(),
                     )
                     .as_str(),
                     2.0,
@@ -774,8 +1154,12 @@ impl SpectrumUI {
                 );
                 if snapshot.window_type != WindowType::Rect {
                     fr.draw_string(
-                        format!(" [B/N] window subdivisions: {}", snapshot.window_kernel_len)
-                            .as_str(),
+                        self.hud_line(
+                            &[Action::FewerSubdivisions, Action::MoreSubdivisions],
+                            "window subdivisions",
+                            snapshot.window_kernel_len.to_string(),
+                        )
+                        .as_str(),
                         2.0,
                         offset + 42.0,
                         0xffaaffaa,
@@ -788,7 +1172,12 @@ impl SpectrumUI {
             // }
 
             fr.draw_string(
-                format!("[L]              log y scale: {}", self.logarithmic_scale,).as_str(),
+                self.hud_line(
+                    &[Action::ToggleLogScale],
+                    "log y scale",
+                    self.logarithmic_scale.to_string(),
+                )
+                .as_str(),
                 2.0,
                 offset + 52.0,
                 0xffaaffaa,
@@ -796,9 +1185,13 @@ impl SpectrumUI {
             );
 
             fr.draw_string(
-                format!(
-                    "[Z/X]            motion blur: {} Hz (every {} samples)",
-                    snapshot.collect_frequency, snapshot.collect_every,
+                self.hud_line(
+                    &[Action::CollectFrequencyDown, Action::CollectFrequencyUp],
+                    "motion blur",
+                    format!(
+                        "{} Hz (every {} samples)",
+                        snapshot.collect_frequency, snapshot.collect_every,
+                    ),
                 )
                 .as_str(),
                 2.0,
@@ -807,14 +1200,196 @@ impl SpectrumUI {
                 false,
             );
 
+            let tc = &self.traces_config;
+            fr.draw_string(
+                self.hud_line(
+                    &[
+                        Action::TogglePeakHold,
+                        Action::ToggleMaxHold,
+                        Action::ToggleMinHold,
+                        Action::CycleAverage,
+                    ],
+                    "traces",
+                    format!(
+                        "peak-hold {}, max {}, min {}, avg {:?}",
+                        tc.peak_hold, tc.max_hold, tc.min_hold, tc.average,
+                    ),
+                )
+                .as_str(),
+                2.0,
+                offset + 72.0,
+                0xffaaffaa,
+                false,
+            );
+
+            fr.draw_string(
+                self.hud_line(
+                    &[Action::ToggleNoiseFloor, Action::ToggleSubtractNoiseFloor],
+                    "noise floor",
+                    format!(
+                        "shown {}, subtracted {}",
+                        self.show_noise_floor, self.subtract_noise_floor,
+                    ),
+                )
+                .as_str(),
+                2.0,
+                offset + 92.0,
+                0xffaaffaa,
+                false,
+            );
+
             fr.draw_string(
-                format!("gain: {:+.2} dB", 20.0 * gain.log10()).as_str(),
+                format!(
+                    "gain: {:+.2} dB, latency: {:.1} ms, sample {}",
+                    20.0 * gain.log10(),
+                    self.latency_ms,
+                    self.drawn_sample_index
+                )
+                .as_str(),
                 2.0,
                 offset + 82.0,
                 0xffaaffaa,
                 false,
             );
 
+            if let Some(generator) = &self.generator {
+                let params = generator.lock().unwrap().params.clone();
+                fr.draw_string(
+                    self.hud_line(
+                        &[
+                            Action::ToggleGenerator,
+                            Action::CycleGeneratorSignal,
+                            Action::GeneratorFrequencyDown,
+                            Action::GeneratorFrequencyUp,
+                            Action::GeneratorLevelDown,
+                            Action::GeneratorLevelUp,
+                        ],
+                        "generator",
+                        format!("{}, {:+.1} dBFS", params.describe(), params.level_db),
+                    )
+                    .as_str(),
+                    2.0,
+                    offset + 112.0,
+                    0xffaaffaa,
+                    false,
+                );
+            }
+
+            if let Some(app) = &self.app {
+                use std::sync::atomic::Ordering;
+                let stats = &app.stats;
+                let overrun = format!(
+                    "{:?}, dropped {} blocks ({} samples), queue {} (max {}), blocked {}, reuse misses {}/{}",
+                    stats.policy(),
+                    stats.dropped_blocks.load(Ordering::Relaxed),
+                    stats.dropped_samples.load(Ordering::Relaxed),
+                    app.main_pcm_tx.len(),
+                    stats.max_queue_depth.load(Ordering::Relaxed),
+                    stats.blocked_sends.load(Ordering::Relaxed),
+                    stats.reuse_misses.load(Ordering::Relaxed),
+                    stats.reuse_overflows.load(Ordering::Relaxed),
+                );
+                fr.draw_string(
+                    self.hud_line(
+                        &[Action::CycleOverrunPolicy],
+                        "audio queue overrun",
+                        overrun,
+                    )
+                    .as_str(),
+                    2.0,
+                    offset + 122.0,
+                    0xffaaffaa,
+                    false,
+                );
+            }
+
+            if let Some(recorder) = &self.recorder {
+                fr.draw_string(
+                    self.hud_line(
+                        &[Action::ToggleRecording],
+                        "recording",
+                        format!(
+                            "{:?} to {}, {} frames",
+                            recorder.config.format,
+                            recorder.path.display(),
+                            recorder.frames()
+                        ),
+                    )
+                    .as_str(),
+                    2.0,
+                    offset + 132.0,
+                    0xffaaffaa,
+                    false,
+                );
+            }
+
+            if let Some(replay) = &self.replay {
+                fr.draw_string(
+                    self.hud_line(
+                        &[
+                            Action::ReplayPause,
+                            Action::ReplayRestart,
+                            Action::ReplayBack,
+                            Action::ReplayForward,
+                            Action::ReplayFaster,
+                            Action::ReplaySlower,
+                        ],
+                        "replay",
+                        replay.describe(),
+                    )
+                    .as_str(),
+                    2.0,
+                    offset + 142.0,
+                    0xffaaffaa,
+                    false,
+                );
+            }
+
+            #[cfg(not(target_arch = "wasm32"))]
+            if let Some(server) = &self.stream_server {
+                fr.draw_string(
+                    self.hud_line(&[], "stream", server.describe()).as_str(),
+                    2.0,
+                    offset + 152.0,
+                    0xffaaffaa,
+                    false,
+                );
+            }
+
+            #[cfg(not(target_arch = "wasm32"))]
+            if let Some(osc) = &self.osc {
+                fr.draw_string(
+                    self.hud_line(&[], "osc", osc.describe()).as_str(),
+                    2.0,
+                    offset + 162.0,
+                    0xffaaffaa,
+                    false,
+                );
+            }
+
+            #[cfg(not(target_arch = "wasm32"))]
+            if let Some(leds) = &self.leds {
+                fr.draw_string(
+                    self.hud_line(&[], "led", leds.describe()).as_str(),
+                    2.0,
+                    offset + 172.0,
+                    0xffaaffaa,
+                    false,
+                );
+            }
+
+            #[cfg(not(target_arch = "wasm32"))]
+            if let Some(input) = &self.input {
+                fr.draw_string(
+                    self.hud_line(&[Action::CycleInputDevice], "input", input.describe())
+                        .as_str(),
+                    2.0,
+                    offset + 182.0,
+                    0xffaaffaa,
+                    false,
+                );
+            }
+
             let div_grid = Self::make_divisions_grid(
                 self.display_params.gui_dx as f32,
                 self.zoom_config.min_f,
@@ -924,7 +1499,12 @@ impl SpectrumUI {
                             Some(bin) => {
                                 let window_len =
                                     bin.bin.length as f32 / self.zoom_config.sample_rate as f32;
-                                let text = format!("window: {}", DisplayMsSecond(window_len));
+                                // a symmetric window delays by half its length
+                                let text = format!(
+                                    "window: {}, group delay: {}",
+                                    DisplayMsSecond(window_len),
+                                    DisplayMsSecond(window_len * 0.5)
+                                );
                                 fr.draw_string(
                                     text.as_str(),
                                     self.mouse_pos.x / (self.gui_scale as f32) - 100.0,
@@ -932,6 +1512,19 @@ impl SpectrumUI {
                                     0xffaaffaa,
                                     false,
                                 );
+                                let text = format!(
+                                    "{:?}: {:.2} Hz wide, {:.1} cycles",
+                                    self.zoom_config.resolution.policy,
+                                    self.zoom_config.sample_rate as f32 / bin.bin.length as f32,
+                                    f * bin.bin.length as f32 / self.zoom_config.sample_rate as f32,
+                                );
+                                fr.draw_string(
+                                    text.as_str(),
+                                    self.mouse_pos.x / (self.gui_scale as f32) - 100.0,
+                                    self.mouse_pos.y / (self.gui_scale as f32) - 0.0,
+                                    0xffaaffaa,
+                                    false,
+                                );
                             }
                         }
                     }
@@ -944,7 +1537,11 @@ impl SpectrumUI {
                             Some(bin) => {
                                 let window_len =
                                     bin.bina.length as f32 / self.zoom_config.sample_rate as f32;
-                                let text1 = format!("nc window: {}", DisplayMsSecond(window_len));
+                                let text1 = format!(
+                                    "nc window: {}, group delay: {}",
+                                    DisplayMsSecond(window_len),
+                                    DisplayMsSecond(window_len * 0.5)
+                                );
                                 let delta_freq =
                                     self.zoom_config.sample_rate as f64 / (bin.bina.length as f64);
                                 let text2 = format!("nc freq: {:.2} Hz", delta_freq);
@@ -962,6 +1559,15 @@ impl SpectrumUI {
                                     0xffaaffaa,
                                     false,
                                 );
+                                let text3 =
+                                    format!("policy: {:?}", self.zoom_config.resolution.policy);
+                                fr.draw_string(
+                                    text3.as_str(),
+                                    self.mouse_pos.x / (self.gui_scale as f32) - 120.0,
+                                    self.mouse_pos.y / (self.gui_scale as f32) + 10.0,
+                                    0xffaaffaa,
+                                    false,
+                                );
                             }
                         }
                     }
@@ -1121,6 +1727,176 @@ impl SpectrumUI {
         }
     }
 
+    fn render_transfer(&self, pc: &mut Vec<PosColVertex>, pct: &mut Vec<PosColTexVertex>) {
+        let transfer = match &self.transfer {
+            Some(transfer) => transfer,
+            None => return,
+        };
+        // a mono device leaves the second channel without input
+        #[cfg(not(target_arch = "wasm32"))]
+        let device_channels = self
+            .input
+            .as_ref()
+            .map(|h| h.channels.load(std::sync::atomic::Ordering::Relaxed) as usize);
+        #[cfg(target_arch = "wasm32")]
+        let device_channels: Option<usize> = None;
+        let needed = transfer.config.reference.max(transfer.config.measurement) + 1;
+        if let Some(channels) = device_channels.filter(|c| *c > 0 && *c < needed) {
+            let mut fr = FontRenderer::new(self.font_atlas.clone(), pct);
+            fr.ui_scale = self.gui_scale as f32;
+            let text = format!(
+                "transfer function needs two input channels, the device has {}",
+                channels
+            );
+            fr.draw_string(text.as_str(), 2.0, 112.0, 0xffaaffaa, false);
+            return;
+        }
+
+        let ybase = self.display_params.dy as f32 - 30.0;
+        let top = 140.0 * self.gui_scale as f32;
+        let height = (ybase - top).max(1.0);
+        let mid = top + height * 0.5;
+        const RANGE_DB: f32 = 40.0;
+
+        let len = transfer.magnitude.len();
+        let dx = self.display_params.dx as f32;
+        let zero_db = [[0.0, mid], [dx, mid]];
+        Self::push_line_ab(pc, zero_db[0], zero_db[1], [[0.0; 2]; 2], 0.5, 0x40ffffff);
+
+        let coherence = transfer
+            .coherence
+            .iter()
+            .map(|c| ybase - *c as f32 * height);
+        self.render_polyline(pc, len, coherence, 0xff00ff00, 0.8);
+
+        let phase = transfer
+            .phase
+            .iter()
+            .map(|p| mid - (*p / std::f64::consts::PI) as f32 * height * 0.45);
+        self.render_polyline(pc, len, phase, 0xffffff00, 0.8);
+
+        let magnitude = transfer.magnitude.iter().map(|m| {
+            let db = (20.0 * (m + 1e-12).log10()) as f32;
+            mid - db.clamp(-RANGE_DB, RANGE_DB) / RANGE_DB * height * 0.5
+        });
+        self.render_polyline(pc, len, magnitude, 0xffffffff, 1.2);
+
+        if self.divisions_hz {
+            let mut fr = FontRenderer::new(self.font_atlas.clone(), pct);
+            fr.ui_scale = self.gui_scale as f32;
+            fr.draw_string(
+                format!(
+                    "[{}] H1 ch{} -> ch{}: |H| white +-{} dB, phase cyan, coherence green",
+                    self.keymap
+                        .keys(&[Action::ToggleTransfer, Action::SwapTransferChannels]),
+                    transfer.config.reference,
+                    transfer.config.measurement,
+                    RANGE_DB,
+                )
+                .as_str(),
+                2.0,
+                112.0,
+                0xffaaffaa,
+                false,
+            );
+        }
+    }
+
+    // signal-above-noise view
+    fn subtract_noise_floor(collected: &mut Collected) {
+        if let Some(floor) = &collected.noise_floor {
+            for (s, f) in collected.spectrum.iter_mut().zip(floor.iter()) {
+                s.value = (s.value - f).max(0.0);
+            }
+            if let Some(peaks) = &mut collected.peaks {
+                for peak in peaks.iter_mut() {
+                    if let Some(f) = floor.get(peak.probe_index as usize) {
+                        peak.value = (peak.value - f).max(0.0);
+                    }
+                }
+            }
+        }
+    }
+
+    fn render_traces(&self, pc: &mut Vec<PosColVertex>, traces: &SpectrumTraces, gain: f64) {
+        let styles = [
+            (TraceKind::Average, 0xffffffff, 1.2),
+            (TraceKind::MinHold, 0xffff9040, 0.8),
+            (TraceKind::MaxHold, 0xff4040ff, 0.8),
+        ];
+        for (kind, color, width) in styles {
+            if self.traces_config.is_enabled(kind) {
+                self.render_trace_line(pc, traces.get(kind), gain, color, width);
+            }
+        }
+        if self.traces_config.peak_hold {
+            self.render_trace_dashes(pc, &traces.peak_hold, gain, 0xff00ffff);
+        }
+    }
+
+    fn trace_y(&self, val: f64, gain: f64) -> f32 {
+        let ybase = self.display_params.dy as f32 - 30.0;
+        let val = self.maybe_log(val * gain).max(0.0);
+        ybase - 0.004 * val as f32 * self.display_params.gui_dy as f32
+    }
+
+    fn render_trace_line(
+        &self,
+        pc: &mut Vec<PosColVertex>,
+        values: &[f64],
+        gain: f64,
+        color: u32,
+        width: f32,
+    ) {
+        let ys = values.iter().map(|val| self.trace_y(*val, gain));
+        self.render_polyline(pc, values.len(), ys, color, width);
+    }
+
+    fn render_polyline<I>(
+        &self,
+        pc: &mut Vec<PosColVertex>,
+        len: usize,
+        ys: I,
+        color: u32,
+        width: f32,
+    ) where
+        I: Iterator<Item = f32>,
+    {
+        let width_factor = (self.display_params.dx as f32) / (len as f32);
+        let mut lastlinepos = [[0.0, 0.0], [0.0, 0.0]];
+        let mut last_y = 0.0;
+        for (i, y) in ys.enumerate() {
+            if i != 0 {
+                let ii = i as f32;
+                let la = [(ii - 1.0) * width_factor, last_y];
+                let lb = [ii * width_factor, y];
+                lastlinepos = Self::push_line_ab(pc, la, lb, lastlinepos, width, color);
+            }
+            last_y = y;
+        }
+    }
+
+    // one short horizontal tick per bin, like the hold markers of hardware analyzers
+    fn render_trace_dashes(
+        &self,
+        pc: &mut Vec<PosColVertex>,
+        values: &[f64],
+        gain: f64,
+        color: u32,
+    ) {
+        let width_factor = (self.display_params.dx as f32) / (values.len() as f32);
+        let w = (width_factor * 0.4).max(0.5);
+        for (i, val) in values.iter().enumerate() {
+            let y = self.trace_y(*val, gain);
+            let x = i as f32 * width_factor;
+            let posa = [x - w, y + 1.0];
+            let posb = [x - w, y - 1.0];
+            let posc = [x + w, y - 1.0];
+            let posd = [x + w, y + 1.0];
+            Self::push_rect_abcd(pc, posa, posb, posc, posd, color);
+        }
+    }
+
     fn maybe_log(&self, val: f64) -> f64 {
         // magic number for some offset
         if self.logarithmic_scale {
@@ -1222,6 +1998,7 @@ impl SpectrumUI {
         pct: &mut Vec<PosColTexVertex>,
     ) {
         self.render_gui_status(pc, pct);
+        self.render_gui_help(pct);
         if false {
             let display = &self.display_params;
             let posa = [0.0, 0.0];
@@ -1267,6 +2044,10 @@ impl SpectrumUI {
             });
         }
 
+        if let Some(replay) = &mut self.replay {
+            replay.tick(kwasm::now_ms());
+        }
+
         //let gl = &self.display.gl;
         for (channel_num, sliding_cell) in self.sliding_impls.iter().enumerate() {
             let mut sliding_main = sliding_cell.borrow_mut();
@@ -1274,12 +2055,56 @@ impl SpectrumUI {
             loop {
                 let msg = sliding_main.spectrum_receiver.try_recv();
                 match msg {
-                    Ok(RendererMsg::NewSpectrum(collected)) => {
+                    Ok(RendererMsg::NewSpectrum(mut collected)) => {
+                        if let Some(recorder) = &mut self.recorder {
+                            if let Err(err) = recorder.record(channel_num, &collected) {
+                                println!("recorder: {}, stopped", err);
+                                self.recorder = None;
+                            }
+                        }
+                        #[cfg(not(target_arch = "wasm32"))]
+                        if let Some(server) = &mut self.stream_server {
+                            server.publish(channel_num, &collected, &self.zoom_config);
+                        }
+                        #[cfg(not(target_arch = "wasm32"))]
+                        if let Some(osc) = &mut self.osc {
+                            if channel_num == 0 {
+                                osc.send_frame(&collected, &self.zoom_config);
+                            }
+                        }
+                        #[cfg(not(target_arch = "wasm32"))]
+                        if let Some(leds) = &mut self.leds {
+                            if channel_num == 0 {
+                                leds.send_frame(&collected, &self.zoom_config);
+                            }
+                        }
+                        if self.subtract_noise_floor {
+                            Self::subtract_noise_floor(&mut collected);
+                        }
                         sliding_main.last_rolling_gain = collected.cur_rolling_gain;
+                        sliding_main.traces.update(&self.traces_config, &collected);
+                        if self.show_transfer {
+                            if let Some(transfer) = &mut self.transfer {
+                                transfer.push(channel_num, &collected);
+                            }
+                        }
+                        if self.show_distortion && channel_num == 0 {
+                            self.distortion = collected.distortion.clone();
+                        }
                         sliding_main.collected_spectrums.push_front(collected);
                     }
                     Ok(RendererMsg::ConfigUpdate(config)) => {
+                        if let Some(recorder) = &mut self.recorder {
+                            if let Err(err) = recorder.reconfigure(&config) {
+                                println!("recorder: {}, stopped", err);
+                                self.recorder = None;
+                            }
+                        }
                         self.zoom_config = config;
+                        sliding_main.traces.reset();
+                        if let Some(transfer) = &mut self.transfer {
+                            transfer.reset();
+                        }
                     }
                     Err(_) => {
                         break;
@@ -1306,6 +2131,15 @@ impl SpectrumUI {
 
             if channel_num == 0 {
                 if let Some(first) = sliding_main.collected_spectrums.front() {
+                    // a replayed frame was captured long ago
+                    if first.capture_ms > 0.0 && self.replay.is_none() {
+                        let latency = kwasm::now_ms() - first.capture_ms;
+                        if self.latency_ms == 0.0 {
+                            self.latency_ms = latency;
+                        }
+                        self.latency_ms += 0.1 * (latency - self.latency_ms);
+                    }
+                    self.drawn_sample_index = first.sample_index;
                     self.render_gui_divisions_grid(&first.snapshot, pc, pct, gain);
                 }
 
@@ -1380,6 +2214,15 @@ impl SpectrumUI {
                 }
                 lastalpha = currentalpha;
             }
+
+            self.render_traces(pc, &sliding_main.traces, gain);
+            if self.show_noise_floor && !self.subtract_noise_floor {
+                if let Some(first) = sliding_main.collected_spectrums.front() {
+                    if let Some(floor) = &first.noise_floor {
+                        self.render_trace_line(pc, floor, gain, 0xff808080, 0.8);
+                    }
+                }
+            }
             /*
             fr.draw_string(
                 &format!("alpha sum: {}", debuga).as_str(),
@@ -1392,5 +2235,74 @@ impl SpectrumUI {
             //     _ => {}
             // }
         }
+
+        if self.show_transfer {
+            self.render_transfer(pc, pct);
+        }
+        if self.show_distortion {
+            self.render_distortion(pct);
+        }
+    }
+
+    fn render_distortion(&self, pct: &mut Vec<PosColTexVertex>) {
+        let keys = self.keymap.keys(&[Action::ToggleDistortion]);
+        let mut lines = vec![format!("[{}] distortion", keys)];
+        match &self.distortion {
+            Some(report) => {
+                let f = &report.fundamental;
+                lines.push(format!(
+                    "F   {:8.2} Hz {:6.1} dBFS",
+                    f.frequency_hz,
+                    f.dbfs()
+                ));
+                for h in &report.harmonics {
+                    lines.push(format!(
+                        "H{:<2} {:8.1} Hz {:6.1} dBc",
+                        h.order,
+                        h.frequency_hz,
+                        h.dbc(f)
+                    ));
+                }
+                lines.push(format!(
+                    "THD   {:.4} % {:6.1} dB",
+                    100.0 * report.thd,
+                    report.thd_db()
+                ));
+                lines.push(format!(
+                    "THD+N {:.4} % {:6.1} dB",
+                    100.0 * report.thd_n,
+                    report.thd_n_db()
+                ));
+                lines.push(format!("SINAD {:.1} dB", report.sinad_db));
+                lines.push(format!("ENOB  {:.2} bits", report.enob));
+                lines.push(format!(
+                    "band {:.0}..{:.0} Hz",
+                    report.band_hz.0, report.band_hz.1
+                ));
+            }
+            None => {
+                let is_dft = self.sliding_impls.first().map_or(false, |c| {
+                    c.borrow()
+                        .collected_spectrums
+                        .front()
+                        .map_or(false, |s| s.snapshot.current_algo == SpectrumBinsState::DFT)
+                });
+                let keys = self.keymap.keys(&[Action::CycleMethod]);
+                lines.push(if is_dft {
+                    "no dominant tone".to_string()
+                } else if keys.is_empty() {
+                    "needs DFT method".to_string()
+                } else {
+                    format!("needs DFT method [{}]", keys)
+                });
+            }
+        }
+
+        let mut fr = FontRenderer::new(self.font_atlas.clone(), pct);
+        fr.ui_scale = self.gui_scale as f32;
+        let x = self.display_params.gui_dx as f32 - 170.0;
+        for (i, line) in lines.iter().enumerate() {
+            fr.draw_string(line.as_str(), x, 22.0 + 10.0 * i as f32, 0xffaaffff, false);
+        }
     }
 }
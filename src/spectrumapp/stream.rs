@@ -0,0 +1,570 @@
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::ops::Range;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crossbeam_channel::Receiver;
+use crossbeam_channel::RecvTimeoutError;
+use crossbeam_channel::Sender;
+use crossbeam_channel::TrySendError;
+use serde_derive::Deserialize;
+use serde_derive::Serialize;
+use tungstenite::Message;
+
+use super::recorder::BinFrequencies;
+use super::recorder::MAX_PEAKS;
+use super::sbswdft::Collected;
+use super::sbswdft::SpectrumConfig;
+
+// Publishes collected frames to local clients, as newline-delimited JSON over
+// TCP or as WebSocket messages. Each client sends a subscription, a JSON line
+// over TCP or a text message over WebSocket, whenever it wants to change it:
+//   {"format": "binary", "every": 4, "min_f": 100, "max_f": 4000, "channels": [0]}
+// Every field is optional. Before its first frame, and whenever the bins
+// change, a client gets the frequencies of the bins it subscribed to.
+//
+// JSON messages:
+//   {"type":"bins","frequencies":[..]}
+//   {"type":"frame","channel":0,"frame":12,"sample_index":..,"capture_ms":..,
+//    "magnitudes":[..],"peaks":[[hz,value],..]}
+//   {"type":"error","message":".."}
+// Binary messages, little endian, prefixed with their u32 length over TCP:
+//   'B', u32 n, f32 frequencies[n]
+//   'F', u16 channel, u64 frame, u64 sample_index, f64 capture_ms, u32 n,
+//        u16 npeaks, f32 magnitudes[n], (f32 hz, f32 value)[npeaks]
+//   'E', utf-8 message
+
+/// frames queued per client, a slow client misses frames beyond this
+const CLIENT_QUEUE: usize = 64;
+
+#[derive(Clone)]
+pub struct StreamConfig {
+    pub bind: String,
+    pub tcp_port: Option<u16>,
+    pub ws_port: Option<u16>,
+}
+
+impl StreamConfig {
+    pub fn new() -> Self {
+        Self {
+            bind: "127.0.0.1".to_string(),
+            tcp_port: None,
+            ws_port: None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamFormat {
+    Json,
+    Binary,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Subscription {
+    pub format: StreamFormat,
+    /// send every n-th frame of a channel
+    pub every: usize,
+    pub min_f: f32,
+    pub max_f: f32,
+    /// all channels if not given
+    pub channels: Option<Vec<usize>>,
+}
+
+impl Subscription {
+    pub fn new() -> Self {
+        Self {
+            format: StreamFormat::Json,
+            every: 1,
+            min_f: 0.0,
+            max_f: f32::INFINITY,
+            channels: None,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let s: Self = serde_json::from_str(text).map_err(|err| err.to_string())?;
+        if s.every == 0 {
+            return Err("every must be at least 1".to_string());
+        }
+        if !(s.min_f <= s.max_f) {
+            return Err("min_f must not be above max_f".to_string());
+        }
+        Ok(s)
+    }
+}
+
+impl Default for Subscription {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// One collected frame, shared by every client
+pub struct StreamFrame {
+    pub channel: usize,
+    pub frame: usize,
+    pub sample_index: u64,
+    pub capture_ms: f64,
+    /// the same Arc until the bins change
+    pub frequencies: Arc<Vec<f32>>,
+    pub magnitudes: Vec<f32>,
+    /// (Hz, value)
+    pub peaks: Vec<(f32, f32)>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum JsonMessage<'a> {
+    Bins {
+        frequencies: &'a [f32],
+    },
+    Frame {
+        channel: usize,
+        frame: usize,
+        sample_index: u64,
+        capture_ms: f64,
+        magnitudes: &'a [f32],
+        peaks: &'a [(f32, f32)],
+    },
+    Error {
+        message: &'a str,
+    },
+}
+
+pub enum Encoded {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// Per-client state, turns frames into the messages its subscription asks for
+pub struct Client {
+    pub subscription: Subscription,
+    /// frequencies the last bins message was sent for
+    sent_frequencies: Option<Arc<Vec<f32>>>,
+    range: Range<usize>,
+}
+
+impl Client {
+    pub fn new() -> Self {
+        Self {
+            subscription: Subscription::new(),
+            sent_frequencies: None,
+            range: 0..0,
+        }
+    }
+
+    /// Applies a subscription message, an error message to send back if it's bad
+    pub fn subscribe(&mut self, text: &str) -> Option<Encoded> {
+        match Subscription::parse(text) {
+            Ok(subscription) => {
+                self.subscription = subscription;
+                self.sent_frequencies = None;
+                None
+            }
+            Err(message) => Some(self.encode_error(&message)),
+        }
+    }
+
+    pub fn encode_error(&self, message: &str) -> Encoded {
+        match self.subscription.format {
+            StreamFormat::Json => {
+                Encoded::Text(serde_json::to_string(&JsonMessage::Error { message }).unwrap())
+            }
+            StreamFormat::Binary => {
+                let mut out = vec![b'E'];
+                out.extend_from_slice(message.as_bytes());
+                Encoded::Binary(out)
+            }
+        }
+    }
+
+    pub fn encode(&mut self, frame: &StreamFrame) -> Vec<Encoded> {
+        let s = self.subscription.clone();
+        let mut out = Vec::new();
+        if frame.frame % s.every != 0 {
+            return out;
+        }
+        if let Some(channels) = &s.channels {
+            if !channels.contains(&frame.channel) {
+                return out;
+            }
+        }
+
+        let sent = match &self.sent_frequencies {
+            Some(f) => Arc::ptr_eq(f, &frame.frequencies),
+            None => false,
+        };
+        if !sent {
+            let f = &frame.frequencies;
+            self.range = f.partition_point(|f| *f < s.min_f)..f.partition_point(|f| *f <= s.max_f);
+            self.sent_frequencies = Some(f.clone());
+            let frequencies = &f[self.range.clone()];
+            out.push(match s.format {
+                StreamFormat::Json => Encoded::Text(
+                    serde_json::to_string(&JsonMessage::Bins { frequencies }).unwrap(),
+                ),
+                StreamFormat::Binary => {
+                    let mut b = vec![b'B'];
+                    b.extend_from_slice(&(frequencies.len() as u32).to_le_bytes());
+                    for f in frequencies {
+                        b.extend_from_slice(&f.to_le_bytes());
+                    }
+                    Encoded::Binary(b)
+                }
+            });
+        }
+
+        let magnitudes = &frame.magnitudes[self.range.clone()];
+        let peaks: Vec<(f32, f32)> = frame
+            .peaks
+            .iter()
+            .filter(|p| p.0 >= s.min_f && p.0 <= s.max_f)
+            .cloned()
+            .collect();
+        out.push(match s.format {
+            StreamFormat::Json => Encoded::Text(
+                serde_json::to_string(&JsonMessage::Frame {
+                    channel: frame.channel,
+                    frame: frame.frame,
+                    sample_index: frame.sample_index,
+                    capture_ms: frame.capture_ms,
+                    magnitudes,
+                    peaks: &peaks,
+                })
+                .unwrap(),
+            ),
+            StreamFormat::Binary => {
+                let mut b = Vec::with_capacity(33 + 4 * magnitudes.len() + 8 * peaks.len());
+                b.push(b'F');
+                b.extend_from_slice(&(frame.channel as u16).to_le_bytes());
+                b.extend_from_slice(&(frame.frame as u64).to_le_bytes());
+                b.extend_from_slice(&frame.sample_index.to_le_bytes());
+                b.extend_from_slice(&frame.capture_ms.to_le_bytes());
+                b.extend_from_slice(&(magnitudes.len() as u32).to_le_bytes());
+                b.extend_from_slice(&(peaks.len() as u16).to_le_bytes());
+                for m in magnitudes {
+                    b.extend_from_slice(&m.to_le_bytes());
+                }
+                for (hz, value) in &peaks {
+                    b.extend_from_slice(&hz.to_le_bytes());
+                    b.extend_from_slice(&value.to_le_bytes());
+                }
+                Encoded::Binary(b)
+            }
+        });
+        out
+    }
+}
+
+pub struct SpectrumServer {
+    pub config: StreamConfig,
+    pub tcp_addr: Option<SocketAddr>,
+    pub ws_addr: Option<SocketAddr>,
+    clients: Arc<Mutex<Vec<Sender<Arc<StreamFrame>>>>>,
+    bins: BinFrequencies,
+    /// frames not queued because a client was behind
+    pub dropped: usize,
+}
+
+impl SpectrumServer {
+    pub fn start(config: StreamConfig) -> std::io::Result<Self> {
+        let clients = Arc::new(Mutex::new(Vec::new()));
+        let mut server = Self {
+            config: config.clone(),
+            tcp_addr: None,
+            ws_addr: None,
+            clients: clients.clone(),
+            bins: BinFrequencies::new(),
+            dropped: 0,
+        };
+        if let Some(port) = config.tcp_port {
+            let listener = TcpListener::bind((config.bind.as_str(), port))?;
+            server.tcp_addr = Some(listener.local_addr()?);
+            Self::accept(listener, clients.clone(), serve_tcp);
+        }
+        if let Some(port) = config.ws_port {
+            let listener = TcpListener::bind((config.bind.as_str(), port))?;
+            server.ws_addr = Some(listener.local_addr()?);
+            Self::accept(listener, clients.clone(), serve_ws);
+        }
+        Ok(server)
+    }
+
+    fn accept(
+        listener: TcpListener,
+        clients: Arc<Mutex<Vec<Sender<Arc<StreamFrame>>>>>,
+        serve: fn(TcpStream, Receiver<Arc<StreamFrame>>) -> Result<(), anyhow::Error>,
+    ) {
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        println!("stream: accept: {}", err);
+                        continue;
+                    }
+                };
+                let (sender, receiver) = crossbeam_channel::bounded(CLIENT_QUEUE);
+                clients.lock().unwrap().push(sender);
+                std::thread::spawn(move || {
+                    let peer = stream.peer_addr();
+                    if let Err(err) = serve(stream, receiver) {
+                        println!("stream: client {:?}: {}", peer, err);
+                    }
+                });
+            }
+        });
+    }
+
+    pub fn clients(&self) -> usize {
+        self.clients.lock().unwrap().len()
+    }
+
+    /// Queues `collected` for every client
+    pub fn publish(&mut self, channel: usize, collected: &Collected, spectrum: &SpectrumConfig) {
+        if self.clients.lock().unwrap().is_empty() {
+            return;
+        }
+        self.bins.update(spectrum);
+        let frequencies = &self.bins.frequencies;
+        let n = frequencies.len();
+        if collected.spectrum.len() < n {
+            return;
+        }
+        let peaks = match &collected.peaks {
+            Some(peaks) => peaks
+                .iter()
+                .filter_map(|p| {
+                    let f = frequencies.get(usize::try_from(p.probe_index).ok()?)?;
+                    Some((*f, p.value as f32))
+                })
+                .take(MAX_PEAKS)
+                .collect(),
+            None => Vec::new(),
+        };
+        self.broadcast(Arc::new(StreamFrame {
+            channel,
+            frame: collected.frame_index,
+            sample_index: collected.sample_index,
+            capture_ms: collected.capture_ms,
+            frequencies: frequencies.clone(),
+            magnitudes: collected.spectrum[..n]
+                .iter()
+                .map(|s| s.value as f32)
+                .collect(),
+            peaks,
+        }));
+    }
+
+    pub fn broadcast(&mut self, frame: Arc<StreamFrame>) {
+        let mut dropped = 0;
+        self.clients
+            .lock()
+            .unwrap()
+            .retain(|client| match client.try_send(frame.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    dropped += 1;
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+        self.dropped += dropped;
+    }
+
+    pub fn describe(&self) -> String {
+        let mut s = String::new();
+        if let Some(addr) = self.tcp_addr {
+            s += &format!("tcp {}, ", addr);
+        }
+        if let Some(addr) = self.ws_addr {
+            s += &format!("ws://{}, ", addr);
+        }
+        format!("{}{} clients, {} dropped", s, self.clients(), self.dropped)
+    }
+}
+
+fn serve_tcp(stream: TcpStream, frames: Receiver<Arc<StreamFrame>>) -> Result<(), anyhow::Error> {
+    // subscription lines are read on their own thread, applied before the next frame
+    let pending: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+    let reader = BufReader::new(stream.try_clone()?);
+    let lines = pending.clone();
+    std::thread::spawn(move || {
+        for line in reader.lines() {
+            match line {
+                Ok(line) => lines.lock().unwrap().push(line),
+                Err(_) => break,
+            }
+        }
+    });
+
+    let mut client = Client::new();
+    let mut out = BufWriter::new(stream);
+    for frame in frames.iter() {
+        let mut messages = Vec::new();
+        for line in pending.lock().unwrap().drain(..) {
+            messages.extend(client.subscribe(&line));
+        }
+        messages.extend(client.encode(&frame));
+        for message in messages {
+            match message {
+                Encoded::Text(text) => {
+                    out.write_all(text.as_bytes())?;
+                    out.write_all(b"\n")?;
+                }
+                Encoded::Binary(bytes) => {
+                    out.write_all(&(bytes.len() as u32).to_le_bytes())?;
+                    out.write_all(&bytes)?;
+                }
+            }
+        }
+        out.flush()?;
+    }
+    Ok(())
+}
+
+fn serve_ws(stream: TcpStream, frames: Receiver<Arc<StreamFrame>>) -> Result<(), anyhow::Error> {
+    let mut ws = tungstenite::accept(stream).map_err(|err| anyhow::anyhow!("{}", err))?;
+    // short reads, so subscriptions are picked up between frames
+    ws.get_ref()
+        .set_read_timeout(Some(Duration::from_millis(1)))?;
+
+    let mut client = Client::new();
+    loop {
+        let mut messages = Vec::new();
+        match frames.recv_timeout(Duration::from_millis(50)) {
+            Ok(frame) => messages.extend(client.encode(&frame)),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+        loop {
+            match ws.read_message() {
+                Ok(Message::Text(text)) => messages.extend(client.subscribe(&text)),
+                Ok(Message::Close(_)) => return Ok(()),
+                Ok(_) => {}
+                Err(tungstenite::Error::Io(err))
+                    if err.kind() == std::io::ErrorKind::WouldBlock
+                        || err.kind() == std::io::ErrorKind::TimedOut =>
+                {
+                    break
+                }
+                Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+                Err(err) => return Err(err.into()),
+            }
+        }
+        for message in messages {
+            ws.write_message(match message {
+                Encoded::Text(text) => Message::Text(text),
+                Encoded::Binary(bytes) => Message::Binary(bytes),
+            })?;
+        }
+    }
+}
+
+#[test]
+fn test_stream_subscriptions() {
+    let frequencies = Arc::new((1..=10).map(|i| 100.0 * i as f32).collect::<Vec<f32>>());
+    let frame = |frame: usize| StreamFrame {
+        channel: 0,
+        frame,
+        sample_index: 80 * frame as u64,
+        capture_ms: 1000.0,
+        frequencies: frequencies.clone(),
+        magnitudes: (0..10).map(|i| i as f32).collect(),
+        peaks: vec![(200.0, 1.0), (500.0, 4.0)],
+    };
+
+    let mut client = Client::new();
+    let json: Vec<String> = client
+        .encode(&frame(0))
+        .into_iter()
+        .map(|m| match m {
+            Encoded::Text(text) => text,
+            Encoded::Binary(_) => panic!("json expected"),
+        })
+        .collect();
+    assert_eq!(json.len(), 2);
+    assert!(json[0].starts_with(r#"{"type":"bins","frequencies":[100.0,200.0"#));
+    let v: serde_json::Value = serde_json::from_str(&json[1]).unwrap();
+    assert_eq!(v["type"], "frame");
+    assert_eq!(v["magnitudes"].as_array().unwrap().len(), 10);
+    assert_eq!(v["peaks"][1][0], 500.0);
+    // bins are sent once
+    assert_eq!(client.encode(&frame(1)).len(), 1);
+
+    assert!(client.subscribe(r#"{"every": 0}"#).is_some());
+    assert!(client.subscribe(r#"{"bogus": 1}"#).is_some());
+    let sub = r#"{"format": "binary", "every": 2, "min_f": 250, "max_f": 700}"#;
+    assert!(client.subscribe(sub).is_none());
+    assert!(client.encode(&frame(3)).is_empty());
+    let binary: Vec<Vec<u8>> = client
+        .encode(&frame(4))
+        .into_iter()
+        .map(|m| match m {
+            Encoded::Binary(bytes) => bytes,
+            Encoded::Text(_) => panic!("binary expected"),
+        })
+        .collect();
+    assert_eq!(binary.len(), 2);
+    // 300 .. 700 Hz
+    assert_eq!(binary[0][0], b'B');
+    assert_eq!(binary[0][1..5], 5_u32.to_le_bytes());
+    assert_eq!(binary[0][5..9], 300.0_f32.to_le_bytes());
+    let f = &binary[1];
+    assert_eq!(f[0], b'F');
+    assert_eq!(f[3..11], 4_u64.to_le_bytes());
+    assert_eq!(f[27..31], 5_u32.to_le_bytes());
+    assert_eq!(f[31..33], 1_u16.to_le_bytes());
+    assert_eq!(f[33..37], 2.0_f32.to_le_bytes());
+    assert_eq!(f.len(), 33 + 4 * 5 + 8);
+    assert_eq!(f[53..57], 500.0_f32.to_le_bytes());
+}
+
+#[test]
+fn test_stream_server() {
+    let mut config = StreamConfig::new();
+    config.tcp_port = Some(0);
+    config.ws_port = Some(0);
+    let mut server = SpectrumServer::start(config).unwrap();
+
+    let tcp = TcpStream::connect(server.tcp_addr.unwrap()).unwrap();
+    let ws_stream = TcpStream::connect(server.ws_addr.unwrap()).unwrap();
+    let url = format!("ws://{}/", server.ws_addr.unwrap());
+    let (mut ws, _) = tungstenite::client(url, ws_stream).unwrap();
+    while server.clients() < 2 {
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    let frequencies = Arc::new(vec![100.0, 200.0]);
+    server.broadcast(Arc::new(StreamFrame {
+        channel: 1,
+        frame: 7,
+        sample_index: 560,
+        capture_ms: 0.0,
+        frequencies,
+        magnitudes: vec![0.5, 0.25],
+        peaks: Vec::new(),
+    }));
+
+    let mut lines = BufReader::new(tcp).lines();
+    assert_eq!(
+        lines.next().unwrap().unwrap(),
+        r#"{"type":"bins","frequencies":[100.0,200.0]}"#
+    );
+    let frame: serde_json::Value = serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap();
+    assert_eq!(frame["frame"], 7);
+    assert_eq!(frame["channel"], 1);
+
+    assert!(matches!(ws.read_message().unwrap(), Message::Text(t) if t.contains("bins")));
+    assert!(matches!(ws.read_message().unwrap(), Message::Text(t) if t.contains("[0.5,0.25]")));
+}
@@ -0,0 +1,244 @@
+use std::collections::VecDeque;
+
+use num_enum::IntoPrimitive;
+use num_enum::TryFromPrimitive;
+
+use super::sbswdft::Collected;
+
+// auxiliary analyzer traces, computed on the UI side
+// from the stream of collected spectrums
+
+#[derive(Copy, Clone, Debug, PartialEq, TryFromPrimitive, IntoPrimitive)]
+#[repr(u8)]
+pub enum AverageMode {
+    Off,
+    Exponential,
+    Linear,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TraceKind {
+    PeakHold,
+    MaxHold,
+    MinHold,
+    Average,
+}
+
+#[derive(Clone)]
+pub struct TracesConfig {
+    pub peak_hold: bool,
+    /// how fast the peak-hold trace falls back, in dB per second
+    pub peak_decay_db_per_s: f64,
+    pub max_hold: bool,
+    pub min_hold: bool,
+    pub average: AverageMode,
+    /// N of the N-frame average (or equivalent N of the exponential one)
+    pub average_frames: usize,
+}
+
+impl TracesConfig {
+    pub fn new() -> Self {
+        Self {
+            peak_hold: false,
+            peak_decay_db_per_s: 20.0,
+            max_hold: false,
+            min_hold: false,
+            average: AverageMode::Off,
+            average_frames: 32,
+        }
+    }
+
+    pub fn is_enabled(&self, kind: TraceKind) -> bool {
+        match kind {
+            TraceKind::PeakHold => self.peak_hold,
+            TraceKind::MaxHold => self.max_hold,
+            TraceKind::MinHold => self.min_hold,
+            TraceKind::Average => self.average != AverageMode::Off,
+        }
+    }
+}
+
+pub struct SpectrumTraces {
+    pub peak_hold: Vec<f64>,
+    pub max_hold: Vec<f64>,
+    pub min_hold: Vec<f64>,
+    pub average: Vec<f64>,
+
+    linear_frames: VecDeque<Vec<f64>>,
+    linear_sum: Vec<f64>,
+}
+
+impl SpectrumTraces {
+    pub fn new() -> Self {
+        Self {
+            peak_hold: Vec::new(),
+            max_hold: Vec::new(),
+            min_hold: Vec::new(),
+            average: Vec::new(),
+            linear_frames: VecDeque::new(),
+            linear_sum: Vec::new(),
+        }
+    }
+
+    pub fn get(&self, kind: TraceKind) -> &Vec<f64> {
+        match kind {
+            TraceKind::PeakHold => &self.peak_hold,
+            TraceKind::MaxHold => &self.max_hold,
+            TraceKind::MinHold => &self.min_hold,
+            TraceKind::Average => &self.average,
+        }
+    }
+
+    pub fn clear(&mut self, kind: TraceKind) {
+        match kind {
+            TraceKind::PeakHold => self.peak_hold.clear(),
+            TraceKind::MaxHold => self.max_hold.clear(),
+            TraceKind::MinHold => self.min_hold.clear(),
+            TraceKind::Average => {
+                self.average.clear();
+                self.linear_frames.clear();
+                self.linear_sum.clear();
+            }
+        }
+    }
+
+    /// Bins moved or changed their frequency, old values mean nothing now
+    pub fn reset(&mut self) {
+        self.clear(TraceKind::PeakHold);
+        self.clear(TraceKind::MaxHold);
+        self.clear(TraceKind::MinHold);
+        self.clear(TraceKind::Average);
+    }
+
+    pub fn update(&mut self, config: &TracesConfig, collected: &Collected) {
+        let len = collected.spectrum.len();
+        let values = collected.spectrum.iter().map(|s| s.value);
+
+        if config.peak_hold {
+            let frame_time = 1.0 / collected.snapshot.collect_frequency.max(1) as f64;
+            let decay = 10f64.powf(-config.peak_decay_db_per_s * frame_time / 20.0);
+            Self::fold(&mut self.peak_hold, len, values.clone(), |held, v| {
+                v.max(held * decay)
+            });
+        }
+        if config.max_hold {
+            Self::fold(&mut self.max_hold, len, values.clone(), |held, v| {
+                v.max(held)
+            });
+        }
+        if config.min_hold {
+            Self::fold(&mut self.min_hold, len, values.clone(), |held, v| {
+                v.min(held)
+            });
+        }
+
+        let frames = config.average_frames.max(1);
+        match config.average {
+            AverageMode::Off => {}
+            AverageMode::Exponential => {
+                let alpha = 2.0 / (frames as f64 + 1.0);
+                Self::fold(&mut self.average, len, values, |avg, v| {
+                    avg + alpha * (v - avg)
+                });
+            }
+            AverageMode::Linear => {
+                if self.linear_sum.len() != len {
+                    self.linear_sum = vec![0.0; len];
+                    self.linear_frames.clear();
+                }
+                let frame: Vec<f64> = values.collect();
+                for (sum, v) in self.linear_sum.iter_mut().zip(frame.iter()) {
+                    *sum += v;
+                }
+                self.linear_frames.push_back(frame);
+                while self.linear_frames.len() > frames {
+                    let old = self.linear_frames.pop_front().unwrap();
+                    for (sum, v) in self.linear_sum.iter_mut().zip(old.iter()) {
+                        *sum -= v;
+                    }
+                }
+                let inv_n = 1.0 / self.linear_frames.len() as f64;
+                self.average.clear();
+                self.average
+                    .extend(self.linear_sum.iter().map(|sum| (sum * inv_n).max(0.0)));
+            }
+        }
+    }
+
+    fn fold<I, F>(trace: &mut Vec<f64>, len: usize, values: I, f: F)
+    where
+        I: Iterator<Item = f64>,
+        F: Fn(f64, f64) -> f64,
+    {
+        if trace.len() != len {
+            // first frame starts the trace
+            trace.clear();
+            trace.extend(values);
+        } else {
+            for (held, v) in trace.iter_mut().zip(values) {
+                *held = f(*held, v);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_holds() {
+    let mut config = TracesConfig::new();
+    config.peak_hold = true;
+    config.max_hold = true;
+    config.min_hold = true;
+    config.peak_decay_db_per_s = 10.0;
+    let mut traces = SpectrumTraces::new();
+
+    // a peak, then 2 s of silence at 50 spectra a second
+    traces.update(&config, &Collected::from_values(&[1.0, 0.5], 50));
+    for _ in 0..100 {
+        traces.update(&config, &Collected::from_values(&[0.0, 0.0], 50));
+    }
+    let db = 20.0 * traces.peak_hold[0].log10();
+    assert!((db + 20.0).abs() < 1e-9, "{}", db);
+
+    // the holds only ever move outwards
+    let mut max = traces.max_hold.clone();
+    let mut min = traces.min_hold.clone();
+    assert_eq!((max[0], min[0]), (1.0, 0.0));
+    for i in 0..50 {
+        let v = ((i * 7) % 11) as f64 * 0.1;
+        traces.update(&config, &Collected::from_values(&[v, 1.0 - v], 50));
+        for b in 0..2 {
+            assert!(traces.max_hold[b] >= max[b] && traces.min_hold[b] <= min[b]);
+        }
+        max = traces.max_hold.clone();
+        min = traces.min_hold.clone();
+    }
+    assert_eq!(max, vec![1.0, 1.0]);
+}
+
+#[test]
+fn test_averages() {
+    let mut config = TracesConfig::new();
+    config.average_frames = 4;
+    let values = [1.0, 5.0, 2.0, 8.0, 3.0, 7.0];
+
+    // the mean of the last N
+    config.average = AverageMode::Linear;
+    let mut traces = SpectrumTraces::new();
+    for (i, v) in values.iter().enumerate() {
+        traces.update(&config, &Collected::from_values(&[*v], 50));
+        let last = &values[(i + 1).saturating_sub(4)..=i];
+        let mean = last.iter().sum::<f64>() / last.len() as f64;
+        assert!((traces.average[0] - mean).abs() < 1e-12);
+    }
+
+    // alpha = 2/(N+1) per spectrum, towards a constant input
+    config.average = AverageMode::Exponential;
+    let mut traces = SpectrumTraces::new();
+    traces.update(&config, &Collected::from_values(&[0.0], 50));
+    traces.update(&config, &Collected::from_values(&[1.0], 50));
+    assert!((traces.average[0] - 0.4).abs() < 1e-12);
+    for _ in 0..100 {
+        traces.update(&config, &Collected::from_values(&[1.0], 50));
+    }
+    assert!((traces.average[0] - 1.0).abs() < 1e-9);
+}
@@ -0,0 +1,247 @@
+use std::collections::VecDeque;
+
+use super::sbswdft::Collected;
+use super::sbswdft::ComplexF64;
+
+// Dual-channel transfer function measurement.
+// Both channels run identical bins, so per-bin complex values of
+// the same frame line up and give a cross-spectrum:
+//   Gxx = E[|X|^2], Gyy = E[|Y|^2], Gxy = E[conj(X) * Y]
+//   H1 = Gxy / Gxx, coherence = |Gxy|^2 / (Gxx * Gyy)
+// where X is the reference channel and Y the measurement channel.
+
+pub const DEFAULT_AVERAGING_S: f64 = 1.0;
+
+pub struct TransferConfig {
+    pub reference: usize,
+    pub measurement: usize,
+    /// time constant of the exponential averaging, seconds
+    pub averaging_s: f64,
+}
+
+pub struct TransferFunction {
+    pub config: TransferConfig,
+
+    pending_reference: VecDeque<(usize, Vec<ComplexF64>)>,
+    pending_measurement: VecDeque<(usize, Vec<ComplexF64>)>,
+
+    gxx: Vec<f64>,
+    gyy: Vec<f64>,
+    gxy: Vec<ComplexF64>,
+    pub averaged_frames: usize,
+
+    /// |H1|, linear
+    pub magnitude: Vec<f64>,
+    /// arg(H1), radians
+    pub phase: Vec<f64>,
+    /// magnitude-squared coherence, 0..1
+    pub coherence: Vec<f64>,
+}
+
+impl TransferFunction {
+    pub fn new(reference: usize, measurement: usize) -> Self {
+        Self {
+            config: TransferConfig {
+                reference,
+                measurement,
+                averaging_s: DEFAULT_AVERAGING_S,
+            },
+            pending_reference: VecDeque::new(),
+            pending_measurement: VecDeque::new(),
+            gxx: Vec::new(),
+            gyy: Vec::new(),
+            gxy: Vec::new(),
+            averaged_frames: 0,
+            magnitude: Vec::new(),
+            phase: Vec::new(),
+            coherence: Vec::new(),
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.pending_reference.clear();
+        self.pending_measurement.clear();
+        self.gxx.clear();
+        self.gyy.clear();
+        self.gxy.clear();
+        self.averaged_frames = 0;
+        self.magnitude.clear();
+        self.phase.clear();
+        self.coherence.clear();
+    }
+
+    pub fn swap_channels(&mut self) {
+        let c = &mut self.config;
+        std::mem::swap(&mut c.reference, &mut c.measurement);
+        self.reset();
+    }
+
+    /// Feeds a frame of one of the channels, frames are paired by `frame_index`
+    pub fn push(&mut self, channel: usize, collected: &Collected) {
+        let complex = match &collected.complex {
+            Some(complex) => complex,
+            None => return,
+        };
+        let frame = (collected.frame_index, complex.clone());
+        if channel == self.config.reference {
+            self.pending_reference.push_back(frame);
+        } else if channel == self.config.measurement {
+            self.pending_measurement.push_back(frame);
+        } else {
+            return;
+        }
+
+        let frame_rate = collected.snapshot.collect_frequency.max(1) as f64;
+        self.pair_frames(frame_rate);
+    }
+
+    fn pair_frames(&mut self, frame_rate: f64) {
+        while let (Some(x), Some(y)) = (
+            self.pending_reference.front(),
+            self.pending_measurement.front(),
+        ) {
+            if x.0 < y.0 {
+                self.pending_reference.pop_front();
+            } else if y.0 < x.0 {
+                self.pending_measurement.pop_front();
+            } else {
+                let (_, x) = self.pending_reference.pop_front().unwrap();
+                let (_, y) = self.pending_measurement.pop_front().unwrap();
+                self.accumulate(&x, &y, frame_rate);
+            }
+        }
+    }
+
+    fn accumulate(&mut self, x: &[ComplexF64], y: &[ComplexF64], frame_rate: f64) {
+        let len = x.len().min(y.len());
+        if self.gxx.len() != len {
+            self.gxx = vec![0.0; len];
+            self.gyy = vec![0.0; len];
+            self.gxy = vec![ComplexF64::default(); len];
+            self.averaged_frames = 0;
+        }
+
+        // plain mean for the first frames, exponential after that
+        let time_frames = (self.config.averaging_s * frame_rate).max(1.0);
+        self.averaged_frames += 1;
+        let alpha = 1.0 / time_frames.min(self.averaged_frames as f64);
+
+        for i in 0..len {
+            let x = x[i];
+            let y = y[i];
+            // conj(x) * y
+            let xy = ComplexF64 {
+                re: x.re * y.re + x.im * y.im,
+                im: x.re * y.im - x.im * y.re,
+            };
+            self.gxx[i] += alpha * (x.magnitude_squared() - self.gxx[i]);
+            self.gyy[i] += alpha * (y.magnitude_squared() - self.gyy[i]);
+            self.gxy[i].re += alpha * (xy.re - self.gxy[i].re);
+            self.gxy[i].im += alpha * (xy.im - self.gxy[i].im);
+        }
+
+        self.magnitude.clear();
+        self.phase.clear();
+        self.coherence.clear();
+        for i in 0..len {
+            let gxx = self.gxx[i];
+            let gyy = self.gyy[i];
+            let gxy = self.gxy[i];
+            let cross = gxy.magnitude_squared();
+            if gxx > 0.0 {
+                self.magnitude.push(cross.sqrt() / gxx);
+            } else {
+                self.magnitude.push(0.0);
+            }
+            self.phase.push(gxy.im.atan2(gxy.re));
+            if gxx * gyy > 0.0 {
+                self.coherence.push((cross / (gxx * gyy)).min(1.0));
+            } else {
+                self.coherence.push(0.0);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_transfer_function() {
+    // Y = H X on 8 bins, random X, and noise on Y when `noise` > 0
+    let h = ComplexF64 {
+        re: 0.5 * 0.7f64.cos(),
+        im: 0.5 * 0.7f64.sin(),
+    };
+    let mut seed = 12345u64;
+    let mut random = move || {
+        seed = seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (seed >> 11) as f64 / (1u64 << 53) as f64 - 0.5
+    };
+    let mut frames = |count: usize, noise: f64| {
+        let mut pairs = Vec::new();
+        for _ in 0..count {
+            let x: Vec<ComplexF64> = (0..8)
+                .map(|_| ComplexF64 {
+                    re: random(),
+                    im: random(),
+                })
+                .collect();
+            let y = x
+                .iter()
+                .map(|x| ComplexF64 {
+                    re: h.re * x.re - h.im * x.im + noise * random(),
+                    im: h.re * x.im + h.im * x.re + noise * random(),
+                })
+                .collect();
+            pairs.push((x, y));
+        }
+        pairs
+    };
+    let feed = |transfer: &mut TransferFunction, index: usize, channel: usize, c: Vec<_>| {
+        let mut collected = Collected::from_values(&[0.0; 8], 50);
+        collected.frame_index = index;
+        collected.complex = Some(c);
+        transfer.push(channel, &collected);
+    };
+
+    for noise in [0.0, 0.5] {
+        let mut transfer = TransferFunction::new(0, 1);
+        transfer.config.averaging_s = 10.0;
+        for (i, (x, y)) in frames(2000, noise).into_iter().enumerate() {
+            feed(&mut transfer, i, 0, x);
+            feed(&mut transfer, i, 1, y);
+        }
+        for i in 0..8 {
+            assert!(
+                (transfer.magnitude[i] - 0.5).abs() < 0.05,
+                "{:?}",
+                transfer.magnitude
+            );
+            assert!(
+                (transfer.phase[i] - 0.7).abs() < 0.1,
+                "{:?}",
+                transfer.phase
+            );
+            let coherence = transfer.coherence[i];
+            if noise == 0.0 {
+                assert!(coherence > 1.0 - 1e-9, "{}", coherence);
+            } else {
+                // as much noise as signal on Y
+                assert!((coherence - 0.5).abs() < 0.15, "{}", coherence);
+            }
+        }
+    }
+
+    // a frame missing on one channel is dropped, not paired with the next
+    let mut transfer = TransferFunction::new(0, 1);
+    let pairs = frames(4, 0.0);
+    for (i, (x, _)) in pairs.iter().enumerate() {
+        feed(&mut transfer, i, 0, x.clone());
+    }
+    for (i, (_, y)) in pairs.iter().enumerate().filter(|(i, _)| *i != 1) {
+        feed(&mut transfer, i, 1, y.clone());
+    }
+    assert_eq!(transfer.averaged_frames, 3);
+    assert!(transfer.coherence.iter().all(|c| *c > 1.0 - 1e-9));
+    assert!((transfer.magnitude[0] - 0.5).abs() < 1e-9);
+}